@@ -5,6 +5,18 @@
 //!
 //! Idea adapted from: https://github.com/CraneStation/wasmtime/blob/master/build.rs
 //! Thanks @sunfishcode
+//!
+//! It also generates `wasi_common.h`, the C header of the library (see `build/header.rs`).
+//! Its directory is passed on to dependents through the `include` metadata of the `links` key.
+
+#[path = "build/header.rs"]
+mod header;
+#[allow(dead_code)]
+#[path = "wasi-common-witx/src/layout.rs"]
+mod layout;
+#[allow(dead_code)]
+#[path = "wasi-common-witx/src/parser.rs"]
+mod parser;
 
 use std::env;
 use std::fs::{read_dir, DirEntry, File};
@@ -18,6 +30,15 @@ fn main() {
         .expect("error generating test source file");
 
     test_directory(&mut out, "misc_testsuite").expect("generating tests");
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=build");
+    println!("cargo:rerun-if-changed=misc_testsuite");
+    match header::generate(&out_dir) {
+        // exposed to the build scripts of dependents as `DEP_WASI_COMMON_INCLUDE`
+        Ok(include_dir) => println!("cargo:include={}", include_dir.display()),
+        Err(e) => panic!("error generating wasi_common.h: {}", e),
+    }
 }
 
 fn test_directory(out: &mut File, testsuite: &str) -> io::Result<()> {
@@ -0,0 +1,584 @@
+//! Generates `wasi_common.h`, the C header declaring the functions exported by the
+//! `#[wasi_common_cbindgen]` wrappers of the hostcalls, along with the WASI types and
+//! constants they use.
+//!
+//! The prototypes are read off the hostcalls in `src/hostcalls`, and rewritten the same way
+//! the attribute rewrites their signatures, and off the functions exported as they are to let C
+//! create a context and guest memory. The WASI types are the `wasm32` ones, generated from the
+//! same `.witx` file as `src/wasm32.rs`.
+
+use crate::layout::{self, FieldType, Layout, Member, StructField, Target};
+use crate::parser::{Document, IntRepr, Type, Typename};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::{FnArg, Item, ItemFn, Pat, ReturnType, TypeParamBound};
+
+const WITX: &str = "witx/preview0/typenames.witx";
+const HOSTCALLS: &str = "src/hostcalls";
+/// Sources of the `#[no_mangle]` functions which C calls to set up the arguments of hostcalls.
+const C_API: &[&str] = &["src/ctx.rs", "src/guest_memory.rs"];
+
+/// Width of a pointer in the memory of a `wasm32` guest.
+const POINTER_SIZE: u64 = 4;
+
+/// Types of the library which may appear in the signature of a hostcall.
+const LIBRARY_TYPES: &str = "\
+/**
+ * The context of a WASI instance, owned by the embedder, which creates it with
+ * `wasi_common_ctx_new`.
+ */
+typedef struct WasiCtx WasiCtx;
+
+/**
+ * Pointers to a `GuestMemory` point to a `&dyn GuestMemory` reference to the memory of
+ * the guest, rather than to the memory itself, such as those returned by
+ * `wasi_common_slice_memory_new`.
+ */
+typedef struct GuestMemory GuestMemory;
+
+/**
+ * The ABIs of a guest, which determine the width of pointers and sizes in its memory, as
+ * passed to `wasi_common_slice_memory_new`.
+ */
+#define GuestAbi_Wasm32 0
+#define GuestAbi_Wasm64 1
+
+/**
+ * An address in the memory of the guest.
+ */
+typedef uint64_t GuestAddr;
+
+/**
+ * A size of a region of the memory of the guest.
+ */
+typedef uint64_t GuestSize;
+
+/**
+ * Returned by `wasi_common_proc_exit`, which does not terminate the host process: the
+ * embedder must not resume the guest afterwards.
+ */
+typedef __wasi_exitcode_t ProcExit;
+";
+
+fn c_int(repr: IntRepr) -> &'static str {
+    match repr {
+        IntRepr::U8 => "uint8_t",
+        IntRepr::U16 => "uint16_t",
+        IntRepr::U32 => "uint32_t",
+        IntRepr::U64 => "uint64_t",
+        IntRepr::S8 => "int8_t",
+        IntRepr::S16 => "int16_t",
+        IntRepr::S32 => "int32_t",
+        IntRepr::S64 => "int64_t",
+    }
+}
+
+/// The macro spelling an integer constant of the given type.
+fn c_int_constant(repr: IntRepr) -> &'static str {
+    match repr {
+        IntRepr::U8 => "UINT8_C",
+        IntRepr::U16 => "UINT16_C",
+        IntRepr::U32 => "UINT32_C",
+        IntRepr::U64 => "UINT64_C",
+        IntRepr::S8 => "INT8_C",
+        IntRepr::S16 => "INT16_C",
+        IntRepr::S32 => "INT32_C",
+        IntRepr::S64 => "INT64_C",
+    }
+}
+
+/// Writes the docs of a declaration as a comment, indented by `indent`.
+fn docs(out: &mut String, indent: &str, docs: &str) {
+    if docs.trim().is_empty() {
+        return;
+    }
+    writeln!(out, "{}/**", indent).unwrap();
+    for line in docs.lines() {
+        let line = line.replace("*/", "* /");
+        if line.is_empty() {
+            writeln!(out, "{} *", indent).unwrap();
+        } else {
+            writeln!(out, "{} * {}", indent, line).unwrap();
+        }
+    }
+    writeln!(out, "{} */", indent).unwrap();
+}
+
+/// Declares `name` of type `ty`, which may be a pointer type.
+fn declare(ty: &str, name: &str) -> String {
+    if ty.ends_with('*') {
+        format!("{}{}", ty, name)
+    } else {
+        format!("{} {}", ty, name)
+    }
+}
+
+struct TypeWriter<'a> {
+    layout: Layout<'a>,
+    out: String,
+    written: Vec<&'a str>,
+}
+
+impl<'a> TypeWriter<'a> {
+    fn field_type(&self, ty: &FieldType) -> String {
+        match ty {
+            FieldType::Int(repr) => c_int(*repr).to_owned(),
+            FieldType::Named(name) | FieldType::UnionBody(name) => layout::type_name(name),
+            // guest pointers are offsets in the memory of the guest
+            FieldType::Pointer { .. } => "uint32_t".to_owned(),
+        }
+    }
+
+    /// Writes a type after the types it refers to, which C requires to be declared first.
+    fn write(&mut self, typename: &'a Typename) -> Result<(), String> {
+        if self.written.contains(&typename.name.as_str()) {
+            return Ok(());
+        }
+        self.written.push(&typename.name);
+        let mut dependencies = Vec::new();
+        collect_dependencies(&typename.ty, &mut dependencies);
+        for dependency in dependencies {
+            let dependency = self.layout.doc.resolve(dependency)?;
+            self.write(dependency)?;
+        }
+        self.write_typename(typename)
+            .map_err(|e| format!("in ${}: {}", typename.name, e))
+    }
+
+    fn write_typename(&mut self, typename: &Typename) -> Result<(), String> {
+        let name = &typename.name;
+        let ty = layout::type_name(name);
+        match &typename.ty {
+            Type::Ref(_) | Type::Int(_) if name == "size" => {
+                docs(&mut self.out, "", &typename.docs);
+                writeln!(self.out, "typedef uint32_t {};\n", ty).unwrap();
+            }
+            Type::Int(_) | Type::Ref(_) | Type::Pointer(_) | Type::ConstPointer(_) => {
+                let alias = self.field_type(&self.layout.field_type(&typename.ty)?);
+                docs(&mut self.out, "", &typename.docs);
+                writeln!(self.out, "typedef {};\n", declare(&alias, &ty)).unwrap();
+            }
+            Type::Handle => {
+                docs(&mut self.out, "", &typename.docs);
+                writeln!(self.out, "typedef uint32_t {};\n", ty).unwrap();
+            }
+            Type::Enum {
+                repr,
+                variants: values,
+            }
+            | Type::Flags {
+                repr,
+                flags: values,
+            } => {
+                let flags = match typename.ty {
+                    Type::Flags { .. } => true,
+                    _ => false,
+                };
+                docs(&mut self.out, "", &typename.docs);
+                writeln!(self.out, "typedef {} {};\n", c_int(*repr), ty).unwrap();
+                for (constant, value, variant) in layout::constants(name, values, flags) {
+                    docs(&mut self.out, "", &variant.docs);
+                    if flags {
+                        writeln!(
+                            self.out,
+                            "#define {} ({}({:#x}))\n",
+                            constant,
+                            c_int_constant(*repr),
+                            value
+                        )
+                        .unwrap();
+                    } else {
+                        writeln!(
+                            self.out,
+                            "#define {} ({}({}))\n",
+                            constant,
+                            c_int_constant(*repr),
+                            value
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+            Type::Record(fields) => {
+                let fields = self.layout.struct_fields(name, fields)?;
+                docs(&mut self.out, "", &typename.docs);
+                self.write_struct(name, &fields);
+            }
+            Type::Union { cases, .. } => {
+                if self.layout.is_embedded_union(name) {
+                    docs(&mut self.out, "", &typename.docs);
+                    self.write_union_body(name, cases)?;
+                } else {
+                    self.write_union_body(name, cases)?;
+                    let fields = self.layout.union_struct_fields(typename)?;
+                    docs(&mut self.out, "", &typename.docs);
+                    self.write_struct(name, &fields);
+                }
+            }
+            Type::List => {}
+        }
+        Ok(())
+    }
+
+    fn write_struct(&mut self, name: &str, fields: &[StructField]) {
+        let ty = layout::type_name(name);
+        writeln!(self.out, "typedef struct {} {{", ty).unwrap();
+        for member in self.layout.members(fields) {
+            match member {
+                Member::Field { field, .. } => {
+                    docs(&mut self.out, "    ", &field.docs);
+                    let field_ty = self.field_type(&field.ty);
+                    // `type` is only renamed because it's a keyword in Rust
+                    let name = if field.name == "type_" {
+                        "type"
+                    } else {
+                        &field.name
+                    };
+                    writeln!(self.out, "    {};", declare(&field_ty, name)).unwrap();
+                }
+                Member::Padding { index, len } => {
+                    writeln!(self.out, "    uint8_t __padding_{}[{}];", index, len).unwrap();
+                }
+            }
+        }
+        writeln!(self.out, "}} {};\n", ty).unwrap();
+        let (size, _) = self.layout.struct_layout(fields);
+        writeln!(self.out, "WASI_COMMON_ASSERT_SIZE({}, {})\n", ty, size).unwrap();
+    }
+
+    fn write_union_body(&mut self, name: &str, cases: &[String]) -> Result<(), String> {
+        let ty = layout::type_name(&self.layout.union_body_name(name));
+        writeln!(self.out, "typedef union {} {{", ty).unwrap();
+        for (field, case) in self.layout.union_cases(name, cases) {
+            writeln!(self.out, "    {} {};", layout::type_name(&case), field).unwrap();
+        }
+        writeln!(self.out, "}} {};\n", ty).unwrap();
+        let (size, _) = self.layout.union_body_layout(name, cases)?;
+        writeln!(self.out, "WASI_COMMON_ASSERT_SIZE({}, {})\n", ty, size).unwrap();
+        Ok(())
+    }
+}
+
+/// Names of the types `ty` refers to.
+fn collect_dependencies<'a>(ty: &'a Type, dependencies: &mut Vec<&'a str>) {
+    match ty {
+        Type::Ref(name) => dependencies.push(name),
+        Type::Pointer(to) | Type::ConstPointer(to) => collect_dependencies(to, dependencies),
+        Type::Record(fields) => {
+            for field in fields {
+                collect_dependencies(&field.ty, dependencies);
+            }
+        }
+        Type::Union { tag, cases } => {
+            dependencies.push(tag);
+            dependencies.extend(cases.iter().map(String::as_str));
+        }
+        Type::Int(_) | Type::Enum { .. } | Type::Flags { .. } | Type::Handle | Type::List => {}
+    }
+}
+
+fn write_types(out: &mut String, doc: &Document) -> Result<(), String> {
+    let mut writer = TypeWriter {
+        layout: Layout {
+            doc,
+            target: Target::Guest {
+                pointer_size: POINTER_SIZE,
+            },
+        },
+        out: String::new(),
+        written: Vec::new(),
+    };
+    for typename in &doc.typenames {
+        writer.write(typename)?;
+    }
+    out.push_str(&writer.out);
+    Ok(())
+}
+
+fn is_trait_object(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::TraitObject(_) => true,
+        _ => false,
+    }
+}
+
+/// The C spelling of a type taken or returned by a hostcall.
+fn c_type(doc: &Document, ty: &syn::Type) -> Result<String, String> {
+    match ty {
+        syn::Type::Path(path) if path.qself.is_none() => {
+            let segments: Vec<String> = path
+                .path
+                .segments
+                .iter()
+                .map(|s| s.ident.to_string())
+                .collect();
+            let name = segments.last().unwrap();
+            if name.starts_with("__wasi_") && name.ends_with("_t") {
+                if segments.len() != 2 || segments[0] != "wasm32" {
+                    return Err(format!("`{}` is not a `wasm32` type", segments.join("::")));
+                }
+                doc.resolve(&name["__wasi_".len()..name.len() - "_t".len()])?;
+                return Ok(name.clone());
+            }
+            let c_name = match name.as_str() {
+                "WasiCtx" | "GuestAbi" | "GuestAddr" | "GuestSize" | "ProcExit" => name.as_str(),
+                "c_char" => "char",
+                "u8" => "uint8_t",
+                "u16" => "uint16_t",
+                "u32" => "uint32_t",
+                "u64" => "uint64_t",
+                "i8" => "int8_t",
+                "i16" => "int16_t",
+                "i32" => "int32_t",
+                "i64" => "int64_t",
+                "usize" => "size_t",
+                "isize" => "ptrdiff_t",
+                other => return Err(format!("`{}` has no C equivalent", other)),
+            };
+            Ok(c_name.to_owned())
+        }
+        syn::Type::Reference(reference) => match &*reference.elem {
+            syn::Type::TraitObject(object) => {
+                let name = object.bounds.iter().filter_map(|bound| match bound {
+                    TypeParamBound::Trait(bound) => bound.path.segments.last(),
+                    TypeParamBound::Lifetime(_) => None,
+                });
+                match name.map(|s| s.value().ident.to_string()).next() {
+                    Some(ref name) if name == "GuestMemory" => Ok("GuestMemory *".to_owned()),
+                    Some(name) => Err(format!("`dyn {}` has no C equivalent", name)),
+                    None => Err("expected a trait".to_owned()),
+                }
+            }
+            elem => Ok(format!("{} *", c_type(doc, elem)?)),
+        },
+        syn::Type::Ptr(ptr) => {
+            let pointee = match &*ptr.elem {
+                // `GuestMemory *` already stands for a pointer to a reference
+                syn::Type::Reference(reference) if is_trait_object(&reference.elem) => {
+                    return c_type(doc, &ptr.elem);
+                }
+                elem => c_type(doc, elem)?,
+            };
+            match ptr.const_token {
+                Some(_) if pointee.ends_with('*') => Ok(format!("{}const *", pointee)),
+                Some(_) => Ok(format!("const {} *", pointee)),
+                None if pointee.ends_with('*') => Ok(format!("{}*", pointee)),
+                None => Ok(format!("{} *", pointee)),
+            }
+        }
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => Ok("void".to_owned()),
+        _ => Err("unsupported type".to_owned()),
+    }
+}
+
+/// The parameters of the wrapper of a hostcall, which takes slices as a pointer and a length.
+fn c_params(doc: &Document, hostcall: &ItemFn) -> Result<Vec<String>, String> {
+    let mut params = Vec::new();
+    for input in &hostcall.decl.inputs {
+        let (name, ty) = match input {
+            FnArg::Captured(arg) => match &arg.pat {
+                // unused parameters keep their name without the leading underscore
+                Pat::Ident(pat) => (
+                    pat.ident.to_string().trim_start_matches('_').to_owned(),
+                    &arg.ty,
+                ),
+                _ => return Err("expected an identifier".to_owned()),
+            },
+            _ => return Err("unsupported parameter".to_owned()),
+        };
+        let slice = match ty {
+            syn::Type::Reference(reference) => match &*reference.elem {
+                syn::Type::Slice(slice) => Some(&*slice.elem),
+                _ => None,
+            },
+            _ => None,
+        };
+        match slice {
+            Some(elem) => {
+                let elem = c_type(doc, elem)?;
+                params.push(declare(&format!("{} *", elem), &name));
+                params.push(declare("size_t", &format!("{}_len", name)));
+            }
+            None => params.push(declare(&c_type(doc, ty)?, &name)),
+        }
+    }
+    Ok(params)
+}
+
+fn write_prototype(
+    out: &mut String,
+    doc: &Document,
+    name: &str,
+    hostcall: &ItemFn,
+) -> Result<(), String> {
+    let result = match &hostcall.decl.output {
+        ReturnType::Default => "void".to_owned(),
+        ReturnType::Type(_, ty) => c_type(doc, ty)?,
+    };
+    let params = c_params(doc, hostcall)?;
+
+    let mut hostcall_docs = String::new();
+    for attr in &hostcall.attrs {
+        if let Ok(syn::Meta::NameValue(meta)) = attr.parse_meta() {
+            if let syn::Lit::Str(line) = &meta.lit {
+                if meta.ident == "doc" {
+                    let line = line.value();
+                    hostcall_docs.push_str(line.trim_start_matches(' '));
+                    hostcall_docs.push('\n');
+                }
+            }
+        }
+    }
+    docs(out, "", &hostcall_docs);
+
+    if params.is_empty() {
+        writeln!(out, "{}(void);\n", declare(&result, name)).unwrap();
+    } else {
+        writeln!(out, "{}(", declare(&result, name)).unwrap();
+        for (i, param) in params.iter().enumerate() {
+            let end = if i + 1 == params.len() { ");" } else { "," };
+            writeln!(out, "    {}{}", param, end).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+    Ok(())
+}
+
+fn parse(path: &Path) -> Result<syn::File, String> {
+    let source =
+        fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path.display(), e))?;
+    syn::parse_file(&source).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Writes the prototypes of the functions C calls to create the arguments of the hostcalls.
+fn write_c_api(out: &mut String, doc: &Document) -> Result<(), String> {
+    for path in C_API {
+        let path = Path::new(path);
+        for item in &parse(path)?.items {
+            let function = match item {
+                Item::Fn(f) if f.attrs.iter().any(|a| a.path.is_ident("no_mangle")) => f,
+                _ => continue,
+            };
+            write_prototype(out, doc, &function.ident.to_string(), function)
+                .map_err(|e| format!("{}: in {}: {}", path.display(), function.ident, e))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_prototypes(out: &mut String, doc: &Document) -> Result<(), String> {
+    let mut files: Vec<_> = fs::read_dir(HOSTCALLS)
+        .map_err(|e| format!("reading {}: {}", HOSTCALLS, e))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("reading {}: {}", HOSTCALLS, e))?;
+    files.sort();
+
+    for path in files {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("rs") {
+            continue;
+        }
+        for item in &parse(&path)?.items {
+            let hostcall = match item {
+                Item::Fn(f)
+                    if f.attrs
+                        .iter()
+                        .any(|a| a.path.is_ident("wasi_common_cbindgen")) =>
+                {
+                    f
+                }
+                _ => continue,
+            };
+            let name = format!("wasi_common_{}", hostcall.ident);
+            write_prototype(out, doc, &name, hostcall)
+                .map_err(|e| format!("{}: in {}: {}", path.display(), hostcall.ident, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `contents` to `path`, unless it already holds them, so that C code including the
+/// header isn't rebuilt needlessly.
+fn write_if_changed(path: &Path, contents: &str) -> Result<(), String> {
+    let unchanged = fs::read_to_string(path)
+        .map(|old| old == contents)
+        .unwrap_or(false);
+    if !unchanged {
+        fs::write(path, contents).map_err(|e| format!("writing {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Writes `wasi_common.h` into `<out_dir>/include`, and returns that directory.
+///
+/// If the `WASI_COMMON_HEADER_DIR` environment variable is set, the header is also copied into
+/// the directory it names, for C builds which don't go through Cargo.
+pub fn generate(out_dir: &Path) -> Result<PathBuf, String> {
+    for input in [WITX, HOSTCALLS].iter().chain(C_API) {
+        println!("cargo:rerun-if-changed={}", input);
+    }
+    println!("cargo:rerun-if-env-changed=WASI_COMMON_HEADER_DIR");
+
+    let source = fs::read_to_string(WITX).map_err(|e| format!("reading {}: {}", WITX, e))?;
+    let doc = Document::parse(&source).map_err(|e| format!("{}: {}", WITX, e))?;
+
+    let mut out = String::new();
+    out.push_str(
+        "\
+/**
+ * Declarations of the functions exported by the wasi-common library, and of the WASI types
+ * they use, as laid out in the memory of a wasm32 guest.
+ *
+ * This file is generated by the build script of wasi-common; do not edit it.
+ */
+
+#ifndef WASI_COMMON_H
+#define WASI_COMMON_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern \"C\" {
+#endif
+
+#if defined(__cplusplus) && __cplusplus >= 201103L
+#define WASI_COMMON_ASSERT_SIZE(type, size) static_assert(sizeof(type) == size, \"size of \" #type);
+#elif defined(__STDC_VERSION__) && __STDC_VERSION__ >= 201112L
+#define WASI_COMMON_ASSERT_SIZE(type, size) _Static_assert(sizeof(type) == size, \"size of \" #type);
+#else
+#define WASI_COMMON_ASSERT_SIZE(type, size)
+#endif
+
+",
+    );
+    write_types(&mut out, &doc)?;
+    out.push_str(LIBRARY_TYPES);
+    out.push('\n');
+    write_c_api(&mut out, &doc)?;
+    write_prototypes(&mut out, &doc)?;
+    out.push_str(
+        "\
+#undef WASI_COMMON_ASSERT_SIZE
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
+",
+    );
+
+    let include_dir = out_dir.join("include");
+    fs::create_dir_all(&include_dir)
+        .map_err(|e| format!("creating {}: {}", include_dir.display(), e))?;
+    write_if_changed(&include_dir.join("wasi_common.h"), &out)?;
+    if let Some(dir) = env::var_os("WASI_COMMON_HEADER_DIR") {
+        let dir = Path::new(&dir);
+        fs::create_dir_all(dir).map_err(|e| format!("creating {}: {}", dir.display(), e))?;
+        write_if_changed(&dir.join("wasi_common.h"), &out)?;
+    }
+    Ok(include_dir)
+}
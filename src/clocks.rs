@@ -0,0 +1,580 @@
+//! Clocks backing the guest's view of time.
+use crate::host;
+use crate::sys::hostcalls_impl;
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+
+/// A source of time for the guest, consulted by `clock_res_get`, `clock_time_get` and the clock
+/// subscriptions of `poll_oneoff`.
+///
+/// Implementations may offset, scale or freeze the host's clocks, or drive time from a
+/// simulation altogether. Unsupported clock ids should be answered with `__WASI_EINVAL`.
+pub trait WasiClocks: fmt::Debug + Send + Sync {
+    fn res_get(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t>;
+
+    fn time_get(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t>;
+
+    /// Reads the clock without the side effects a guest's reading may have, for evaluating
+    /// `poll_oneoff` subscriptions.
+    fn now(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        self.time_get(clock_id)
+    }
+
+    /// Whether time is simulated rather than following the host. A guest waiting on a
+    /// simulated clock doesn't sleep on the host; time is moved forward with `advance` instead.
+    fn is_simulated(&self) -> bool {
+        false
+    }
+
+    /// Lets `delay` nanoseconds pass on a simulated clock.
+    fn advance(&self, _delay: host::__wasi_timestamp_t) {}
+}
+
+/// The host's clocks, used unless the embedder provides other ones.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HostClocks;
+
+impl WasiClocks for HostClocks {
+    fn res_get(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        hostcalls_impl::clock_res_get(clock_id)
+    }
+
+    fn time_get(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        hostcalls_impl::clock_time_get(clock_id)
+    }
+}
+
+/// A simulated clock which only moves when told to, used in deterministic mode.
+///
+/// Every clock id reads the same counter, starting at zero. Each reading by the guest advances
+/// it by a fixed `step`, and sleeps in `poll_oneoff` complete instantly by advancing it by the
+/// requested delay.
+#[derive(Debug)]
+pub struct VirtualClock {
+    now: AtomicU64,
+    step: host::__wasi_timestamp_t,
+}
+
+impl VirtualClock {
+    pub fn new(step: host::__wasi_timestamp_t) -> Self {
+        Self {
+            now: AtomicU64::new(0),
+            step,
+        }
+    }
+
+    fn check(clock_id: host::__wasi_clockid_t) -> Result<(), host::__wasi_errno_t> {
+        match clock_id {
+            host::__WASI_CLOCK_REALTIME
+            | host::__WASI_CLOCK_MONOTONIC
+            | host::__WASI_CLOCK_PROCESS_CPUTIME_ID
+            | host::__WASI_CLOCK_THREAD_CPUTIME_ID => Ok(()),
+            _ => Err(host::__WASI_EINVAL),
+        }
+    }
+}
+
+impl WasiClocks for VirtualClock {
+    fn res_get(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        Self::check(clock_id)?;
+        // a resolution of zero is not allowed, even if the clock never ticks on its own
+        Ok(cmp::max(self.step, 1))
+    }
+
+    fn time_get(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        Self::check(clock_id)?;
+        Ok(self.now.fetch_add(self.step, Ordering::SeqCst))
+    }
+
+    fn now(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        Self::check(clock_id)?;
+        Ok(self.now.load(Ordering::SeqCst))
+    }
+
+    fn is_simulated(&self) -> bool {
+        true
+    }
+
+    fn advance(&self, delay: host::__wasi_timestamp_t) {
+        self.now.fetch_add(delay, Ordering::SeqCst);
+    }
+}
+
+/// Coarsens the timestamps the guest gets to see, to blunt timing side channels.
+///
+/// Timestamps are rounded down to a multiple of the granularity. With jitter, each interval
+/// between two multiples is instead split at a secret pseudo-random point, past which timestamps
+/// are rounded up. This hides exactly when an interval starts, while keeping clocks monotonic.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Quantum {
+    granularity: host::__wasi_timestamp_t,
+    jitter_key: Option<u64>,
+}
+
+impl Quantum {
+    pub(crate) fn new(granularity: host::__wasi_timestamp_t, jitter_key: Option<u64>) -> Self {
+        Self {
+            granularity: cmp::max(granularity, 1),
+            jitter_key,
+        }
+    }
+
+    pub(crate) fn granularity(&self) -> host::__wasi_timestamp_t {
+        self.granularity
+    }
+
+    pub(crate) fn apply(&self, time: host::__wasi_timestamp_t) -> host::__wasi_timestamp_t {
+        let interval = time / self.granularity;
+        let start = interval * self.granularity;
+        match self.jitter_key {
+            Some(key) => {
+                let mut hasher = DefaultHasher::new();
+                (key, interval).hash(&mut hasher);
+                if time - start >= hasher.finish() % self.granularity {
+                    start.saturating_add(self.granularity)
+                } else {
+                    start
+                }
+            }
+            None => start,
+        }
+    }
+
+    /// Rounds a delay up to a whole number of intervals, so that a sleep ends only once the
+    /// coarsened clock can have reached its deadline.
+    pub(crate) fn round_up(&self, delay: host::__wasi_timestamp_t) -> host::__wasi_timestamp_t {
+        match delay % self.granularity {
+            0 => delay,
+            rem => delay.saturating_add(self.granularity - rem),
+        }
+    }
+}
+
+/// Clocks whose readings are coarsened by a `Quantum`.
+#[derive(Debug)]
+pub(crate) struct QuantizedClocks {
+    inner: Box<dyn WasiClocks>,
+    quantum: Quantum,
+}
+
+impl QuantizedClocks {
+    pub(crate) fn new(inner: Box<dyn WasiClocks>, quantum: Quantum) -> Self {
+        Self { inner, quantum }
+    }
+}
+
+impl WasiClocks for QuantizedClocks {
+    fn res_get(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        self.inner
+            .res_get(clock_id)
+            .map(|resolution| cmp::max(resolution, self.quantum.granularity()))
+    }
+
+    fn time_get(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        self.inner
+            .time_get(clock_id)
+            .map(|time| self.quantum.apply(time))
+    }
+
+    fn now(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        self.inner
+            .now(clock_id)
+            .map(|time| self.quantum.apply(time))
+    }
+
+    fn is_simulated(&self) -> bool {
+        self.inner.is_simulated()
+    }
+
+    fn advance(&self, delay: host::__wasi_timestamp_t) {
+        self.inner.advance(delay)
+    }
+}
+
+/// CPU time spent executing a single instance.
+///
+/// The embedder reports each thread entering and leaving the instance, and the time is taken
+/// from the CPU clock of the calling thread on both ends. Entries may nest, e.g. when the guest
+/// calls back into the host which re-enters it; only the outermost pair counts.
+///
+/// Only threads currently inside the instance are tracked: once a thread leaves its outermost
+/// section, its time is folded into the total and the thread is forgotten.
+#[derive(Debug, Default)]
+pub(crate) struct CpuTime(Mutex<CpuTimeState>);
+
+#[derive(Debug, Default)]
+struct CpuTimeState {
+    /// Time accumulated by all threads, not counting sections still in progress.
+    total: host::__wasi_timestamp_t,
+    /// Threads inside the instance.
+    threads: HashMap<ThreadId, ThreadSection>,
+}
+
+/// The outermost section a thread is executing.
+#[derive(Debug)]
+struct ThreadSection {
+    depth: usize,
+    entered_at: host::__wasi_timestamp_t,
+}
+
+impl CpuTime {
+    fn lock(&self) -> MutexGuard<'_, CpuTimeState> {
+        // the state is consistent between any two statements which could panic
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn thread_now() -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        hostcalls_impl::clock_time_get(host::__WASI_CLOCK_THREAD_CPUTIME_ID)
+    }
+
+    pub(crate) fn enter(&self) -> Result<(), host::__wasi_errno_t> {
+        let now = Self::thread_now()?;
+        let mut state = self.lock();
+        state
+            .threads
+            .entry(thread::current().id())
+            .or_insert(ThreadSection {
+                depth: 0,
+                entered_at: now,
+            })
+            .depth += 1;
+        Ok(())
+    }
+
+    pub(crate) fn exit(&self) -> Result<(), host::__wasi_errno_t> {
+        let now = Self::thread_now()?;
+        let mut state = self.lock();
+        let id = thread::current().id();
+        let section = state.threads.get_mut(&id).ok_or(host::__WASI_EINVAL)?;
+        section.depth -= 1;
+        if section.depth == 0 {
+            let elapsed = now.saturating_sub(section.entered_at);
+            state.threads.remove(&id);
+            state.total = state.total.saturating_add(elapsed);
+        }
+        Ok(())
+    }
+
+    /// Time spent in the section the calling thread is executing, if any.
+    fn in_progress(
+        state: &CpuTimeState,
+        now: host::__wasi_timestamp_t,
+    ) -> host::__wasi_timestamp_t {
+        state
+            .threads
+            .get(&thread::current().id())
+            .map_or(0, |section| now.saturating_sub(section.entered_at))
+    }
+
+    /// Time spent in the instance by all threads. Sections in progress on threads other than the
+    /// calling one are only accounted for once they end.
+    pub(crate) fn process_time(&self) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        let now = Self::thread_now()?;
+        let state = self.lock();
+        Ok(state.total.saturating_add(Self::in_progress(&state, now)))
+    }
+
+    /// Time spent in the instance by the calling thread since it entered its outermost section.
+    pub(crate) fn thread_time(&self) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        let now = Self::thread_now()?;
+        Ok(Self::in_progress(&self.lock(), now))
+    }
+}
+
+/// Clocks whose CPU time clocks only measure the time spent executing a single instance.
+#[derive(Debug)]
+pub(crate) struct InstanceCpuClocks {
+    inner: Box<dyn WasiClocks>,
+    cpu_time: Arc<CpuTime>,
+}
+
+impl InstanceCpuClocks {
+    pub(crate) fn new(inner: Box<dyn WasiClocks>, cpu_time: Arc<CpuTime>) -> Self {
+        Self { inner, cpu_time }
+    }
+}
+
+impl WasiClocks for InstanceCpuClocks {
+    fn res_get(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        match clock_id {
+            host::__WASI_CLOCK_PROCESS_CPUTIME_ID | host::__WASI_CLOCK_THREAD_CPUTIME_ID => {
+                hostcalls_impl::clock_res_get(host::__WASI_CLOCK_THREAD_CPUTIME_ID)
+            }
+            _ => self.inner.res_get(clock_id),
+        }
+    }
+
+    fn time_get(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        match clock_id {
+            host::__WASI_CLOCK_PROCESS_CPUTIME_ID => self.cpu_time.process_time(),
+            host::__WASI_CLOCK_THREAD_CPUTIME_ID => self.cpu_time.thread_time(),
+            _ => self.inner.time_get(clock_id),
+        }
+    }
+
+    fn now(
+        &self,
+        clock_id: host::__wasi_clockid_t,
+    ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+        match clock_id {
+            host::__WASI_CLOCK_PROCESS_CPUTIME_ID | host::__WASI_CLOCK_THREAD_CPUTIME_ID => {
+                self.time_get(clock_id)
+            }
+            _ => self.inner.now(clock_id),
+        }
+    }
+
+    fn is_simulated(&self) -> bool {
+        self.inner.is_simulated()
+    }
+
+    fn advance(&self, delay: host::__wasi_timestamp_t) {
+        self.inner.advance(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hostcalls, SliceMemory, WasiCtx, WasiCtxBuilder};
+
+    /// A simulated clock starting at one second, which only knows about the monotonic clock.
+    #[derive(Debug, Default)]
+    struct Simulated(AtomicU64);
+
+    impl WasiClocks for Simulated {
+        fn res_get(
+            &self,
+            clock_id: host::__wasi_clockid_t,
+        ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+            self.time_get(clock_id).map(|_| 7)
+        }
+
+        fn time_get(
+            &self,
+            clock_id: host::__wasi_clockid_t,
+        ) -> Result<host::__wasi_timestamp_t, host::__wasi_errno_t> {
+            match clock_id {
+                host::__WASI_CLOCK_MONOTONIC => Ok(1_000_000_000 + self.0.load(Ordering::SeqCst)),
+                _ => Err(host::__WASI_EINVAL),
+            }
+        }
+
+        fn is_simulated(&self) -> bool {
+            true
+        }
+
+        fn advance(&self, delay: host::__wasi_timestamp_t) {
+            self.0.fetch_add(delay, Ordering::SeqCst);
+        }
+    }
+
+    fn read_u64(mem: &[u8], offset: usize) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&mem[offset..offset + 8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Returns the errno and the timestamp of `clock_time_get`.
+    fn time_get(ctx: &WasiCtx, clock_id: host::__wasi_clockid_t) -> (host::__wasi_errno_t, u64) {
+        let mut mem = vec![0; 8];
+        let errno = hostcalls::clock_time_get(ctx, &SliceMemory::new(&mut mem), clock_id, 0, 0);
+        (errno, read_u64(&mem, 0))
+    }
+
+    #[test]
+    fn custom_clocks() {
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .clocks(Box::new(Simulated::default()))
+            .build()
+            .unwrap();
+        let monotonic = host::__WASI_CLOCK_MONOTONIC;
+        assert_eq!(
+            time_get(&ctx, monotonic),
+            (host::__WASI_ESUCCESS, 1_000_000_000)
+        );
+        assert_eq!(
+            time_get(&ctx, host::__WASI_CLOCK_REALTIME).0,
+            host::__WASI_EINVAL
+        );
+        let mut mem = vec![0; 8];
+        let errno = hostcalls::clock_res_get(&ctx, &SliceMemory::new(&mut mem), monotonic, 0);
+        assert_eq!((errno, read_u64(&mem, 0)), (host::__WASI_ESUCCESS, 7));
+
+        // sleeping on a simulated clock advances it instead of blocking
+        let mut mem = vec![0; 256];
+        mem[8] = host::__WASI_EVENTTYPE_CLOCK;
+        mem[24..28].copy_from_slice(&monotonic.to_le_bytes());
+        mem[32..40].copy_from_slice(&3_600_000_000_000u64.to_le_bytes());
+        let start = std::time::Instant::now();
+        let errno = hostcalls::poll_oneoff(&ctx, &SliceMemory::new(&mut mem), 0, 64, 1, 128);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(time_get(&ctx, monotonic).1, 3_601_000_000_000);
+    }
+
+    #[test]
+    fn host_clocks() {
+        let ctx = WasiCtxBuilder::new().unwrap().build().unwrap();
+        let (errno, realtime) = time_get(&ctx, host::__WASI_CLOCK_REALTIME);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        // some time after 2020
+        assert!(realtime > 1_577_836_800_000_000_000);
+        assert_eq!(time_get(&ctx, 77).0, host::__WASI_EINVAL);
+    }
+
+    #[test]
+    fn quantum() {
+        let plain = Quantum::new(1000, None);
+        assert_eq!(
+            [0, 999, 1000, 2500]
+                .iter()
+                .map(|&t| plain.apply(t))
+                .collect::<Vec<_>>(),
+            [0, 0, 1000, 2000]
+        );
+        assert_eq!(plain.round_up(1), 1000);
+        assert_eq!(plain.round_up(2000), 2000);
+        assert_eq!(plain.round_up(u64::max_value()), u64::max_value());
+        // a zero granularity leaves timestamps untouched
+        assert_eq!(Quantum::new(0, None).apply(1234), 1234);
+
+        let jittered = Quantum::new(1000, Some(42));
+        let mut last = 0;
+        for time in (0..100_000).step_by(7) {
+            let coarse = jittered.apply(time);
+            assert_eq!(coarse % 1000, 0);
+            assert!(coarse >= last && coarse + 1000 > time && coarse <= time + 1000);
+            last = coarse;
+        }
+    }
+
+    #[test]
+    fn quantized_clocks() {
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .clocks(Box::new(Simulated::default()))
+            .clock_granularity(std::time::Duration::from_micros(100))
+            .clock_jitter(true)
+            .build()
+            .unwrap();
+        let monotonic = host::__WASI_CLOCK_MONOTONIC;
+        let mut mem = vec![0; 8];
+        let errno = hostcalls::clock_res_get(&ctx, &SliceMemory::new(&mut mem), monotonic, 0);
+        assert_eq!((errno, read_u64(&mem, 0)), (host::__WASI_ESUCCESS, 100_000));
+
+        let mut last = 0;
+        for _ in 0..50 {
+            let (errno, time) = time_get(&ctx, monotonic);
+            assert_eq!(errno, host::__WASI_ESUCCESS);
+            assert_eq!(time % 100_000, 0);
+            assert!(time >= last);
+            last = time;
+            ctx.clocks.advance(30_000);
+        }
+        assert!(last >= 1_001_400_000);
+    }
+
+    /// Keeps the calling thread busy for at least `time` nanoseconds of CPU time.
+    fn spin(time: host::__wasi_timestamp_t) {
+        let start = CpuTime::thread_now().unwrap();
+        while CpuTime::thread_now().unwrap() - start < time {}
+    }
+
+    #[test]
+    fn nested_cpu_time_sections() {
+        let cpu_time = CpuTime::default();
+        assert_eq!(cpu_time.exit(), Err(host::__WASI_EINVAL));
+        cpu_time.enter().unwrap();
+        spin(1_000_000);
+        cpu_time.enter().unwrap();
+        spin(1_000_000);
+        cpu_time.exit().unwrap();
+        // the inner section doesn't end the outer one
+        assert_eq!(cpu_time.lock().total, 0);
+        assert!(cpu_time.thread_time().unwrap() >= 2_000_000);
+        cpu_time.exit().unwrap();
+
+        let total = cpu_time.lock().total;
+        assert!(total >= 2_000_000);
+        assert!(cpu_time.lock().threads.is_empty());
+        assert_eq!(cpu_time.thread_time(), Ok(0));
+        assert_eq!(cpu_time.process_time(), Ok(total));
+        assert_eq!(cpu_time.exit(), Err(host::__WASI_EINVAL));
+    }
+
+    #[test]
+    fn thread_and_instance_cpu_time() {
+        let ctx = Arc::new(
+            WasiCtxBuilder::new()
+                .unwrap()
+                .instance_cputime(true)
+                .build()
+                .unwrap(),
+        );
+        let other = Arc::clone(&ctx);
+        thread::spawn(move || {
+            other.enter().unwrap();
+            spin(20_000_000);
+            other.exit().unwrap();
+        })
+        .join()
+        .unwrap();
+
+        ctx.enter().unwrap();
+        spin(1_000_000);
+        let thread_time = time_get(&ctx, host::__WASI_CLOCK_THREAD_CPUTIME_ID).1;
+        let process_time = time_get(&ctx, host::__WASI_CLOCK_PROCESS_CPUTIME_ID).1;
+        ctx.exit().unwrap();
+        assert!((1_000_000..20_000_000).contains(&thread_time));
+        assert!(process_time >= thread_time + 20_000_000);
+        let total = ctx.cpu_time().unwrap().unwrap();
+        assert!(total >= std::time::Duration::from_nanos(process_time));
+    }
+}
@@ -1,17 +1,87 @@
-use super::fdentry::FdEntry;
+use super::clocks::{
+    CpuTime, HostClocks, InstanceCpuClocks, QuantizedClocks, Quantum, VirtualClock, WasiClocks,
+};
+use super::fdentry::{Descriptor, FdEntry};
 use super::host;
-use super::sys::{dev_null, errno_from_host};
+use super::memory::enc_errno;
+use super::random::{RngProvider, WasiRng};
+use super::sys::{dev_null, errno_from_host, preopen_dir};
+use super::virtfs::{CaptureBuffer, MemFs, VirtualFile};
+use super::wasm32;
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
 use std::borrow::Borrow;
 use std::collections::HashMap;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::fs::File;
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::os::raw::c_char;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::os::unix::prelude::{FromRawFd, IntoRawFd};
 use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+/// Rights granted to the guest on a preopened directory and everything opened beneath it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreopenRights {
+    /// Files may be read, but nothing may be created, written, renamed or removed.
+    ReadOnly,
+    /// All the rights a directory can have.
+    ReadWrite,
+    /// The given base and inheriting rights, further restricted to what a directory can have.
+    Custom {
+        base: host::__wasi_rights_t,
+        inheriting: host::__wasi_rights_t,
+    },
+}
+
+impl PreopenRights {
+    fn masks(self) -> (host::__wasi_rights_t, host::__wasi_rights_t) {
+        match self {
+            PreopenRights::ReadOnly => (!host::RIGHTS_MUTATING, !host::RIGHTS_MUTATING),
+            PreopenRights::ReadWrite => (host::RIGHTS_ALL, host::RIGHTS_ALL),
+            PreopenRights::Custom { base, inheriting } => (base, inheriting),
+        }
+    }
+}
+
+/// The snapshot of the WASI API a guest was compiled against, i.e. the module its WASI functions
+/// are imported from.
+///
+/// All hostcalls are shared between the snapshots, but the few types whose encoding differs are
+/// laid out according to the snapshot, as defined in `wasm32` and `wasi_snapshot_preview1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WasiSnapshot {
+    /// `wasi_unstable`
+    Unstable,
+    /// `wasi_snapshot_preview1`
+    Preview1,
+}
 
 pub struct WasiCtxBuilder {
     fds: HashMap<host::__wasi_fd_t, FdEntry>,
-    preopens: HashMap<PathBuf, File>,
+    preopens: HashMap<PathBuf, (Descriptor, PreopenRights)>,
+    memfs: Vec<MemFs>,
+    sockets: Vec<(Option<host::__wasi_fd_t>, FdEntry)>,
+    listen_fds: Vec<(FdEntry, String)>,
     args: Vec<CString>,
     env: HashMap<CString, CString>,
+    stdout_capture: Option<CaptureBuffer>,
+    stderr_capture: Option<CaptureBuffer>,
+    deterministic_seed: Option<u64>,
+    clock_step: host::__wasi_timestamp_t,
+    clocks: Option<Box<dyn WasiClocks>>,
+    clock_granularity: host::__wasi_timestamp_t,
+    clock_jitter: bool,
+    instance_cputime: bool,
+    rng: Option<Box<dyn WasiRng>>,
+    snapshot: WasiSnapshot,
 }
 
 impl WasiCtxBuilder {
@@ -20,8 +90,21 @@ impl WasiCtxBuilder {
         let mut builder = Self {
             fds: HashMap::new(),
             preopens: HashMap::new(),
+            memfs: vec![],
+            sockets: vec![],
+            listen_fds: vec![],
             args: vec![],
             env: HashMap::new(),
+            stdout_capture: None,
+            stderr_capture: None,
+            deterministic_seed: None,
+            clock_step: 0,
+            clocks: None,
+            clock_granularity: 0,
+            clock_jitter: false,
+            instance_cputime: false,
+            rng: None,
+            snapshot: WasiSnapshot::Unstable,
         };
 
         builder.fds.insert(0, FdEntry::from(dev_null()?)?);
@@ -52,9 +135,62 @@ impl WasiCtxBuilder {
         self.fds.insert(0, FdEntry::duplicate_stdin()?);
         self.fds.insert(1, FdEntry::duplicate_stdout()?);
         self.fds.insert(2, FdEntry::duplicate_stderr()?);
+        self.stdout_capture = None;
+        self.stderr_capture = None;
+        Ok(self)
+    }
+
+    /// Feeds the guest's stdin from `input`.
+    pub fn stdin(mut self, input: Box<dyn Read + Send>) -> Self {
+        self.fds.insert(0, FdEntry::from_reader(input));
+        self
+    }
+
+    /// Feeds the guest's stdin from an in-memory buffer.
+    pub fn stdin_bytes<B: Into<Vec<u8>>>(self, bytes: B) -> Self {
+        self.stdin(Box::new(io::Cursor::new(bytes.into())))
+    }
+
+    /// Feeds the guest's stdin from the file at `path` on the host.
+    pub fn stdin_path<P: AsRef<Path>>(mut self, path: P) -> Result<Self, host::__wasi_errno_t> {
+        let file = File::open(path)
+            .map_err(|err| err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host))?;
+        self.fds.insert(0, FdEntry::from(file)?);
         Ok(self)
     }
 
+    /// Redirects the guest's stdout to `out`.
+    pub fn stdout(mut self, out: Box<dyn Write + Send>) -> Self {
+        self.fds.insert(1, FdEntry::from_writer(out));
+        self.stdout_capture = None;
+        self
+    }
+
+    /// Redirects the guest's stderr to `err`.
+    pub fn stderr(mut self, err: Box<dyn Write + Send>) -> Self {
+        self.fds.insert(2, FdEntry::from_writer(err));
+        self.stderr_capture = None;
+        self
+    }
+
+    /// Collects the guest's stdout into a buffer, which can be read back with
+    /// `WasiCtx::captured_stdout`.
+    pub fn stdout_capture(self) -> Self {
+        let capture = CaptureBuffer::new();
+        let mut builder = self.stdout(Box::new(capture.clone()));
+        builder.stdout_capture = Some(capture);
+        builder
+    }
+
+    /// Collects the guest's stderr into a buffer, which can be read back with
+    /// `WasiCtx::captured_stderr`.
+    pub fn stderr_capture(self) -> Self {
+        let capture = CaptureBuffer::new();
+        let mut builder = self.stderr(Box::new(capture.clone()));
+        builder.stderr_capture = Some(capture);
+        builder
+    }
+
     pub fn inherit_env(self) -> Result<Self, host::__wasi_errno_t> {
         self.envs(std::env::vars())
     }
@@ -86,32 +222,300 @@ impl WasiCtxBuilder {
         Ok(self)
     }
 
-    pub fn preopened_dir<P: AsRef<Path>>(mut self, dir: File, guest_path: P) -> Self {
-        self.preopens.insert(guest_path.as_ref().to_owned(), dir);
+    pub fn preopened_dir<P: AsRef<Path>>(self, dir: File, guest_path: P) -> Self {
+        self.preopened_dir_with_rights(dir, guest_path, PreopenRights::ReadWrite)
+    }
+
+    /// Preopens a directory, granting the guest only the given `rights` on it.
+    pub fn preopened_dir_with_rights<P: AsRef<Path>>(
+        mut self,
+        dir: File,
+        guest_path: P,
+        rights: PreopenRights,
+    ) -> Self {
+        self.preopens.insert(
+            guest_path.as_ref().to_owned(),
+            (Descriptor::File(dir), rights),
+        );
+        self
+    }
+
+    /// Preopens a directory which isn't backed by the host filesystem, granting the guest only
+    /// the given `rights` on it. All hostcalls operating on it, or on anything opened beneath
+    /// it, are dispatched to `dir`.
+    pub fn preopened_virt<P: AsRef<Path>>(
+        mut self,
+        dir: Box<dyn VirtualFile>,
+        guest_path: P,
+        rights: PreopenRights,
+    ) -> Self {
+        self.preopens.insert(
+            guest_path.as_ref().to_owned(),
+            (Descriptor::VirtualFile(dir), rights),
+        );
+        self
+    }
+
+    /// Preopens the root of an in-memory filesystem, granting the guest only the given `rights`
+    /// on it. The embedder may keep a clone of `memfs` to seed it beforehand and inspect it once
+    /// the guest has finished.
+    ///
+    /// From then on, the timestamps of its files are taken from the clocks of the `WasiCtx`. In
+    /// `deterministic` mode, the files it already holds are also stamped with the start time of
+    /// the virtual clock, so that all its timestamps are reproducible.
+    pub fn preopened_memfs<P: AsRef<Path>>(
+        mut self,
+        guest_path: P,
+        memfs: MemFs,
+        rights: PreopenRights,
+    ) -> Self {
+        let root = memfs.root();
+        self.memfs.push(memfs);
+        self.preopened_virt(root, guest_path, rights)
+    }
+
+    /// Hands a connected TCP stream to the guest at descriptor `fd`, or at the first free
+    /// descriptor after the preopens if `fd` is `None`.
+    #[cfg(unix)]
+    pub fn tcp_stream(
+        self,
+        stream: TcpStream,
+        fd: Option<host::__wasi_fd_t>,
+    ) -> Result<Self, host::__wasi_errno_t> {
+        self.socket(stream, fd)
+    }
+
+    /// Hands a listening TCP socket to the guest at descriptor `fd`, or at the first free
+    /// descriptor after the preopens if `fd` is `None`.
+    #[cfg(unix)]
+    pub fn tcp_listener(
+        self,
+        listener: TcpListener,
+        fd: Option<host::__wasi_fd_t>,
+    ) -> Result<Self, host::__wasi_errno_t> {
+        self.socket(listener, fd)
+    }
+
+    /// Hands a UDP socket to the guest at descriptor `fd`, or at the first free descriptor
+    /// after the preopens if `fd` is `None`.
+    #[cfg(unix)]
+    pub fn udp_socket(
+        self,
+        socket: UdpSocket,
+        fd: Option<host::__wasi_fd_t>,
+    ) -> Result<Self, host::__wasi_errno_t> {
+        self.socket(socket, fd)
+    }
+
+    /// Hands a connected Unix domain stream socket to the guest at descriptor `fd`, or at the
+    /// first free descriptor after the preopens if `fd` is `None`.
+    #[cfg(unix)]
+    pub fn unix_stream(
+        self,
+        stream: UnixStream,
+        fd: Option<host::__wasi_fd_t>,
+    ) -> Result<Self, host::__wasi_errno_t> {
+        self.socket(stream, fd)
+    }
+
+    #[cfg(unix)]
+    fn socket<S: IntoRawFd>(
+        mut self,
+        socket: S,
+        fd: Option<host::__wasi_fd_t>,
+    ) -> Result<Self, host::__wasi_errno_t> {
+        // stdio can't be replaced with a socket
+        if let Some(0..=2) = fd {
+            return Err(host::__WASI_EBADF);
+        }
+        // the entry takes ownership of the socket, and closes it when dropped
+        let fe = FdEntry::from(unsafe { File::from_raw_fd(socket.into_raw_fd()) })?;
+        fe.restrict_rights(host::RIGHTS_SOCKET_BASE, host::RIGHTS_SOCKET_INHERITING);
+        self.sockets.push((fd, fe));
+        Ok(self)
+    }
+
+    /// Hands the sockets passed to this process through systemd-style socket activation
+    /// (`LISTEN_FDS`, `LISTEN_PID` and `LISTEN_FDNAMES`) over to the guest.
+    ///
+    /// The sockets are given consecutive descriptors after the preopens. The guest finds them
+    /// through its environment: `LISTEN_FDS_START` holds the first descriptor, while `LISTEN_FDS`
+    /// and `LISTEN_FDNAMES` hold their count and names as usual. The host's `LISTEN_*`
+    /// variables are removed, so the sockets can only be adopted once.
+    #[cfg(unix)]
+    pub fn inherit_listen_fds(mut self) -> Result<Self, host::__wasi_errno_t> {
+        for (file, name) in crate::sys::listen_fds()? {
+            self.listen_fds.push((FdEntry::from(file)?, name));
+        }
+        Ok(self)
+    }
+
+    /// Makes the guest's execution reproducible, as far as its environment is concerned.
+    ///
+    /// - All clocks are replaced with a virtual clock starting at zero, which only advances by
+    ///   the step set with `clock_step` on every reading, and by the full delay whenever the
+    ///   guest sleeps in `poll_oneoff`; such sleeps complete instantly. Clocks set with `clocks`
+    ///   take precedence.
+    ///
+    /// - `random_get` draws from a pseudo-random generator seeded with `seed`, unless a generator
+    ///   is set with `rng`.
+    ///
+    /// - `fd_readdir` lists directory entries sorted by name. This isn't supported on Windows
+    ///   yet, where reading a host directory fails with `__WASI_ENOTSUP` instead.
+    pub fn deterministic(mut self, seed: u64) -> Self {
+        self.deterministic_seed = Some(seed);
+        self
+    }
+
+    /// Sets how much the virtual clock of a `deterministic` context advances every time the
+    /// guest reads it. Defaults to zero, i.e. time only passes when the guest sleeps.
+    pub fn clock_step(mut self, step: Duration) -> Self {
+        self.clock_step = duration_to_timestamp(step);
         self
     }
 
+    /// Sets the clocks the guest reads and sleeps on. Defaults to the host's clocks.
+    pub fn clocks(mut self, clocks: Box<dyn WasiClocks>) -> Self {
+        self.clocks = Some(clocks);
+        self
+    }
+
+    /// Coarsens every timestamp the guest sees to a multiple of `granularity`, to make
+    /// high-resolution timing attacks harder.
+    ///
+    /// This applies to all clocks, whose reported resolution becomes at least `granularity`, to
+    /// the timestamps in file attributes, and to the wake-ups of `poll_oneoff`, which happen on
+    /// granularity boundaries. Disabled by default.
+    pub fn clock_granularity(mut self, granularity: Duration) -> Self {
+        self.clock_granularity = duration_to_timestamp(granularity);
+        self
+    }
+
+    /// Randomizes the point within each granularity interval at which coarsened timestamps
+    /// move on to the next one, so the guest can't learn the exact time by waiting for a
+    /// timestamp to change. Only has an effect along with `clock_granularity`.
+    pub fn clock_jitter(mut self, jitter: bool) -> Self {
+        self.clock_jitter = jitter;
+        self
+    }
+
+    /// Makes the CPU time clocks measure only the time spent executing this instance, rather than
+    /// the time of the whole host process or thread.
+    ///
+    /// The embedder has to report every time a thread starts and stops executing the instance
+    /// with `WasiCtx::enter` and `WasiCtx::exit`; the clocks don't advance outside of these.
+    /// The thread CPU time clock only counts the time since the calling thread last entered the
+    /// instance.
+    pub fn instance_cputime(mut self, enable: bool) -> Self {
+        self.instance_cputime = enable;
+        self
+    }
+
+    /// Sets the generator `random_get` draws from. Defaults to the operating system's CSPRNG.
+    pub fn rng(mut self, rng: Box<dyn WasiRng>) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+
+    /// Sets the snapshot of the WASI API the guest was compiled against. Defaults to
+    /// `WasiSnapshot::Unstable`.
+    pub fn snapshot(mut self, snapshot: WasiSnapshot) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// Builds the context.
+    ///
+    /// Preopens are numbered from 3 onwards, whatever the descriptors chosen for sockets. Fails
+    /// with `__WASI_EEXIST` if a socket was handed over at a descriptor which is already taken by
+    /// a preopen or another socket; the stdio descriptors are rejected right away by the socket
+    /// methods.
     pub fn build(mut self) -> Result<WasiCtx, host::__wasi_errno_t> {
         // startup code starts looking at fd 3 for preopens
         let mut preopen_fd = 3;
-        for (guest_path, dir) in self.preopens {
-            if !dir
-                .metadata()
-                .map_err(|err| err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host))?
-                .is_dir()
-            {
-                return Err(host::__WASI_EBADF);
-            }
+        for (guest_path, (dir, rights)) in self.preopens {
+            let mut fe = match dir {
+                Descriptor::File(dir) => {
+                    if !dir
+                        .metadata()
+                        .map_err(|err| {
+                            err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host)
+                        })?
+                        .is_dir()
+                    {
+                        return Err(host::__WASI_EBADF);
+                    }
+                    FdEntry::from(dir)?
+                }
+                Descriptor::VirtualFile(dir) => {
+                    if dir.file_type() != host::__WASI_FILETYPE_DIRECTORY {
+                        return Err(host::__WASI_EBADF);
+                    }
+                    FdEntry::from_virtual(dir)
+                }
+                _ => return Err(host::__WASI_EBADF),
+            };
 
             while self.fds.contains_key(&preopen_fd) {
                 preopen_fd = preopen_fd.checked_add(1).ok_or(host::__WASI_ENFILE)?;
             }
-            let mut fe = FdEntry::from(dir)?;
+            let (base_mask, inheriting_mask) = rights.masks();
+            fe.restrict_rights(base_mask, inheriting_mask);
             fe.preopen_path = Some(guest_path);
             self.fds.insert(preopen_fd, fe);
             preopen_fd += 1;
         }
 
+        let (placed_sockets, sockets): (Vec<_>, Vec<_>) =
+            self.sockets.into_iter().partition(|(fd, _)| fd.is_some());
+        for (fd, fe) in placed_sockets {
+            let fd = fd.unwrap();
+            if self.fds.contains_key(&fd) {
+                return Err(host::__WASI_EEXIST);
+            }
+            self.fds.insert(fd, fe);
+        }
+
+        // the remaining sockets go after the preopens, as startup code stops scanning for
+        // preopens at the first descriptor which isn't one
+        let mut socket_fd = preopen_fd;
+        if !self.listen_fds.is_empty() {
+            let count = self.listen_fds.len() as host::__wasi_fd_t;
+            let fds = &self.fds;
+            while (socket_fd..socket_fd.checked_add(count).ok_or(host::__WASI_ENFILE)?)
+                .any(|fd| fds.contains_key(&fd))
+            {
+                socket_fd = socket_fd.checked_add(1).ok_or(host::__WASI_ENFILE)?;
+            }
+            let names: Vec<_> = self
+                .listen_fds
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect();
+            let listen_env = [
+                ("LISTEN_FDS_START", socket_fd.to_string()),
+                ("LISTEN_FDS", count.to_string()),
+                ("LISTEN_FDNAMES", names.join(":")),
+            ];
+            for (k, v) in listen_env.iter() {
+                self.env.insert(
+                    CString::new(*k).unwrap(),
+                    CString::new(v.as_str()).map_err(|_| host::__WASI_EILSEQ)?,
+                );
+            }
+            self.env.remove(&CString::new("LISTEN_PID").unwrap());
+            for (fe, _) in self.listen_fds {
+                self.fds.insert(socket_fd, fe);
+                socket_fd += 1;
+            }
+        }
+        for (_, fe) in sockets {
+            while self.fds.contains_key(&socket_fd) {
+                socket_fd = socket_fd.checked_add(1).ok_or(host::__WASI_ENFILE)?;
+            }
+            self.fds.insert(socket_fd, fe);
+        }
+
         let env = self
             .env
             .into_iter()
@@ -124,19 +528,85 @@ impl WasiCtxBuilder {
             })
             .collect();
 
+        let seed = self.deterministic_seed;
+        let clock_step = self.clock_step;
+        let clocks = self.clocks.unwrap_or_else(|| match seed {
+            Some(_) => Box::new(VirtualClock::new(clock_step)),
+            None => Box::new(HostClocks),
+        });
+        let cpu_time = if self.instance_cputime {
+            Some(Arc::new(CpuTime::default()))
+        } else {
+            None
+        };
+        let clocks: Box<dyn WasiClocks> = match &cpu_time {
+            Some(cpu_time) => Box::new(InstanceCpuClocks::new(clocks, Arc::clone(cpu_time))),
+            None => clocks,
+        };
+        let mut rng: Box<dyn WasiRng> = match (self.rng, seed) {
+            (Some(rng), _) => rng,
+            (None, Some(seed)) => Box::new(StdRng::seed_from_u64(seed)),
+            (None, None) => Box::new(OsRng::new().map_err(|_| host::__WASI_EIO)?),
+        };
+        let clock_quantum = if self.clock_granularity > 0 {
+            let jitter_key = if self.clock_jitter {
+                let mut key = [0; 8];
+                rng.try_fill_bytes(&mut key).map_err(|_| host::__WASI_EIO)?;
+                Some(u64::from_le_bytes(key))
+            } else {
+                None
+            };
+            Some(Quantum::new(self.clock_granularity, jitter_key))
+        } else {
+            None
+        };
+        let clocks: Arc<dyn WasiClocks> = match clock_quantum {
+            Some(quantum) => Arc::new(QuantizedClocks::new(clocks, quantum)),
+            None => Arc::from(clocks),
+        };
+        for memfs in &self.memfs {
+            memfs.use_clocks(Arc::clone(&clocks));
+        }
         Ok(WasiCtx {
-            fds: self.fds,
+            fds: RwLock::new(
+                self.fds
+                    .into_iter()
+                    .map(|(fd, fe)| (fd, Arc::new(fe)))
+                    .collect(),
+            ),
             args: self.args,
             env,
+            stdout_capture: self.stdout_capture,
+            stderr_capture: self.stderr_capture,
+            exit_status: Mutex::new(None),
+            clocks,
+            clock_quantum,
+            cpu_time,
+            rng: RngProvider::new(rng),
+            sorted_readdir: seed.is_some(),
+            snapshot: self.snapshot,
         })
     }
 }
 
+/// The state of a WASI instance.
+///
+/// A `WasiCtx` can be shared between threads, so that guests using wasm threads can call into
+/// WASI concurrently; all hostcalls only need a shared reference to it.
 #[derive(Debug)]
 pub struct WasiCtx {
-    pub fds: HashMap<host::__wasi_fd_t, FdEntry>,
+    pub(crate) fds: RwLock<HashMap<host::__wasi_fd_t, Arc<FdEntry>>>,
     pub args: Vec<CString>,
     pub env: Vec<CString>,
+    stdout_capture: Option<CaptureBuffer>,
+    stderr_capture: Option<CaptureBuffer>,
+    pub(crate) exit_status: Mutex<Option<host::__wasi_exitcode_t>>,
+    pub(crate) clocks: Arc<dyn WasiClocks>,
+    pub(crate) clock_quantum: Option<Quantum>,
+    cpu_time: Option<Arc<CpuTime>>,
+    pub(crate) rng: RngProvider,
+    pub(crate) sorted_readdir: bool,
+    pub(crate) snapshot: WasiSnapshot,
 }
 
 impl WasiCtx {
@@ -155,30 +625,73 @@ impl WasiCtx {
             .and_then(|ctx| ctx.build())
     }
 
-    pub fn get_fd_entry(
-        &self,
-        fd: host::__wasi_fd_t,
-        rights_base: host::__wasi_rights_t,
-        rights_inheriting: host::__wasi_rights_t,
-    ) -> Result<&FdEntry, host::__wasi_errno_t> {
-        if let Some(fe) = self.fds.get(&fd) {
-            Self::validate_rights(fe, rights_base, rights_inheriting).and(Ok(fe))
-        } else {
-            Err(host::__WASI_EBADF)
+    /// Returns everything the guest has written to stdout so far, or `None` if stdout wasn't
+    /// captured with `WasiCtxBuilder::stdout_capture`.
+    pub fn captured_stdout(&self) -> Option<Vec<u8>> {
+        self.stdout_capture.as_ref().map(CaptureBuffer::contents)
+    }
+
+    /// Returns everything the guest has written to stderr so far, or `None` if stderr wasn't
+    /// captured with `WasiCtxBuilder::stderr_capture`.
+    pub fn captured_stderr(&self) -> Option<Vec<u8>> {
+        self.stderr_capture.as_ref().map(CaptureBuffer::contents)
+    }
+
+    /// Returns the exit code the guest passed to `proc_exit`, or `None` if it hasn't called it.
+    pub fn exit_status(&self) -> Option<host::__wasi_exitcode_t> {
+        *self.exit_status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Signals that the calling thread starts executing the guest, for the CPU time clocks
+    /// enabled with `WasiCtxBuilder::instance_cputime`. Does nothing otherwise.
+    pub fn enter(&self) -> Result<(), host::__wasi_errno_t> {
+        match &self.cpu_time {
+            Some(cpu_time) => cpu_time.enter(),
+            None => Ok(()),
         }
     }
 
-    pub fn get_fd_entry_mut(
-        &mut self,
+    /// Signals that the calling thread stops executing the guest, after a matching `enter`.
+    ///
+    /// Returns `__WASI_EINVAL` if the thread hasn't entered the guest.
+    pub fn exit(&self) -> Result<(), host::__wasi_errno_t> {
+        match &self.cpu_time {
+            Some(cpu_time) => cpu_time.exit(),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the CPU time spent executing the guest so far, if measured with
+    /// `WasiCtxBuilder::instance_cputime`.
+    pub fn cpu_time(&self) -> Result<Option<Duration>, host::__wasi_errno_t> {
+        match &self.cpu_time {
+            Some(cpu_time) => cpu_time
+                .process_time()
+                .map(|time| Some(Duration::from_nanos(time))),
+            None => Ok(None),
+        }
+    }
+
+    fn fds(&self) -> RwLockReadGuard<'_, HashMap<host::__wasi_fd_t, Arc<FdEntry>>> {
+        self.fds.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn fds_mut(&self) -> RwLockWriteGuard<'_, HashMap<host::__wasi_fd_t, Arc<FdEntry>>> {
+        self.fds.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Looks up the entry of `fd`, checking that it has the given rights.
+    ///
+    /// The entry stays usable even if another thread closes or renumbers `fd` in the meantime;
+    /// the underlying file is only closed once the last reference to the entry is gone.
+    pub fn get_fd_entry(
+        &self,
         fd: host::__wasi_fd_t,
         rights_base: host::__wasi_rights_t,
         rights_inheriting: host::__wasi_rights_t,
-    ) -> Result<&mut FdEntry, host::__wasi_errno_t> {
-        if let Some(fe) = self.fds.get_mut(&fd) {
-            Self::validate_rights(fe, rights_base, rights_inheriting).and(Ok(fe))
-        } else {
-            Err(host::__WASI_EBADF)
-        }
+    ) -> Result<Arc<FdEntry>, host::__wasi_errno_t> {
+        let fe = self.fds().get(&fd).cloned().ok_or(host::__WASI_EBADF)?;
+        Self::validate_rights(&fe, rights_base, rights_inheriting).and(Ok(fe))
     }
 
     fn validate_rights(
@@ -186,27 +699,250 @@ impl WasiCtx {
         rights_base: host::__wasi_rights_t,
         rights_inheriting: host::__wasi_rights_t,
     ) -> Result<(), host::__wasi_errno_t> {
-        if !fe.rights_base & rights_base != 0 || !fe.rights_inheriting & rights_inheriting != 0 {
+        if !fe.rights_base() & rights_base != 0 || !fe.rights_inheriting() & rights_inheriting != 0
+        {
             Err(host::__WASI_ENOTCAPABLE)
         } else {
             Ok(())
         }
     }
 
-    pub fn insert_fd_entry(
-        &mut self,
-        fe: FdEntry,
-    ) -> Result<host::__wasi_fd_t, host::__wasi_errno_t> {
+    pub fn insert_fd_entry(&self, fe: FdEntry) -> Result<host::__wasi_fd_t, host::__wasi_errno_t> {
+        let mut fds = self.fds_mut();
         // never insert where stdio handles usually are
         let mut fd = 3;
-        while self.fds.contains_key(&fd) {
+        while fds.contains_key(&fd) {
             if let Some(next_fd) = fd.checked_add(1) {
                 fd = next_fd;
             } else {
                 return Err(host::__WASI_EMFILE);
             }
         }
-        self.fds.insert(fd, fe);
+        fds.insert(fd, Arc::new(fe));
         Ok(fd)
     }
+
+    /// Removes `fd` from the table. Preopened directories can't be removed.
+    pub fn remove_fd_entry(
+        &self,
+        fd: host::__wasi_fd_t,
+    ) -> Result<Arc<FdEntry>, host::__wasi_errno_t> {
+        let mut fds = self.fds_mut();
+        match fds.get(&fd) {
+            Some(fe) if fe.preopen_path.is_some() => Err(host::__WASI_ENOTSUP),
+            Some(_) => fds.remove(&fd).ok_or(host::__WASI_EBADF),
+            None => Err(host::__WASI_EBADF),
+        }
+    }
+
+    /// Moves the entry of `from` to `to`, replacing the entry previously there. Neither may be
+    /// a preopened directory.
+    pub fn renumber_fd_entry(
+        &self,
+        from: host::__wasi_fd_t,
+        to: host::__wasi_fd_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        let mut fds = self.fds_mut();
+        let fe_from = fds.get(&from).ok_or(host::__WASI_EBADF)?;
+        let fe_to = fds.get(&to).ok_or(host::__WASI_EBADF)?;
+
+        // Don't allow renumbering over a pre-opened resource.
+        // TODO: Eventually, we do want to permit this, once libpreopen in
+        // userspace is capable of removing entries from its tables as well.
+        if fe_from.preopen_path.is_some() || fe_to.preopen_path.is_some() {
+            return Err(host::__WASI_ENOTSUP);
+        }
+
+        // the entry is moved within the table under a single lock, so no other thread can
+        // observe `from` and `to` referring to the same file, or `to` being closed
+        let fe = fds.remove(&from).ok_or(host::__WASI_EBADF)?;
+        fds.insert(to, fe);
+        Ok(())
+    }
+}
+
+fn duration_to_timestamp(duration: Duration) -> host::__wasi_timestamp_t {
+    duration
+        .as_secs()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(u64::from(duration.subsec_nanos()))
+}
+
+/// Creates a context like `WasiCtx::new`, for embedders calling the hostcalls from C.
+///
+/// The guest gets the `argc` arguments `argv`, the standard streams and environment of the host
+/// process, and the `npreopens` host directories `preopen_host_paths` preopened at the
+/// corresponding `preopen_guest_paths`. On success, the context is stored in `*ctx_out`, and
+/// must be freed with `wasi_common_ctx_free`.
+///
+/// Returns `__WASI_EILSEQ` if one of the strings isn't valid UTF-8.
+///
+/// # Safety
+///
+/// Each array must hold as many pointers to NUL-terminated strings as given, unless it is empty,
+/// in which case it may be null. `ctx_out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn wasi_common_ctx_new(
+    argv: *const *const c_char,
+    argc: usize,
+    preopen_guest_paths: *const *const c_char,
+    preopen_host_paths: *const *const c_char,
+    npreopens: usize,
+    ctx_out: *mut *mut WasiCtx,
+) -> wasm32::__wasi_errno_t {
+    let ctx = c_strings(argv, argc).and_then(|args| {
+        let guest_paths = c_strings(preopen_guest_paths, npreopens)?;
+        let host_paths = c_strings(preopen_host_paths, npreopens)?;
+        let mut builder = WasiCtxBuilder::new()?
+            .args(args.into_iter())?
+            .inherit_stdio()?
+            .inherit_env()?;
+        for (guest_path, host_path) in guest_paths.into_iter().zip(host_paths) {
+            builder = builder.preopened_dir(preopen_dir(host_path)?, guest_path);
+        }
+        builder.build()
+    });
+    match ctx {
+        Ok(ctx) => {
+            *ctx_out = Box::into_raw(Box::new(ctx));
+            wasm32::__WASI_ESUCCESS
+        }
+        Err(e) => enc_errno(e),
+    }
+}
+
+/// Frees a context created with `wasi_common_ctx_new`, closing the descriptors it holds. Does
+/// nothing if `ctx` is null.
+///
+/// # Safety
+///
+/// `ctx` must have been created with `wasi_common_ctx_new`, and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn wasi_common_ctx_free(ctx: *mut WasiCtx) {
+    if !ctx.is_null() {
+        drop(Box::from_raw(ctx));
+    }
+}
+
+/// Borrows the `len` NUL-terminated strings `ptrs` points to.
+unsafe fn c_strings<'a>(
+    ptrs: *const *const c_char,
+    len: usize,
+) -> Result<Vec<&'a str>, host::__wasi_errno_t> {
+    if len == 0 {
+        return Ok(vec![]);
+    }
+    slice::from_raw_parts(ptrs, len)
+        .iter()
+        .map(|&ptr| {
+            CStr::from_ptr(ptr)
+                .to_str()
+                .map_err(|_| host::__WASI_EILSEQ)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_descriptors() {
+        use std::os::unix::net::UnixStream;
+
+        let stream = || UnixStream::pair().unwrap().0;
+        let builder = || {
+            WasiCtxBuilder::new()
+                .unwrap()
+                .preopened_memfs("/a", MemFs::new(), PreopenRights::ReadWrite)
+                .preopened_memfs("/b", MemFs::new(), PreopenRights::ReadWrite)
+        };
+        let file_type =
+            |ctx: &WasiCtx, fd| ctx.get_fd_entry(fd, 0, 0).map(|fe| fe.fd_object.file_type);
+
+        // preopens start at 3 even if a socket asked for it first
+        let ctx = builder()
+            .unix_stream(stream(), Some(5))
+            .unwrap()
+            .unix_stream(stream(), None)
+            .unwrap()
+            .build()
+            .unwrap();
+        let directory = Ok(host::__WASI_FILETYPE_DIRECTORY);
+        let socket = Ok(host::__WASI_FILETYPE_SOCKET_STREAM);
+        assert_eq!(file_type(&ctx, 3), directory);
+        assert_eq!(file_type(&ctx, 4), directory);
+        assert_eq!(file_type(&ctx, 5), socket);
+        assert_eq!(file_type(&ctx, 6), socket);
+
+        let res = builder().unix_stream(stream(), Some(2));
+        assert_eq!(res.err(), Some(host::__WASI_EBADF));
+        let res = builder().unix_stream(stream(), Some(4)).unwrap().build();
+        assert_eq!(res.err(), Some(host::__WASI_EEXIST));
+        let res = builder()
+            .unix_stream(stream(), Some(7))
+            .unwrap()
+            .unix_stream(stream(), Some(7))
+            .unwrap()
+            .build();
+        assert_eq!(res.err(), Some(host::__WASI_EEXIST));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listen_fd_names_without_nul() {
+        let mut builder = WasiCtxBuilder::new().unwrap();
+        let file = File::open(std::env::temp_dir()).unwrap();
+        let fe = FdEntry::from(file).unwrap();
+        builder.listen_fds.push((fe, String::from("http\0")));
+        assert_eq!(builder.build().err(), Some(host::__WASI_EILSEQ));
+    }
+
+    #[test]
+    fn ctx_from_c() {
+        let strings = |strings: &[&str]| -> Vec<CString> {
+            strings.iter().map(|s| CString::new(*s).unwrap()).collect()
+        };
+        let pointers = |strings: &[CString]| -> Vec<*const c_char> {
+            strings.iter().map(|s| s.as_ptr()).collect()
+        };
+        let args = strings(&["prog", "arg"]);
+        let guest_paths = strings(&["/sandbox"]);
+        let host_paths = [CString::new(std::env::temp_dir().to_str().unwrap()).unwrap()];
+        let mut ctx = std::ptr::null_mut();
+        let errno = unsafe {
+            wasi_common_ctx_new(
+                pointers(&args).as_ptr(),
+                2,
+                pointers(&guest_paths).as_ptr(),
+                pointers(&host_paths).as_ptr(),
+                1,
+                &mut ctx,
+            )
+        };
+        assert_eq!(errno, wasm32::__WASI_ESUCCESS);
+        let wasi_ctx = unsafe { &*ctx };
+        assert_eq!(wasi_ctx.args, args);
+        assert_eq!(
+            wasi_ctx.get_fd_entry(3, 0, 0).unwrap().preopen_path,
+            Some(PathBuf::from("/sandbox"))
+        );
+        unsafe { wasi_common_ctx_free(ctx) };
+
+        let bad = [b"\xff\0".as_ptr() as *const c_char];
+        let mut ctx = std::ptr::null_mut();
+        let errno = unsafe {
+            wasi_common_ctx_new(
+                bad.as_ptr(),
+                1,
+                std::ptr::null(),
+                std::ptr::null(),
+                0,
+                &mut ctx,
+            )
+        };
+        assert_eq!(errno, wasm32::__WASI_EILSEQ);
+        assert!(ctx.is_null());
+    }
 }
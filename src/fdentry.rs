@@ -1,10 +1,12 @@
 use super::host;
 use crate::sys::{errno_from_host, fdentry_impl};
+use crate::virtfs::{self, ReadPipe, VirtualFile, WritePipe};
 
 use std::fs;
-use std::io;
+use std::io::{self, Read, Write};
 use std::mem::ManuallyDrop;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Debug)]
 pub enum Descriptor {
@@ -12,6 +14,7 @@ pub enum Descriptor {
     Stdin,
     Stdout,
     Stderr,
+    VirtualFile(Box<dyn VirtualFile>),
 }
 
 #[derive(Debug)]
@@ -25,8 +28,9 @@ pub struct FdObject {
 #[derive(Debug)]
 pub struct FdEntry {
     pub fd_object: FdObject,
-    pub rights_base: host::__wasi_rights_t,
-    pub rights_inheriting: host::__wasi_rights_t,
+    // entries are shared between threads, and rights can only ever be taken away
+    rights_base: AtomicU64,
+    rights_inheriting: AtomicU64,
     pub preopen_path: Option<PathBuf>,
 }
 
@@ -47,13 +51,62 @@ impl FdEntry {
                     descriptor: ManuallyDrop::new(Descriptor::File(file)),
                     needs_close: true,
                 },
-                rights_base,
-                rights_inheriting,
+                rights_base: AtomicU64::new(rights_base),
+                rights_inheriting: AtomicU64::new(rights_inheriting),
                 preopen_path: None,
             },
         )
     }
 
+    pub fn from_virtual(file: Box<dyn VirtualFile>) -> Self {
+        let file_type = file.file_type();
+        let (rights_base, rights_inheriting) = virtfs::type_rights(file_type);
+        Self {
+            fd_object: FdObject {
+                file_type,
+                descriptor: ManuallyDrop::new(Descriptor::VirtualFile(file)),
+                needs_close: true,
+            },
+            rights_base: AtomicU64::new(rights_base),
+            rights_inheriting: AtomicU64::new(rights_inheriting),
+            preopen_path: None,
+        }
+    }
+
+    /// Creates a read-only entry reading from `source`.
+    pub fn from_reader(source: Box<dyn Read + Send>) -> Self {
+        let fe = Self::from_virtual(Box::new(ReadPipe::new(source)));
+        fe.restrict_rights(!host::__WASI_RIGHT_FD_WRITE, host::RIGHTS_ALL);
+        fe
+    }
+
+    /// Creates a write-only entry forwarding everything written to it to `sink`.
+    pub fn from_writer(sink: Box<dyn Write + Send>) -> Self {
+        let fe = Self::from_virtual(Box::new(WritePipe::new(sink)));
+        fe.restrict_rights(!host::__WASI_RIGHT_FD_READ, host::RIGHTS_ALL);
+        fe
+    }
+
+    pub fn rights_base(&self) -> host::__wasi_rights_t {
+        self.rights_base.load(Ordering::SeqCst)
+    }
+
+    pub fn rights_inheriting(&self) -> host::__wasi_rights_t {
+        self.rights_inheriting.load(Ordering::SeqCst)
+    }
+
+    /// Drops all base and inheriting rights which aren't in `base_mask` and `inheriting_mask`
+    /// respectively.
+    pub fn restrict_rights(
+        &self,
+        base_mask: host::__wasi_rights_t,
+        inheriting_mask: host::__wasi_rights_t,
+    ) {
+        self.rights_base.fetch_and(base_mask, Ordering::SeqCst);
+        self.rights_inheriting
+            .fetch_and(inheriting_mask, Ordering::SeqCst);
+    }
+
     pub fn duplicate(file: &fs::File) -> Result<Self, host::__wasi_errno_t> {
         file.try_clone()
             .map_err(|err| err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host))
@@ -68,8 +121,8 @@ impl FdEntry {
                     descriptor: ManuallyDrop::new(Descriptor::Stdin),
                     needs_close: true,
                 },
-                rights_base,
-                rights_inheriting,
+                rights_base: AtomicU64::new(rights_base),
+                rights_inheriting: AtomicU64::new(rights_inheriting),
                 preopen_path: None,
             },
         )
@@ -83,8 +136,8 @@ impl FdEntry {
                     descriptor: ManuallyDrop::new(Descriptor::Stdout),
                     needs_close: true,
                 },
-                rights_base,
-                rights_inheriting,
+                rights_base: AtomicU64::new(rights_base),
+                rights_inheriting: AtomicU64::new(rights_inheriting),
                 preopen_path: None,
             },
         )
@@ -98,8 +151,8 @@ impl FdEntry {
                     descriptor: ManuallyDrop::new(Descriptor::Stderr),
                     needs_close: true,
                 },
-                rights_base,
-                rights_inheriting,
+                rights_base: AtomicU64::new(rights_base),
+                rights_inheriting: AtomicU64::new(rights_inheriting),
                 preopen_path: None,
             },
         )
@@ -0,0 +1,848 @@
+//! Typed access to the linear memory of a guest.
+//!
+//! Hostcalls never dereference guest pointers directly. Instead, a pointer received from the
+//! guest is turned into a `GuestPtr` or a `GuestSlice`, which checks once that it is properly
+//! aligned and within bounds, and from then on can be read and written safely.
+//!
+//! Memory shared between threads may be written by the guest while a hostcall accesses it, so it
+//! is only ever copied from and to, byte by byte with atomic accesses, and never borrowed in
+//! place. See `GuestMemory::is_shared`.
+use crate::{host, wasi_snapshot_preview1, wasm32, wasm64};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of, MaybeUninit};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// An address in guest memory.
+///
+/// Hostcalls take addresses and lengths at the width of wasm64, so that they serve guests of
+/// either ABI; those of wasm32 guests are simply zero-extended.
+pub type GuestAddr = u64;
+
+/// A length in guest memory, at the width of wasm64.
+pub type GuestSize = u64;
+
+/// The ABI of a guest, which determines the width of pointers and sizes in its memory, and with
+/// it the layout of the WASI types containing them, as defined in the `wasm32` and `wasm64`
+/// modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GuestAbi {
+    Wasm32,
+    Wasm64,
+}
+
+/// The linear memory of a guest, as provided by the runtime.
+///
+/// Handles into the memory don't hold on to its address: it is looked up with `base` on every
+/// access, so the runtime is free to move the memory, e.g. when it grows.
+///
+/// # Safety
+///
+/// `base` must return a pointer to `len` bytes which are valid for reads and writes until the
+/// memory is next moved, and `len` must never decrease. Unless `is_shared` returns `true`, the
+/// memory mustn't be accessed by anything else during a hostcall.
+pub unsafe trait GuestMemory {
+    /// Returns the address and the length in bytes of the memory.
+    fn base(&self) -> (*mut u8, usize);
+
+    /// Returns `true` if the memory is shared with other threads, which may access it while a
+    /// hostcall is in progress. Such a memory must never be moved.
+    fn is_shared(&self) -> bool {
+        false
+    }
+
+    /// Returns the ABI of the guest the memory belongs to.
+    fn abi(&self) -> GuestAbi {
+        GuestAbi::Wasm32
+    }
+}
+
+/// Guest memory backed by a mutable byte slice borrowed for the lifetime `'a`.
+pub struct SliceMemory<'a> {
+    ptr: *mut u8,
+    len: usize,
+    abi: GuestAbi,
+    _slice: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> SliceMemory<'a> {
+    /// The memory of a wasm32 guest.
+    pub fn new(memory: &'a mut [u8]) -> Self {
+        Self::with_abi(memory, GuestAbi::Wasm32)
+    }
+
+    /// The memory of a guest of the given ABI.
+    pub fn with_abi(memory: &'a mut [u8], abi: GuestAbi) -> Self {
+        Self {
+            ptr: memory.as_mut_ptr(),
+            len: memory.len(),
+            abi,
+            _slice: PhantomData,
+        }
+    }
+}
+
+impl<'a> fmt::Debug for SliceMemory<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SliceMemory")
+            .field("len", &self.len)
+            .field("abi", &self.abi)
+            .finish()
+    }
+}
+
+unsafe impl<'a> GuestMemory for SliceMemory<'a> {
+    fn base(&self) -> (*mut u8, usize) {
+        (self.ptr, self.len)
+    }
+
+    fn abi(&self) -> GuestAbi {
+        self.abi
+    }
+}
+
+/// Creates the memory of a guest from the `len` bytes at `ptr`, for embedders calling the
+/// hostcalls from C, which take it as a `GuestMemory *`. `abi` is 0 for `GuestAbi::Wasm32` and 1
+/// for `GuestAbi::Wasm64`. Returns a null pointer if `ptr` is null or `abi` is unknown.
+///
+/// # Safety
+///
+/// The bytes must remain valid for reads and writes, and mustn't be accessed by anything else
+/// during a hostcall, until the memory is freed with `wasi_common_slice_memory_free`.
+#[no_mangle]
+pub unsafe extern "C" fn wasi_common_slice_memory_new(
+    ptr: *mut u8,
+    len: usize,
+    abi: u32,
+) -> *mut &'static dyn GuestMemory {
+    let abi = match abi {
+        0 => GuestAbi::Wasm32,
+        1 => GuestAbi::Wasm64,
+        _ => return ptr::null_mut(),
+    };
+    if ptr.is_null() {
+        return ptr::null_mut();
+    }
+    let memory: &'static SliceMemory = Box::leak(Box::new(SliceMemory::with_abi(
+        slice::from_raw_parts_mut(ptr, len),
+        abi,
+    )));
+    Box::into_raw(Box::new(memory))
+}
+
+/// Frees a memory created with `wasi_common_slice_memory_new`, leaving the bytes it refers to
+/// untouched. Does nothing if `memory` is null.
+///
+/// # Safety
+///
+/// `memory` must have been returned by `wasi_common_slice_memory_new`, and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn wasi_common_slice_memory_free(memory: *mut &'static dyn GuestMemory) {
+    if memory.is_null() {
+        return;
+    }
+    let memory = Box::from_raw(memory);
+    drop(Box::from_raw(
+        *memory as *const dyn GuestMemory as *mut SliceMemory,
+    ));
+}
+
+/// Types which can be read from and written to guest memory as they are.
+///
+/// # Safety
+///
+/// Every bit pattern must be a valid value of the type, so it mustn't contain references,
+/// `bool`s, enums and the like. The type mustn't have any padding either, as values are copied
+/// byte by byte, except for the bytes of a union which some of its variants leave unused.
+///
+/// Values are written to guest memory in full, so every one of their bytes must be initialized:
+/// a union whose variants differ in size must start out zeroed, e.g. with `mem::zeroed`, before
+/// one of its variants is set.
+pub unsafe trait GuestType: Copy {}
+
+macro_rules! guest_types {
+    ( $( $ty:ty ),* $(,)* ) => {
+        $( unsafe impl GuestType for $ty {} )*
+    };
+}
+
+guest_types!(
+    u8,
+    u16,
+    u32,
+    u64,
+    i8,
+    i16,
+    i32,
+    i64,
+    wasm32::__wasi_ciovec_t,
+    wasm32::__wasi_dirent_t,
+    wasm32::__wasi_event_t,
+    wasm32::__wasi_fdstat_t,
+    wasm32::__wasi_filestat_t,
+    wasm32::__wasi_iovec_t,
+    wasm32::__wasi_prestat_t,
+    wasm32::__wasi_subscription_t,
+    wasi_snapshot_preview1::__wasi_filestat_t,
+    wasi_snapshot_preview1::__wasi_subscription_t,
+    wasm64::__wasi_ciovec_t,
+    wasm64::__wasi_iovec_t,
+    wasm64::__wasi_prestat_t,
+);
+
+/// Checks that `len` values of `T` at `offset` are aligned and fit in memory, and returns their
+/// size in bytes.
+fn validate<T>(
+    memory: &dyn GuestMemory,
+    offset: GuestAddr,
+    len: usize,
+) -> Result<usize, host::__wasi_errno_t> {
+    let offset = usize::try_from(offset).map_err(|_| host::__WASI_EFAULT)?;
+    if offset % align_of::<T>() != 0 {
+        return Err(host::__WASI_EINVAL);
+    }
+    let len_bytes = size_of::<T>()
+        .checked_mul(len)
+        .ok_or(host::__WASI_EOVERFLOW)?;
+    let end = offset.checked_add(len_bytes).ok_or(host::__WASI_EFAULT)?;
+    if end > memory.base().1 {
+        return Err(host::__WASI_EFAULT);
+    }
+    Ok(len_bytes)
+}
+
+/// Copies `len` bytes from `src` to `dst`, which don't overlap.
+///
+/// If `shared`, the bytes are loaded and stored atomically, as other threads may access them
+/// concurrently, in which case a plain copy would be a data race.
+unsafe fn copy_bytes(shared: bool, src: *const u8, dst: *mut u8, len: usize) {
+    if shared {
+        for i in 0..len {
+            let byte = (*(src.add(i) as *const AtomicU8)).load(Ordering::Relaxed);
+            (*(dst.add(i) as *const AtomicU8)).store(byte, Ordering::Relaxed);
+        }
+    } else {
+        ptr::copy_nonoverlapping(src, dst, len);
+    }
+}
+
+/// A pointer to a value of type `T` in guest memory, known to be aligned and in bounds.
+pub struct GuestPtr<'a, T> {
+    memory: &'a dyn GuestMemory,
+    offset: GuestAddr,
+    _ty: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: GuestType> GuestPtr<'a, T> {
+    pub fn new(
+        memory: &'a dyn GuestMemory,
+        offset: GuestAddr,
+    ) -> Result<Self, host::__wasi_errno_t> {
+        validate::<T>(memory, offset, 1)?;
+        Ok(Self {
+            memory,
+            offset,
+            _ty: PhantomData,
+        })
+    }
+
+    pub fn offset(&self) -> GuestAddr {
+        self.offset
+    }
+
+    fn as_raw(&self) -> *mut T {
+        // in bounds, as the memory never shrinks
+        unsafe { self.memory.base().0.add(self.offset as usize) as *mut T }
+    }
+
+    pub fn read(&self) -> T {
+        let mut value = MaybeUninit::<T>::uninit();
+        unsafe {
+            copy_bytes(
+                self.memory.is_shared(),
+                self.as_raw() as *const u8,
+                value.as_mut_ptr() as *mut u8,
+                size_of::<T>(),
+            );
+            // every bit pattern is a valid `T`
+            value.assume_init()
+        }
+    }
+
+    pub fn write(&self, value: T) {
+        unsafe {
+            copy_bytes(
+                self.memory.is_shared(),
+                &value as *const T as *const u8,
+                self.as_raw() as *mut u8,
+                size_of::<T>(),
+            )
+        }
+    }
+}
+
+impl<'a, T> Clone for GuestPtr<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for GuestPtr<'a, T> {}
+
+impl<'a, T> fmt::Debug for GuestPtr<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GuestPtr({:#x})", self.offset)
+    }
+}
+
+/// A contiguous array of values of type `T` in guest memory, known to be aligned and in bounds.
+pub struct GuestSlice<'a, T> {
+    memory: &'a dyn GuestMemory,
+    offset: GuestAddr,
+    len: usize,
+    _ty: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: GuestType> GuestSlice<'a, T> {
+    pub fn new(
+        memory: &'a dyn GuestMemory,
+        offset: GuestAddr,
+        len: GuestSize,
+    ) -> Result<Self, host::__wasi_errno_t> {
+        let len = usize::try_from(len).map_err(|_| host::__WASI_EOVERFLOW)?;
+        validate::<T>(memory, offset, len)?;
+        Ok(Self {
+            memory,
+            offset,
+            len,
+            _ty: PhantomData,
+        })
+    }
+
+    pub fn offset(&self) -> GuestAddr {
+        self.offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a raw pointer to the first element. It is only valid until the memory is moved.
+    pub fn as_mut_ptr(&self) -> *mut T {
+        // in bounds, as the memory never shrinks
+        unsafe { self.memory.base().0.add(self.offset as usize) as *mut T }
+    }
+
+    /// Returns a pointer to the element at `index`, or `None` if it's out of bounds.
+    pub fn get(&self, index: usize) -> Option<GuestPtr<'a, T>> {
+        if index < self.len {
+            Some(GuestPtr {
+                memory: self.memory,
+                offset: self.offset + (index * size_of::<T>()) as GuestAddr,
+                _ty: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn iter<'s>(&'s self) -> impl Iterator<Item = GuestPtr<'a, T>> + 's {
+        (0..self.len).filter_map(move |index| self.get(index))
+    }
+
+    /// Divides the slice into two at `mid`, like `slice::split_at`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid > len`.
+    pub fn split_at(&self, mid: usize) -> (Self, Self) {
+        assert!(
+            mid <= self.len,
+            "split index out of bounds of the guest slice"
+        );
+        let head = Self {
+            memory: self.memory,
+            offset: self.offset,
+            len: mid,
+            _ty: PhantomData,
+        };
+        let tail = Self {
+            memory: self.memory,
+            offset: self.offset + (mid * size_of::<T>()) as GuestAddr,
+            len: self.len - mid,
+            _ty: PhantomData,
+        };
+        (head, tail)
+    }
+
+    /// Copies the elements out of guest memory.
+    pub fn to_vec(&self) -> Vec<T> {
+        let mut vec = Vec::with_capacity(self.len);
+        unsafe {
+            copy_bytes(
+                self.memory.is_shared(),
+                self.as_mut_ptr() as *const u8,
+                vec.as_mut_ptr() as *mut u8,
+                self.len * size_of::<T>(),
+            );
+            vec.set_len(self.len);
+        }
+        vec
+    }
+
+    /// Copies all elements out of guest memory into `dst`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dst` has a different length.
+    pub fn copy_to_slice(&self, dst: &mut [T]) {
+        assert_eq!(
+            self.len,
+            dst.len(),
+            "destination slice length does not match the guest slice"
+        );
+        unsafe {
+            copy_bytes(
+                self.memory.is_shared(),
+                self.as_mut_ptr() as *const u8,
+                dst.as_mut_ptr() as *mut u8,
+                self.len * size_of::<T>(),
+            )
+        }
+    }
+
+    /// Copies all elements from `src` into guest memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `src` has a different length.
+    pub fn copy_from_slice(&self, src: &[T]) {
+        assert_eq!(
+            self.len,
+            src.len(),
+            "source slice length does not match the guest slice"
+        );
+        unsafe {
+            copy_bytes(
+                self.memory.is_shared(),
+                src.as_ptr() as *const u8,
+                self.as_mut_ptr() as *mut u8,
+                self.len * size_of::<T>(),
+            )
+        }
+    }
+
+    /// Borrows the elements in place.
+    ///
+    /// # Safety
+    ///
+    /// The memory mustn't be shared, nor moved, and the elements mustn't be written through any
+    /// other handle, for as long as the returned slice is alive.
+    pub unsafe fn as_slice(&self) -> &'a [T] {
+        slice::from_raw_parts(self.as_mut_ptr(), self.len)
+    }
+
+    /// Mutably borrows the elements in place.
+    ///
+    /// # Safety
+    ///
+    /// The memory mustn't be shared, nor moved, and the elements mustn't be accessed through any
+    /// other handle, for as long as the returned slice is alive.
+    pub unsafe fn as_slice_mut(&self) -> &'a mut [T] {
+        slice::from_raw_parts_mut(self.as_mut_ptr(), self.len)
+    }
+}
+
+impl<'a> GuestSlice<'a, u8> {
+    /// Lets `fill` write into the buffer, and returns the number of bytes it has written at its
+    /// start.
+    ///
+    /// The buffer is borrowed in place, unless the memory is shared, in which case `fill` writes
+    /// into a copy, whose first bytes are copied back into guest memory afterwards.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `as_slice_mut`, unless the memory is shared.
+    pub unsafe fn fill_with<E>(
+        &self,
+        fill: impl FnOnce(&mut [u8]) -> Result<usize, E>,
+    ) -> Result<usize, E> {
+        if self.memory.is_shared() {
+            let mut buf = vec![0; self.len];
+            let written = fill(&mut buf)?;
+            self.split_at(written).0.copy_from_slice(&buf[..written]);
+            Ok(written)
+        } else {
+            fill(self.as_slice_mut())
+        }
+    }
+}
+
+/// Lets `read` scatter data into the buffers `iovs` of `memory`, and returns the number of bytes
+/// it has read.
+///
+/// Like `GuestSlice::fill_with`, the buffers are borrowed in place, unless the memory is shared.
+///
+/// # Safety
+///
+/// The same as for `GuestSlice::as_slice_mut` for every buffer, unless the memory is shared.
+pub(crate) unsafe fn read_into_iovecs<E>(
+    memory: &dyn GuestMemory,
+    iovs: &[GuestSlice<u8>],
+    read: impl FnOnce(&mut [io::IoSliceMut]) -> Result<usize, E>,
+) -> Result<usize, E> {
+    if memory.is_shared() {
+        let buf_len = iovs.iter().map(GuestSlice::len).sum();
+        let mut buf = vec![0; buf_len];
+        let nread = read(&mut [io::IoSliceMut::new(&mut buf)])?;
+        scatter(iovs, &buf[..nread]);
+        Ok(nread)
+    } else {
+        let mut iovs: Vec<_> = iovs
+            .iter()
+            .map(|iov| io::IoSliceMut::new(iov.as_slice_mut()))
+            .collect();
+        read(&mut iovs)
+    }
+}
+
+/// Lets `write` gather data from the buffers `iovs` of `memory`, and returns the number of bytes
+/// it has written.
+///
+/// The buffers are borrowed in place, unless the memory is shared, in which case `write` gets a
+/// copy of their contents.
+///
+/// # Safety
+///
+/// The same as for `GuestSlice::as_slice` for every buffer, unless the memory is shared.
+pub(crate) unsafe fn write_from_ciovecs<E>(
+    memory: &dyn GuestMemory,
+    iovs: &[GuestSlice<u8>],
+    write: impl FnOnce(&[io::IoSlice]) -> Result<usize, E>,
+) -> Result<usize, E> {
+    if memory.is_shared() {
+        let buf = gather(iovs);
+        write(&[io::IoSlice::new(&buf)])
+    } else {
+        let iovs: Vec<_> = iovs
+            .iter()
+            .map(|iov| io::IoSlice::new(iov.as_slice()))
+            .collect();
+        write(&iovs)
+    }
+}
+
+/// Copies the contents of the buffers `iovs` out of guest memory, one after another.
+pub(crate) fn gather(iovs: &[GuestSlice<u8>]) -> Vec<u8> {
+    let mut buf = vec![0; iovs.iter().map(GuestSlice::len).sum()];
+    let mut buf_offset = 0;
+    for iov in iovs {
+        iov.copy_to_slice(&mut buf[buf_offset..buf_offset + iov.len()]);
+        buf_offset += iov.len();
+    }
+    buf
+}
+
+/// Copies `buf` into the buffers `iovs`, filling one after another until `buf` is exhausted.
+///
+/// # Panics
+///
+/// Panics if `buf` doesn't fit in the buffers.
+pub(crate) fn scatter(iovs: &[GuestSlice<u8>], mut buf: &[u8]) {
+    for iov in iovs {
+        if buf.is_empty() {
+            break;
+        }
+        let len = std::cmp::min(iov.len(), buf.len());
+        iov.split_at(len).0.copy_from_slice(&buf[..len]);
+        buf = &buf[len..];
+    }
+    assert!(buf.is_empty(), "the guest buffers are too small");
+}
+
+impl<'a, T> Clone for GuestSlice<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for GuestSlice<'a, T> {}
+
+impl<'a, T> fmt::Debug for GuestSlice<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GuestSlice({:#x}, {})", self.offset, self.len)
+    }
+}
+
+/// Keeps track of the regions of guest memory the host accesses in place during a hostcall.
+///
+/// The guest may well pass buffers which overlap, but the host mustn't hold a mutable reference
+/// to memory which is referenced anywhere else. Every buffer is registered here before it is
+/// borrowed, and one overlapping an incompatible borrow is rejected with `__WASI_EINVAL`.
+#[derive(Debug, Default)]
+pub struct GuestBorrows {
+    /// Mutably borrowed regions, which never overlap, keyed by their start and mapped to their end.
+    mutable: BTreeMap<usize, usize>,
+    /// Immutably borrowed regions, which may overlap each other.
+    shared: Vec<(usize, usize)>,
+}
+
+impl GuestBorrows {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn borrow<T: GuestType>(
+        &mut self,
+        slice: &GuestSlice<T>,
+    ) -> Result<(), host::__wasi_errno_t> {
+        self.insert(slice.offset, slice.len * size_of::<T>(), false)
+    }
+
+    pub fn borrow_mut<T: GuestType>(
+        &mut self,
+        slice: &GuestSlice<T>,
+    ) -> Result<(), host::__wasi_errno_t> {
+        self.insert(slice.offset, slice.len * size_of::<T>(), true)
+    }
+
+    pub fn borrow_ptr<T: GuestType>(
+        &mut self,
+        ptr: &GuestPtr<T>,
+    ) -> Result<(), host::__wasi_errno_t> {
+        self.insert(ptr.offset, size_of::<T>(), false)
+    }
+
+    pub fn borrow_ptr_mut<T: GuestType>(
+        &mut self,
+        ptr: &GuestPtr<T>,
+    ) -> Result<(), host::__wasi_errno_t> {
+        self.insert(ptr.offset, size_of::<T>(), true)
+    }
+
+    fn insert(
+        &mut self,
+        offset: GuestAddr,
+        len_bytes: usize,
+        mutable: bool,
+    ) -> Result<(), host::__wasi_errno_t> {
+        // an empty region can't alias anything
+        if len_bytes == 0 {
+            return Ok(());
+        }
+        // the region has been checked to be within memory, so this doesn't overflow
+        let start = offset as usize;
+        let end = start + len_bytes;
+        // mutable regions don't overlap, so only the last one starting before `end` can reach
+        // into this region
+        if let Some((_, &other_end)) = self.mutable.range(..end).next_back() {
+            if other_end > start {
+                return Err(host::__WASI_EINVAL);
+            }
+        }
+        if mutable {
+            let overlaps_shared = self
+                .shared
+                .iter()
+                .any(|&(other_start, other_end)| other_start < end && start < other_end);
+            if overlaps_shared {
+                return Err(host::__WASI_EINVAL);
+            }
+            self.mutable.insert(start, end);
+        } else {
+            self.shared.push((start, end));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_bounds_and_alignment() {
+        let mut bytes = [0; 64];
+        let memory = SliceMemory::new(&mut bytes);
+        assert_eq!(validate::<u64>(&memory, 8, 7), Ok(56));
+        assert_eq!(validate::<u8>(&memory, 64, 0), Ok(0));
+        assert_eq!(validate::<u32>(&memory, 2, 1), Err(host::__WASI_EINVAL));
+        assert_eq!(validate::<u64>(&memory, 60, 0), Err(host::__WASI_EINVAL));
+        assert_eq!(validate::<u64>(&memory, 8, 8), Err(host::__WASI_EFAULT));
+        assert_eq!(validate::<u8>(&memory, 65, 0), Err(host::__WASI_EFAULT));
+        assert_eq!(
+            validate::<u64>(&memory, 0, usize::max_value()),
+            Err(host::__WASI_EOVERFLOW)
+        );
+        assert_eq!(
+            validate::<u8>(&memory, 32, usize::max_value() - 16),
+            Err(host::__WASI_EFAULT)
+        );
+        assert_eq!(
+            validate::<u64>(&memory, u64::max_value() - 7, 1),
+            Err(host::__WASI_EFAULT)
+        );
+    }
+
+    #[test]
+    fn slice_memory_from_c() {
+        let mut bytes = [0; 16];
+        unsafe {
+            assert!(wasi_common_slice_memory_new(ptr::null_mut(), 16, 0).is_null());
+            assert!(wasi_common_slice_memory_new(bytes.as_mut_ptr(), 16, 2).is_null());
+            let memory = wasi_common_slice_memory_new(bytes.as_mut_ptr(), 16, 1);
+            assert_eq!((**memory).abi(), GuestAbi::Wasm64);
+            assert_eq!((**memory).base(), (bytes.as_mut_ptr(), 16));
+            GuestPtr::<u32>::new(*memory, 4).unwrap().write(0xdead_beef);
+            assert_eq!(
+                GuestPtr::<u32>::new(*memory, 16).map(|_| ()),
+                Err(host::__WASI_EFAULT)
+            );
+            wasi_common_slice_memory_free(memory);
+            wasi_common_slice_memory_free(ptr::null_mut());
+        }
+        assert_eq!(bytes[4..8], 0xdead_beef_u32.to_le_bytes());
+    }
+
+    #[test]
+    fn borrows() {
+        let mut bytes = [0; 64];
+        let memory = SliceMemory::new(&mut bytes);
+        let region = |offset, len| GuestSlice::<u8>::new(&memory, offset, len).unwrap();
+
+        let mut borrows = GuestBorrows::new();
+        borrows.borrow_mut(&region(8, 8)).unwrap();
+        // adjacent regions don't overlap
+        borrows.borrow_mut(&region(16, 8)).unwrap();
+        borrows.borrow(&region(0, 8)).unwrap();
+        borrows.borrow(&region(24, 8)).unwrap();
+        // nor do empty ones
+        borrows.borrow_mut(&region(12, 0)).unwrap();
+        // shared regions may overlap each other
+        borrows.borrow(&region(28, 8)).unwrap();
+
+        assert_eq!(borrows.borrow_mut(&region(12, 8)), Err(host::__WASI_EINVAL));
+        assert_eq!(borrows.borrow_mut(&region(0, 64)), Err(host::__WASI_EINVAL));
+        assert_eq!(borrows.borrow_mut(&region(4, 5)), Err(host::__WASI_EINVAL));
+        assert_eq!(borrows.borrow(&region(23, 2)), Err(host::__WASI_EINVAL));
+        assert_eq!(borrows.borrow_mut(&region(30, 4)), Err(host::__WASI_EINVAL));
+        let ptr = GuestPtr::<u32>::new(&memory, 20).unwrap();
+        assert_eq!(borrows.borrow_ptr(&ptr), Err(host::__WASI_EINVAL));
+        assert_eq!(borrows.borrow_ptr_mut(&ptr), Err(host::__WASI_EINVAL));
+        // rejected regions aren't recorded
+        borrows.borrow_mut(&region(36, 28)).unwrap();
+    }
+
+    /// Memory which claims to be shared, so that it is only ever copied from and to.
+    struct Shared<'a>(SliceMemory<'a>);
+
+    unsafe impl<'a> GuestMemory for Shared<'a> {
+        fn base(&self) -> (*mut u8, usize) {
+            self.0.base()
+        }
+
+        fn is_shared(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn shared_fill_with() {
+        let mut bytes = [0xff; 16];
+        let memory = Shared(SliceMemory::new(&mut bytes));
+        let buf = GuestSlice::<u8>::new(&memory, 4, 8).unwrap();
+        let filled = unsafe {
+            buf.fill_with(|buf| {
+                assert_eq!(buf.len(), 8);
+                buf.copy_from_slice(&[1; 8]);
+                Ok::<_, ()>(3)
+            })
+        };
+        assert_eq!(filled, Ok(3));
+        assert_eq!(unsafe { buf.fill_with(|_| Err(())) }, Err(()));
+        // only the bytes reported as written are copied back
+        assert_eq!(bytes[..8], [0xff, 0xff, 0xff, 0xff, 1, 1, 1, 0xff]);
+        assert_eq!(bytes[8..], [0xff; 8]);
+    }
+
+    #[test]
+    fn shared_iovecs() {
+        let mut bytes = [0; 16];
+        let memory = Shared(SliceMemory::new(&mut bytes));
+        let iovs = [
+            GuestSlice::<u8>::new(&memory, 0, 4).unwrap(),
+            GuestSlice::<u8>::new(&memory, 8, 4).unwrap(),
+        ];
+        let nread = unsafe {
+            read_into_iovecs(&memory, &iovs, |bufs| {
+                // the buffers are read into as one
+                assert_eq!(bufs.len(), 1);
+                assert_eq!(bufs[0].len(), 8);
+                bufs[0][..6].copy_from_slice(b"abcdef");
+                Ok::<_, ()>(6)
+            })
+        };
+        assert_eq!(nread, Ok(6));
+        let nwritten = unsafe {
+            write_from_ciovecs(&memory, &iovs, |bufs| {
+                assert_eq!(bufs.len(), 1);
+                assert_eq!(&*bufs[0], b"abcdef\0\0");
+                Ok::<_, ()>(bufs[0].len())
+            })
+        };
+        assert_eq!(nwritten, Ok(8));
+        assert_eq!(&bytes, b"abcd\0\0\0\0ef\0\0\0\0\0\0");
+    }
+
+    #[test]
+    fn shared_scatter() {
+        let mut bytes = [0; 8];
+        let memory = Shared(SliceMemory::new(&mut bytes));
+        let iovs = [
+            GuestSlice::<u8>::new(&memory, 0, 0).unwrap(),
+            GuestSlice::<u8>::new(&memory, 2, 3).unwrap(),
+            GuestSlice::<u8>::new(&memory, 6, 2).unwrap(),
+        ];
+        scatter(&iovs, b"xyzw");
+        assert_eq!(gather(&iovs), b"xyzw\0");
+        assert_eq!(&bytes, b"\0\0xyz\0w\0");
+    }
+
+    #[test]
+    #[should_panic(expected = "the guest buffers are too small")]
+    fn scatter_overflow() {
+        let mut bytes = [0; 8];
+        let memory = Shared(SliceMemory::new(&mut bytes));
+        scatter(
+            &[GuestSlice::<u8>::new(&memory, 0, 4).unwrap()],
+            b"too long",
+        );
+    }
+
+    #[test]
+    fn prestat_is_fully_written() {
+        let prestat = host::__wasi_prestat_t {
+            pr_type: host::__WASI_PREOPENTYPE_DIR,
+            u: host::__wasi_prestat_u_t {
+                dir: host::__wasi_prestat_dir_t { pr_name_len: 5 },
+            },
+        };
+        let mut bytes = [0xff; 16];
+        let memory = Shared(SliceMemory::new(&mut bytes));
+        crate::memory::enc_prestat_byref(&memory, 0, prestat).unwrap();
+        assert_eq!(bytes[..8], [0, 0, 0, 0, 5, 0, 0, 0]);
+        let mut bytes = [0xff; 16];
+        let memory = SliceMemory::with_abi(&mut bytes, GuestAbi::Wasm64);
+        crate::memory::enc_prestat_byref(&memory, 0, prestat).unwrap();
+        assert_eq!(bytes, [0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -1,10 +1,15 @@
 #![allow(non_camel_case_types)]
 use super::return_enc_errno;
-use crate::ctx::WasiCtx;
+use crate::ctx::{WasiCtx, WasiSnapshot};
 use crate::fdentry::Descriptor;
+use crate::guest_memory::{
+    gather, read_into_iovecs, scatter, write_from_ciovecs, GuestAddr, GuestBorrows, GuestMemory,
+    GuestSize,
+};
 use crate::memory::*;
 use crate::sys::{errno_from_host, host_impl, hostcalls_impl};
-use crate::{host, wasm32};
+use crate::virtfs::hostcalls_impl as virtfs;
+use crate::{host, wasi_snapshot_preview1, wasm32};
 use log::trace;
 use std::convert::identity;
 use std::io::{self, Read, Write};
@@ -12,21 +17,14 @@ use std::io::{self, Read, Write};
 use wasi_common_cbindgen::wasi_common_cbindgen;
 
 #[wasi_common_cbindgen]
-pub fn fd_close(wasi_ctx: &mut WasiCtx, fd: wasm32::__wasi_fd_t) -> wasm32::__wasi_errno_t {
+pub fn fd_close(wasi_ctx: &WasiCtx, fd: wasm32::__wasi_fd_t) -> wasm32::__wasi_errno_t {
     trace!("fd_close(fd={:?})", fd);
 
     let fd = dec_fd(fd);
-    if let Some(fdent) = wasi_ctx.fds.get(&fd) {
-        // can't close preopened files
-        if fdent.preopen_path.is_some() {
-            return return_enc_errno(host::__WASI_ENOTSUP);
-        }
-    }
-    let ret = if let Some(mut fe) = wasi_ctx.fds.remove(&fd) {
-        fe.fd_object.needs_close = true;
-        host::__WASI_ESUCCESS
-    } else {
-        host::__WASI_EBADF
+    // the file is closed once hostcalls still using it on other threads are done with it
+    let ret = match wasi_ctx.remove_fd_entry(fd) {
+        Ok(_) => host::__WASI_ESUCCESS,
+        Err(e) => e,
     };
 
     return_enc_errno(ret)
@@ -42,13 +40,16 @@ pub fn fd_datasync(wasi_ctx: &WasiCtx, fd: wasm32::__wasi_fd_t) -> wasm32::__was
         Ok(fe) => fe,
         Err(e) => return return_enc_errno(e),
     };
-    let file = match &*fe.fd_object.descriptor {
-        Descriptor::File(f) => f,
-        _ => return return_enc_errno(host::__WASI_EBADF),
-    };
-    let ret = match file.sync_data() {
-        Ok(()) => host::__WASI_ESUCCESS,
-        Err(err) => err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host),
+    let ret = match &*fe.fd_object.descriptor {
+        Descriptor::File(f) => match f.sync_data() {
+            Ok(()) => host::__WASI_ESUCCESS,
+            Err(err) => err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host),
+        },
+        Descriptor::VirtualFile(vf) => match vf.datasync() {
+            Ok(()) => host::__WASI_ESUCCESS,
+            Err(e) => e,
+        },
+        _ => host::__WASI_EBADF,
     };
 
     return_enc_errno(ret)
@@ -57,12 +58,12 @@ pub fn fd_datasync(wasi_ctx: &WasiCtx, fd: wasm32::__wasi_fd_t) -> wasm32::__was
 #[wasi_common_cbindgen]
 pub fn fd_pread(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
-    iovs_ptr: wasm32::uintptr_t,
-    iovs_len: wasm32::size_t,
+    iovs_ptr: GuestAddr,
+    iovs_len: GuestSize,
     offset: wasm32::__wasi_filesize_t,
-    nread: wasm32::uintptr_t,
+    nread: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "fd_pread(fd={:?}, iovs_ptr={:#x?}, iovs_len={:?}, offset={}, nread={:#x?})",
@@ -74,7 +75,7 @@ pub fn fd_pread(
     );
 
     let fd = dec_fd(fd);
-    let iovs = match dec_iovec_slice(memory, iovs_ptr, iovs_len) {
+    let iovs = match dec_iovec_slice(memory, &mut GuestBorrows::new(), iovs_ptr, iovs_len) {
         Ok(iovs) => iovs,
         Err(e) => return return_enc_errno(e),
     };
@@ -83,33 +84,23 @@ pub fn fd_pread(
         Ok(fe) => fe,
         Err(e) => return return_enc_errno(e),
     };
-    let file = match &*fe.fd_object.descriptor {
-        Descriptor::File(f) => f,
-        _ => return return_enc_errno(host::__WASI_EBADF),
-    };
 
     let offset = dec_filesize(offset);
     if offset > i64::max_value() as u64 {
         return return_enc_errno(host::__WASI_EIO);
     }
-    let buf_size = iovs.iter().map(|v| v.buf_len).sum();
+    let buf_size = iovs.iter().map(|v| v.len()).sum();
     let mut buf = vec![0; buf_size];
-    let host_nread = match hostcalls_impl::fd_pread(file, &mut buf, offset) {
+    let maybe_host_nread = match &*fe.fd_object.descriptor {
+        Descriptor::File(f) => hostcalls_impl::fd_pread(f, &mut buf, offset),
+        Descriptor::VirtualFile(vf) => vf.pread(&mut buf, offset),
+        _ => Err(host::__WASI_EBADF),
+    };
+    let host_nread = match maybe_host_nread {
         Ok(host_nread) => host_nread,
         Err(e) => return return_enc_errno(e),
     };
-    let mut buf_offset = 0;
-    let mut left = host_nread;
-    for iov in &iovs {
-        if left == 0 {
-            break;
-        }
-        let vec_len = std::cmp::min(iov.buf_len, left);
-        unsafe { std::slice::from_raw_parts_mut(iov.buf as *mut u8, vec_len) }
-            .copy_from_slice(&buf[buf_offset..buf_offset + vec_len]);
-        buf_offset += vec_len;
-        left -= vec_len;
-    }
+    scatter(&iovs, &buf[..host_nread]);
 
     trace!("     | *nread={:?}", host_nread);
 
@@ -123,12 +114,12 @@ pub fn fd_pread(
 #[wasi_common_cbindgen]
 pub fn fd_pwrite(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
-    iovs_ptr: wasm32::uintptr_t,
-    iovs_len: wasm32::size_t,
+    iovs_ptr: GuestAddr,
+    iovs_len: GuestSize,
     offset: wasm32::__wasi_filesize_t,
-    nwritten: wasm32::uintptr_t,
+    nwritten: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "fd_pwrite(fd={:?}, iovs_ptr={:#x?}, iovs_len={:?}, offset={}, nwritten={:#x?})",
@@ -140,32 +131,27 @@ pub fn fd_pwrite(
     );
 
     let fd = dec_fd(fd);
-    let iovs = match dec_iovec_slice(memory, iovs_ptr, iovs_len) {
+    let iovs = match dec_ciovec_slice(memory, &mut GuestBorrows::new(), iovs_ptr, iovs_len) {
         Ok(iovs) => iovs,
         Err(e) => return return_enc_errno(e),
     };
-    let rights = host::__WASI_RIGHT_FD_READ;
+    let rights = host::__WASI_RIGHT_FD_WRITE | host::__WASI_RIGHT_FD_SEEK;
     let fe = match wasi_ctx.get_fd_entry(fd, rights, 0) {
         Ok(fe) => fe,
         Err(e) => return return_enc_errno(e),
     };
-    let file = match &*fe.fd_object.descriptor {
-        Descriptor::File(f) => f,
-        _ => return return_enc_errno(host::__WASI_EBADF),
-    };
 
     let offset = dec_filesize(offset);
     if offset > i64::max_value() as u64 {
         return return_enc_errno(host::__WASI_EIO);
     }
-    let buf_size = iovs.iter().map(|v| v.buf_len).sum();
-    let mut buf = Vec::with_capacity(buf_size);
-    for iov in &iovs {
-        buf.extend_from_slice(unsafe {
-            std::slice::from_raw_parts(iov.buf as *const u8, iov.buf_len)
-        });
-    }
-    let host_nwritten = match hostcalls_impl::fd_pwrite(file, &buf, offset) {
+    let buf = gather(&iovs);
+    let maybe_host_nwritten = match &*fe.fd_object.descriptor {
+        Descriptor::File(f) => hostcalls_impl::fd_pwrite(f, &buf, offset),
+        Descriptor::VirtualFile(vf) => vf.pwrite(&buf, offset),
+        _ => Err(host::__WASI_EBADF),
+    };
+    let host_nwritten = match maybe_host_nwritten {
         Ok(host_nwritten) => host_nwritten,
         Err(e) => return return_enc_errno(e),
     };
@@ -181,12 +167,12 @@ pub fn fd_pwrite(
 
 #[wasi_common_cbindgen]
 pub fn fd_read(
-    wasi_ctx: &mut WasiCtx,
-    memory: &mut [u8],
+    wasi_ctx: &WasiCtx,
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
-    iovs_ptr: wasm32::uintptr_t,
-    iovs_len: wasm32::size_t,
-    nread: wasm32::uintptr_t,
+    iovs_ptr: GuestAddr,
+    iovs_len: GuestSize,
+    nread: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "fd_read(fd={:?}, iovs_ptr={:#x?}, iovs_len={:?}, nread={:#x?})",
@@ -197,31 +183,29 @@ pub fn fd_read(
     );
 
     let fd = dec_fd(fd);
-    let mut iovs = match dec_iovec_slice(memory, iovs_ptr, iovs_len) {
+    let iovs = match dec_iovec_slice(memory, &mut GuestBorrows::new(), iovs_ptr, iovs_len) {
         Ok(iovs) => iovs,
         Err(e) => return return_enc_errno(e),
     };
-    let fe = match wasi_ctx.get_fd_entry_mut(fd, host::__WASI_RIGHT_FD_READ, 0) {
+    let fe = match wasi_ctx.get_fd_entry(fd, host::__WASI_RIGHT_FD_READ, 0) {
         Ok(fe) => fe,
         Err(e) => return return_enc_errno(e),
     };
-    let mut iovs: Vec<io::IoSliceMut> = iovs
-        .iter_mut()
-        .map(|vec| unsafe { host::iovec_to_host_mut(vec) })
-        .collect();
 
-    let maybe_host_nread = match &mut *fe.fd_object.descriptor {
-        Descriptor::File(f) => f.read_vectored(&mut iovs),
-        Descriptor::Stdin => io::stdin().lock().read_vectored(&mut iovs),
-        _ => return return_enc_errno(host::__WASI_EBADF),
+    let from_io = |err: io::Error| err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host);
+    // the buffers have been checked not to overlap
+    let maybe_host_nread = unsafe {
+        read_into_iovecs(memory, &iovs, |iovs| match &*fe.fd_object.descriptor {
+            Descriptor::File(f) => (&*f).read_vectored(iovs).map_err(from_io),
+            Descriptor::Stdin => io::stdin().lock().read_vectored(iovs).map_err(from_io),
+            Descriptor::VirtualFile(vf) => vf.read_vectored(iovs),
+            _ => Err(host::__WASI_EBADF),
+        })
     };
 
     let host_nread = match maybe_host_nread {
         Ok(host_nread) => host_nread,
-        Err(err) => {
-            let err = err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host);
-            return return_enc_errno(err);
-        }
+        Err(e) => return return_enc_errno(e),
     };
 
     trace!("     | *nread={:?}", host_nread);
@@ -235,7 +219,7 @@ pub fn fd_read(
 
 #[wasi_common_cbindgen]
 pub fn fd_renumber(
-    wasi_ctx: &mut WasiCtx,
+    wasi_ctx: &WasiCtx,
     from: wasm32::__wasi_fd_t,
     to: wasm32::__wasi_fd_t,
 ) -> wasm32::__wasi_errno_t {
@@ -244,7 +228,7 @@ pub fn fd_renumber(
     let from = dec_fd(from);
     let to = dec_fd(to);
 
-    let ret = match hostcalls_impl::fd_renumber(wasi_ctx, from, to) {
+    let ret = match wasi_ctx.renumber_fd_entry(from, to) {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -255,23 +239,33 @@ pub fn fd_renumber(
 #[wasi_common_cbindgen]
 pub fn fd_seek(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
     offset: wasm32::__wasi_filedelta_t,
     whence: wasm32::__wasi_whence_t,
-    newoffset: wasm32::uintptr_t,
+    newoffset: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "fd_seek(fd={:?}, offset={:?}, whence={}, newoffset={:#x?})",
         fd,
         offset,
-        wasm32::whence_to_str(whence),
+        match wasi_ctx.snapshot {
+            WasiSnapshot::Unstable => wasm32::whence_to_str(whence),
+            WasiSnapshot::Preview1 => wasi_snapshot_preview1::whence_to_str(whence),
+        },
         newoffset
     );
 
     let fd = dec_fd(fd);
     let offset = dec_filedelta(offset);
-    let whence = dec_whence(whence);
+    let whence = match wasi_ctx.snapshot {
+        WasiSnapshot::Unstable => dec_whence(whence),
+        WasiSnapshot::Preview1 => dec_whence_preview1(whence),
+    };
+    let whence = match whence {
+        Ok(whence) => whence,
+        Err(e) => return return_enc_errno(e),
+    };
 
     let rights = if offset == 0 && whence == host::__WASI_WHENCE_CUR {
         host::__WASI_RIGHT_FD_TELL
@@ -282,7 +276,11 @@ pub fn fd_seek(
         Ok(fe) => fe,
         Err(e) => return return_enc_errno(e),
     };
-    let host_newoffset = match hostcalls_impl::fd_seek(fe, offset, whence) {
+    let maybe_host_newoffset = match &*fe.fd_object.descriptor {
+        Descriptor::VirtualFile(vf) => vf.seek(offset, whence),
+        _ => hostcalls_impl::fd_seek(&fe, offset, whence),
+    };
+    let host_newoffset = match maybe_host_newoffset {
         Ok(host_newoffset) => host_newoffset,
         Err(e) => return return_enc_errno(e),
     };
@@ -299,9 +297,9 @@ pub fn fd_seek(
 #[wasi_common_cbindgen]
 pub fn fd_tell(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
-    newoffset: wasm32::uintptr_t,
+    newoffset: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!("fd_tell(fd={:?}, newoffset={:#x?})", fd, newoffset);
 
@@ -312,7 +310,11 @@ pub fn fd_tell(
         Ok(fe) => fe,
         Err(e) => return return_enc_errno(e),
     };
-    let host_offset = match hostcalls_impl::fd_tell(fe) {
+    let maybe_host_offset = match &*fe.fd_object.descriptor {
+        Descriptor::VirtualFile(vf) => vf.seek(0, host::__WASI_WHENCE_CUR),
+        _ => hostcalls_impl::fd_tell(&fe),
+    };
+    let host_offset = match maybe_host_offset {
         Ok(host_offset) => host_offset,
         Err(e) => return return_enc_errno(e),
     };
@@ -329,9 +331,9 @@ pub fn fd_tell(
 #[wasi_common_cbindgen]
 pub fn fd_fdstat_get(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
-    fdstat_ptr: wasm32::uintptr_t, // *mut wasm32::__wasi_fdstat_t
+    fdstat_ptr: GuestAddr, // *mut wasm32::__wasi_fdstat_t
 ) -> wasm32::__wasi_errno_t {
     trace!("fd_fdstat_get(fd={:?}, fdstat_ptr={:#x?})", fd, fdstat_ptr);
 
@@ -341,11 +343,15 @@ pub fn fd_fdstat_get(
         Err(e) => return return_enc_errno(e),
     };
 
-    let ret = if let Some(fe) = wasi_ctx.fds.get(&host_fd) {
+    let ret = if let Ok(fe) = wasi_ctx.get_fd_entry(host_fd, 0, 0) {
         host_fdstat.fs_filetype = fe.fd_object.file_type;
-        host_fdstat.fs_rights_base = fe.rights_base;
-        host_fdstat.fs_rights_inheriting = fe.rights_inheriting;
-        host_fdstat.fs_flags = match hostcalls_impl::fd_fdstat_get(fe) {
+        host_fdstat.fs_rights_base = fe.rights_base();
+        host_fdstat.fs_rights_inheriting = fe.rights_inheriting();
+        let maybe_flags = match &*fe.fd_object.descriptor {
+            Descriptor::VirtualFile(vf) => vf.fdstat_get(),
+            _ => hostcalls_impl::fd_fdstat_get(&fe),
+        };
+        host_fdstat.fs_flags = match maybe_flags {
             Ok(flags) => flags,
             Err(e) => return return_enc_errno(e),
         };
@@ -373,12 +379,16 @@ pub fn fd_fdstat_set_flags(
 
     let host_fd = dec_fd(fd);
     let host_fdflags = dec_fdflags(fdflags);
-    let ret = match wasi_ctx.fds.get(&host_fd) {
-        Some(fe) => match hostcalls_impl::fd_fdstat_set_flags(fe, host_fdflags) {
-            Ok(()) => host::__WASI_ESUCCESS,
-            Err(e) => e,
+    let res = match wasi_ctx.get_fd_entry(host_fd, 0, 0) {
+        Ok(fe) => match &*fe.fd_object.descriptor {
+            Descriptor::VirtualFile(vf) => vf.fdstat_set_flags(host_fdflags),
+            _ => hostcalls_impl::fd_fdstat_set_flags(&fe, host_fdflags),
         },
-        None => host::__WASI_EBADF,
+        Err(e) => Err(e),
+    };
+    let ret = match res {
+        Ok(()) => host::__WASI_ESUCCESS,
+        Err(e) => e,
     };
 
     return_enc_errno(ret)
@@ -386,7 +396,7 @@ pub fn fd_fdstat_set_flags(
 
 #[wasi_common_cbindgen]
 pub fn fd_fdstat_set_rights(
-    wasi_ctx: &mut WasiCtx,
+    wasi_ctx: &WasiCtx,
     fd: wasm32::__wasi_fd_t,
     fs_rights_base: wasm32::__wasi_rights_t,
     fs_rights_inheriting: wasm32::__wasi_rights_t,
@@ -399,17 +409,12 @@ pub fn fd_fdstat_set_rights(
     );
 
     let host_fd = dec_fd(fd);
-    let fe = match wasi_ctx.fds.get_mut(&host_fd) {
-        Some(fe) => fe,
-        None => return return_enc_errno(host::__WASI_EBADF),
+    // rights can only be dropped, so they can't be raised by a concurrent call either
+    let fe = match wasi_ctx.get_fd_entry(host_fd, fs_rights_base, fs_rights_inheriting) {
+        Ok(fe) => fe,
+        Err(e) => return return_enc_errno(e),
     };
-    if fe.rights_base & fs_rights_base != fs_rights_base
-        || fe.rights_inheriting & fs_rights_inheriting != fs_rights_inheriting
-    {
-        return return_enc_errno(host::__WASI_ENOTCAPABLE);
-    }
-    fe.rights_base = fs_rights_base;
-    fe.rights_inheriting = fs_rights_inheriting;
+    fe.restrict_rights(fs_rights_base, fs_rights_inheriting);
 
     return_enc_errno(host::__WASI_ESUCCESS)
 }
@@ -424,13 +429,16 @@ pub fn fd_sync(wasi_ctx: &WasiCtx, fd: wasm32::__wasi_fd_t) -> wasm32::__wasi_er
         Ok(fe) => fe,
         Err(e) => return return_enc_errno(e),
     };
-    let file = match &*fe.fd_object.descriptor {
-        Descriptor::File(f) => f,
-        _ => return return_enc_errno(host::__WASI_EBADF),
-    };
-    let ret = match file.sync_all() {
-        Ok(()) => host::__WASI_ESUCCESS,
-        Err(err) => err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host),
+    let ret = match &*fe.fd_object.descriptor {
+        Descriptor::File(f) => match f.sync_all() {
+            Ok(()) => host::__WASI_ESUCCESS,
+            Err(err) => err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host),
+        },
+        Descriptor::VirtualFile(vf) => match vf.sync() {
+            Ok(()) => host::__WASI_ESUCCESS,
+            Err(e) => e,
+        },
+        _ => host::__WASI_EBADF,
     };
 
     return_enc_errno(ret)
@@ -438,12 +446,12 @@ pub fn fd_sync(wasi_ctx: &WasiCtx, fd: wasm32::__wasi_fd_t) -> wasm32::__wasi_er
 
 #[wasi_common_cbindgen]
 pub fn fd_write(
-    wasi_ctx: &mut WasiCtx,
-    memory: &mut [u8],
+    wasi_ctx: &WasiCtx,
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
-    iovs_ptr: wasm32::uintptr_t,
-    iovs_len: wasm32::size_t,
-    nwritten: wasm32::uintptr_t,
+    iovs_ptr: GuestAddr,
+    iovs_len: GuestSize,
+    nwritten: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "fd_write(fd={:?}, iovs_ptr={:#x?}, iovs_len={:?}, nwritten={:#x?})",
@@ -454,32 +462,30 @@ pub fn fd_write(
     );
 
     let fd = dec_fd(fd);
-    let iovs = match dec_iovec_slice(memory, iovs_ptr, iovs_len) {
+    let iovs = match dec_ciovec_slice(memory, &mut GuestBorrows::new(), iovs_ptr, iovs_len) {
         Ok(iovs) => iovs,
         Err(e) => return return_enc_errno(e),
     };
-    let fe = match wasi_ctx.get_fd_entry_mut(fd, host::__WASI_RIGHT_FD_WRITE, 0) {
+    let fe = match wasi_ctx.get_fd_entry(fd, host::__WASI_RIGHT_FD_WRITE, 0) {
         Ok(fe) => fe,
         Err(e) => return return_enc_errno(e),
     };
-    let iovs: Vec<io::IoSlice> = iovs
-        .iter()
-        .map(|vec| unsafe { host::iovec_to_host(vec) })
-        .collect();
 
-    let maybe_host_nwritten = match &mut *fe.fd_object.descriptor {
-        Descriptor::File(f) => f.write_vectored(&iovs),
-        Descriptor::Stdin => return return_enc_errno(host::__WASI_EBADF),
-        Descriptor::Stdout => io::stdout().lock().write_vectored(&iovs),
-        Descriptor::Stderr => io::stderr().lock().write_vectored(&iovs),
+    let from_io = |err: io::Error| err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host);
+    // the buffers are only read from
+    let maybe_host_nwritten = unsafe {
+        write_from_ciovecs(memory, &iovs, |iovs| match &*fe.fd_object.descriptor {
+            Descriptor::File(f) => (&*f).write_vectored(iovs).map_err(from_io),
+            Descriptor::Stdin => Err(host::__WASI_EBADF),
+            Descriptor::Stdout => io::stdout().lock().write_vectored(iovs).map_err(from_io),
+            Descriptor::Stderr => io::stderr().lock().write_vectored(iovs).map_err(from_io),
+            Descriptor::VirtualFile(vf) => vf.write_vectored(iovs),
+        })
     };
 
     let host_nwritten = match maybe_host_nwritten {
         Ok(host_nwritten) => host_nwritten,
-        Err(err) => {
-            let err = err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host);
-            return return_enc_errno(err);
-        }
+        Err(e) => return return_enc_errno(e),
     };
 
     trace!("     | *nwritten={:?}", host_nwritten);
@@ -517,7 +523,11 @@ pub fn fd_advise(
     let offset = dec_filesize(offset);
     let len = dec_filesize(len);
 
-    let ret = match hostcalls_impl::fd_advise(fe, advice, offset, len) {
+    let res = match &*fe.fd_object.descriptor {
+        Descriptor::VirtualFile(vf) => vf.advise(advice, offset, len),
+        _ => hostcalls_impl::fd_advise(&fe, advice, offset, len),
+    };
+    let ret = match res {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -545,6 +555,13 @@ pub fn fd_allocate(
     };
     let f = match &*fe.fd_object.descriptor {
         Descriptor::File(f) => f,
+        Descriptor::VirtualFile(vf) => {
+            let ret = match vf.allocate(offset, len) {
+                Ok(()) => host::__WASI_ESUCCESS,
+                Err(e) => e,
+            };
+            return return_enc_errno(ret);
+        }
         _ => return return_enc_errno(host::__WASI_EBADF),
     };
 
@@ -579,10 +596,10 @@ pub fn fd_allocate(
 #[wasi_common_cbindgen]
 pub fn path_create_directory(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     dirfd: wasm32::__wasi_fd_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: GuestAddr,
+    path_len: GuestSize,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "path_create_directory(dirfd={:?}, path_ptr={:#x?}, path_len={})",
@@ -592,15 +609,19 @@ pub fn path_create_directory(
     );
 
     let dirfd = dec_fd(dirfd);
-    let path = match dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)
-    {
+    let path = match dec_path(memory, path_ptr, path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
 
     trace!("     | (path_ptr,path_len)='{}'", path);
 
-    let ret = match hostcalls_impl::path_create_directory(wasi_ctx, dirfd, path) {
+    let res = if virtfs::is_virtual(wasi_ctx, dirfd) {
+        virtfs::path_create_directory(wasi_ctx, dirfd, &path)
+    } else {
+        hostcalls_impl::path_create_directory(wasi_ctx, dirfd, &path)
+    };
+    let ret = match res {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -611,14 +632,14 @@ pub fn path_create_directory(
 #[wasi_common_cbindgen]
 pub fn path_link(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     old_dirfd: wasm32::__wasi_fd_t,
     old_flags: wasm32::__wasi_lookupflags_t,
-    old_path_ptr: wasm32::uintptr_t,
-    old_path_len: wasm32::size_t,
+    old_path_ptr: GuestAddr,
+    old_path_len: GuestSize,
     new_dirfd: wasm32::__wasi_fd_t,
-    new_path_ptr: wasm32::uintptr_t,
-    new_path_len: wasm32::size_t,
+    new_path_ptr: GuestAddr,
+    new_path_len: GuestSize,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "path_link(old_dirfd={:?}, old_flags={:?}, old_path_ptr={:#x?}, old_path_len={}, new_dirfd={:?}, new_path_ptr={:#x?}, new_path_len={})",
@@ -633,15 +654,11 @@ pub fn path_link(
 
     let old_dirfd = dec_fd(old_dirfd);
     let new_dirfd = dec_fd(new_dirfd);
-    let old_path = match dec_slice_of::<u8>(memory, old_path_ptr, old_path_len)
-        .and_then(host::path_from_slice)
-    {
+    let old_path = match dec_path(memory, old_path_ptr, old_path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
-    let new_path = match dec_slice_of::<u8>(memory, new_path_ptr, new_path_len)
-        .and_then(host::path_from_slice)
-    {
+    let new_path = match dec_path(memory, new_path_ptr, new_path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
@@ -649,15 +666,32 @@ pub fn path_link(
     trace!("     | (old_path_ptr,old_path_len)='{}'", old_path);
     trace!("     | (new_path_ptr,new_path_len)='{}'", new_path);
 
-    let ret = match hostcalls_impl::path_link(
-        wasi_ctx,
-        old_dirfd,
-        new_dirfd,
-        old_path,
-        new_path,
-        host::__WASI_RIGHT_PATH_LINK_SOURCE,
-        host::__WASI_RIGHT_PATH_LINK_TARGET,
-    ) {
+    let source_rights = host::__WASI_RIGHT_PATH_LINK_SOURCE;
+    let target_rights = host::__WASI_RIGHT_PATH_LINK_TARGET;
+
+    let res = if virtfs::is_virtual(wasi_ctx, old_dirfd) || virtfs::is_virtual(wasi_ctx, new_dirfd)
+    {
+        virtfs::path_link(
+            wasi_ctx,
+            old_dirfd,
+            new_dirfd,
+            &old_path,
+            &new_path,
+            source_rights,
+            target_rights,
+        )
+    } else {
+        hostcalls_impl::path_link(
+            wasi_ctx,
+            old_dirfd,
+            new_dirfd,
+            &old_path,
+            &new_path,
+            source_rights,
+            target_rights,
+        )
+    };
+    let ret = match res {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -667,17 +701,17 @@ pub fn path_link(
 
 #[wasi_common_cbindgen]
 pub fn path_open(
-    wasi_ctx: &mut WasiCtx,
-    memory: &mut [u8],
+    wasi_ctx: &WasiCtx,
+    memory: &dyn GuestMemory,
     dirfd: wasm32::__wasi_fd_t,
     dirflags: wasm32::__wasi_lookupflags_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: GuestAddr,
+    path_len: GuestSize,
     oflags: wasm32::__wasi_oflags_t,
     fs_rights_base: wasm32::__wasi_rights_t,
     fs_rights_inheriting: wasm32::__wasi_rights_t,
     fs_flags: wasm32::__wasi_fdflags_t,
-    fd_out_ptr: wasm32::uintptr_t,
+    fd_out_ptr: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "path_open(dirfd={:?}, dirflags={:?}, path_ptr={:#x?}, path_len={:?}, oflags={:#x?}, fs_rights_base={:#x?}, fs_rights_inheriting={:#x?}, fs_flags={:#x?}, fd_out_ptr={:#x?})",
@@ -712,27 +746,45 @@ pub fn path_open(
     let needed_base = host::__WASI_RIGHT_PATH_OPEN;
     let needed_inheriting = fs_rights_base | fs_rights_inheriting;
 
-    let path = match dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)
-    {
+    let path = match dec_path(memory, path_ptr, path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
 
     trace!("     | (path_ptr,path_len)='{}'", path);
 
-    let ret = match hostcalls_impl::path_open(
-        wasi_ctx,
-        dirfd,
-        dirflags,
-        path,
-        oflags,
-        read,
-        write,
-        needed_base,
-        needed_inheriting,
-        fs_flags,
-    ) {
+    let res = if virtfs::is_virtual(wasi_ctx, dirfd) {
+        virtfs::path_open(
+            wasi_ctx,
+            dirfd,
+            dirflags,
+            &path,
+            oflags,
+            read,
+            write,
+            needed_base,
+            needed_inheriting,
+            fs_flags,
+        )
+    } else {
+        hostcalls_impl::path_open(
+            wasi_ctx,
+            dirfd,
+            dirflags,
+            &path,
+            oflags,
+            read,
+            write,
+            needed_base,
+            needed_inheriting,
+            fs_flags,
+        )
+    };
+    let ret = match res {
         Ok(fe) => {
+            // the new descriptor gets no more than the rights which were asked for
+            fe.restrict_rights(fs_rights_base, fs_rights_inheriting);
+
             let guest_fd = match wasi_ctx.insert_fd_entry(fe) {
                 Ok(fd) => fd,
                 Err(e) => return return_enc_errno(e),
@@ -759,12 +811,12 @@ pub fn path_open(
 #[wasi_common_cbindgen]
 pub fn fd_readdir(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
-    buf: wasm32::uintptr_t,
-    buf_len: wasm32::size_t,
+    buf: GuestAddr,
+    buf_len: GuestSize,
     cookie: wasm32::__wasi_dircookie_t,
-    buf_used: wasm32::uintptr_t,
+    buf_used: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "fd_readdir(fd={:?}, buf={:#x?}, buf_len={}, cookie={:#x?}, buf_used={:#x?})",
@@ -785,7 +837,13 @@ pub fn fd_readdir(
         Ok(fe) => fe,
         Err(e) => return return_enc_errno(e),
     };
-    let host_buf = match dec_slice_of_mut::<u8>(memory, buf, buf_len) {
+    let host_buf = match dec_slice_of::<u8>(memory, buf, buf_len).and_then(|host_buf| {
+        // the buffer is written in place, so it mustn't alias `buf_used`
+        let mut borrows = GuestBorrows::new();
+        borrow_usize_mut(memory, &mut borrows, buf_used)?;
+        borrows.borrow_mut(&host_buf)?;
+        Ok(host_buf)
+    }) {
         Ok(host_buf) => host_buf,
         Err(e) => return return_enc_errno(e),
     };
@@ -794,7 +852,17 @@ pub fn fd_readdir(
 
     let cookie = dec_dircookie(cookie);
 
-    let host_bufused = match hostcalls_impl::fd_readdir(fe, host_buf, cookie) {
+    // nothing else accesses guest memory until the host is done with the buffer
+    let maybe_host_bufused = unsafe {
+        host_buf.fill_with(|host_buf| match &*fe.fd_object.descriptor {
+            Descriptor::VirtualFile(vf) => virtfs::fd_readdir(&**vf, host_buf, cookie),
+            _ if wasi_ctx.sorted_readdir => {
+                hostcalls_impl::fd_readdir_sorted(&fe, host_buf, cookie)
+            }
+            _ => hostcalls_impl::fd_readdir(&fe, host_buf, cookie),
+        })
+    };
+    let host_bufused = match maybe_host_bufused {
         Ok(host_bufused) => host_bufused,
         Err(e) => return return_enc_errno(e),
     };
@@ -811,13 +879,13 @@ pub fn fd_readdir(
 #[wasi_common_cbindgen]
 pub fn path_readlink(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     dirfd: wasm32::__wasi_fd_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
-    buf_ptr: wasm32::uintptr_t,
-    buf_len: wasm32::size_t,
-    buf_used: wasm32::uintptr_t,
+    path_ptr: GuestAddr,
+    path_len: GuestSize,
+    buf_ptr: GuestAddr,
+    buf_len: GuestSize,
+    buf_used: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "path_readlink(dirfd={:?}, path_ptr={:#x?}, path_len={:?}, buf_ptr={:#x?}, buf_len={}, buf_used={:#x?})",
@@ -834,28 +902,43 @@ pub fn path_readlink(
         Err(e) => return return_enc_errno(e),
     };
     let dirfd = dec_fd(dirfd);
-    let path = match dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_vec) {
+    let path = match dec_path(memory, path_ptr, path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
 
     trace!("     | (path_ptr,path_len)='{}'", &path);
 
-    let mut buf = match dec_slice_of_mut::<u8>(memory, buf_ptr, buf_len) {
+    let buf = match dec_slice_of::<u8>(memory, buf_ptr, buf_len).and_then(|buf| {
+        // the buffer is written in place, so it mustn't alias the path or `buf_used`
+        let mut borrows = GuestBorrows::new();
+        borrows.borrow(&dec_slice_of::<u8>(memory, path_ptr, path_len)?)?;
+        borrow_usize_mut(memory, &mut borrows, buf_used)?;
+        borrows.borrow_mut(&buf)?;
+        Ok(buf)
+    }) {
         Ok(slice) => slice,
         Err(e) => return return_enc_errno(e),
     };
-    let host_bufused = match hostcalls_impl::path_readlink(
-        wasi_ctx,
-        dirfd,
-        &path,
-        host::__WASI_RIGHT_PATH_READLINK,
-        &mut buf,
-    ) {
+    let rights = host::__WASI_RIGHT_PATH_READLINK;
+    // nothing else accesses guest memory until the host is done with the buffer
+    let maybe_host_bufused = unsafe {
+        buf.fill_with(|host_buf| {
+            if virtfs::is_virtual(wasi_ctx, dirfd) {
+                virtfs::path_readlink(wasi_ctx, dirfd, &path, rights, host_buf)
+            } else {
+                hostcalls_impl::path_readlink(wasi_ctx, dirfd, &path, rights, host_buf)
+            }
+        })
+    };
+    let host_bufused = match maybe_host_bufused {
         Ok(host_bufused) => host_bufused,
         Err(e) => return return_enc_errno(e),
     };
-    trace!("     | (buf_ptr,*buf_used)={:?}", buf);
+    trace!(
+        "     | (buf_ptr,*buf_used)={:?}",
+        buf.split_at(host_bufused).0.to_vec()
+    );
     trace!("     | *buf_used={:?}", host_bufused);
 
     let ret = match enc_usize_byref(memory, buf_used, host_bufused) {
@@ -869,13 +952,13 @@ pub fn path_readlink(
 #[wasi_common_cbindgen]
 pub fn path_rename(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     old_dirfd: wasm32::__wasi_fd_t,
-    old_path_ptr: wasm32::uintptr_t,
-    old_path_len: wasm32::size_t,
+    old_path_ptr: GuestAddr,
+    old_path_len: GuestSize,
     new_dirfd: wasm32::__wasi_fd_t,
-    new_path_ptr: wasm32::uintptr_t,
-    new_path_len: wasm32::size_t,
+    new_path_ptr: GuestAddr,
+    new_path_len: GuestSize,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "path_rename(old_dirfd={:?}, old_path_ptr={:#x?}, old_path_len={:?}, new_dirfd={:?}, new_path_ptr={:#x?}, new_path_len={:?})",
@@ -889,15 +972,11 @@ pub fn path_rename(
 
     let old_dirfd = dec_fd(old_dirfd);
     let new_dirfd = dec_fd(new_dirfd);
-    let old_path = match dec_slice_of::<u8>(memory, old_path_ptr, old_path_len)
-        .and_then(host::path_from_slice)
-    {
+    let old_path = match dec_path(memory, old_path_ptr, old_path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
-    let new_path = match dec_slice_of::<u8>(memory, new_path_ptr, new_path_len)
-        .and_then(host::path_from_slice)
-    {
+    let new_path = match dec_path(memory, new_path_ptr, new_path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
@@ -908,9 +987,17 @@ pub fn path_rename(
     let old_rights = host::__WASI_RIGHT_PATH_RENAME_SOURCE;
     let new_rights = host::__WASI_RIGHT_PATH_RENAME_TARGET;
 
-    let ret = match hostcalls_impl::path_rename(
-        wasi_ctx, old_dirfd, old_path, old_rights, new_dirfd, new_path, new_rights,
-    ) {
+    let res = if virtfs::is_virtual(wasi_ctx, old_dirfd) || virtfs::is_virtual(wasi_ctx, new_dirfd)
+    {
+        virtfs::path_rename(
+            wasi_ctx, old_dirfd, &old_path, old_rights, new_dirfd, &new_path, new_rights,
+        )
+    } else {
+        hostcalls_impl::path_rename(
+            wasi_ctx, old_dirfd, &old_path, old_rights, new_dirfd, &new_path, new_rights,
+        )
+    };
+    let ret = match res {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -921,9 +1008,9 @@ pub fn path_rename(
 #[wasi_common_cbindgen]
 pub fn fd_filestat_get(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
-    filestat_ptr: wasm32::uintptr_t,
+    filestat_ptr: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "fd_filestat_get(fd={:?}, filestat_ptr={:#x?})",
@@ -932,19 +1019,24 @@ pub fn fd_filestat_get(
     );
 
     let host_fd = dec_fd(fd);
-    let fe = match wasi_ctx.fds.get(&host_fd) {
-        Some(fe) => fe,
-        None => return return_enc_errno(host::__WASI_EBADF),
+    let fe = match wasi_ctx.get_fd_entry(host_fd, 0, 0) {
+        Ok(fe) => fe,
+        Err(e) => return return_enc_errno(e),
     };
 
-    let host_filestat = match hostcalls_impl::fd_filestat_get(fe) {
+    let maybe_host_filestat = match &*fe.fd_object.descriptor {
+        Descriptor::VirtualFile(vf) => vf.filestat_get(),
+        _ => hostcalls_impl::fd_filestat_get(&fe),
+    };
+    let host_filestat = match maybe_host_filestat {
         Ok(fstat) => fstat,
         Err(e) => return return_enc_errno(e),
     };
+    let host_filestat = quantize_filestat(wasi_ctx, host_filestat);
 
     trace!("     | *filestat_ptr={:?}", host_filestat);
 
-    let ret = match enc_filestat_byref(memory, filestat_ptr, host_filestat) {
+    let ret = match enc_filestat_byref(memory, wasi_ctx.snapshot, filestat_ptr, host_filestat) {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -978,7 +1070,11 @@ pub fn fd_filestat_set_times(
     let st_mtim = dec_timestamp(st_mtim);
     let fst_flags = dec_fstflags(fst_flags);
 
-    let ret = match hostcalls_impl::fd_filestat_set_times(fe, st_atim, st_mtim, fst_flags) {
+    let res = match &*fe.fd_object.descriptor {
+        Descriptor::VirtualFile(vf) => vf.filestat_set_times(st_atim, st_mtim, fst_flags),
+        _ => hostcalls_impl::fd_filestat_set_times(&fe, st_atim, st_mtim, fst_flags),
+    };
+    let ret = match res {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -1005,7 +1101,11 @@ pub fn fd_filestat_set_size(
         return return_enc_errno(host::__WASI_E2BIG);
     }
 
-    let ret = match hostcalls_impl::fd_filestat_set_size(fe, st_size) {
+    let res = match &*fe.fd_object.descriptor {
+        Descriptor::VirtualFile(vf) => vf.filestat_set_size(st_size),
+        _ => hostcalls_impl::fd_filestat_set_size(&fe, st_size),
+    };
+    let ret = match res {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -1016,12 +1116,12 @@ pub fn fd_filestat_set_size(
 #[wasi_common_cbindgen]
 pub fn path_filestat_get(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     dirfd: wasm32::__wasi_fd_t,
     dirflags: wasm32::__wasi_lookupflags_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
-    filestat_ptr: wasm32::uintptr_t,
+    path_ptr: GuestAddr,
+    path_len: GuestSize,
+    filestat_ptr: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "path_filestat_get(dirfd={:?}, dirflags={:?}, path_ptr={:#x?}, path_len={}, filestat_ptr={:#x?})",
@@ -1034,22 +1134,27 @@ pub fn path_filestat_get(
 
     let dirfd = dec_fd(dirfd);
     let dirflags = dec_lookupflags(dirflags);
-    let path = match dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)
-    {
+    let path = match dec_path(memory, path_ptr, path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
 
     trace!("     | (path_ptr,path_len)='{}'", path);
 
-    let host_filestat = match hostcalls_impl::path_filestat_get(wasi_ctx, dirfd, dirflags, path) {
+    let maybe_host_filestat = if virtfs::is_virtual(wasi_ctx, dirfd) {
+        virtfs::path_filestat_get(wasi_ctx, dirfd, dirflags, &path)
+    } else {
+        hostcalls_impl::path_filestat_get(wasi_ctx, dirfd, dirflags, &path)
+    };
+    let host_filestat = match maybe_host_filestat {
         Ok(host_filestat) => host_filestat,
         Err(e) => return return_enc_errno(e),
     };
+    let host_filestat = quantize_filestat(wasi_ctx, host_filestat);
 
     trace!("     | *filestat_ptr={:?}", host_filestat);
 
-    let ret = match enc_filestat_byref(memory, filestat_ptr, host_filestat) {
+    let ret = match enc_filestat_byref(memory, wasi_ctx.snapshot, filestat_ptr, host_filestat) {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -1060,11 +1165,11 @@ pub fn path_filestat_get(
 #[wasi_common_cbindgen]
 pub fn path_filestat_set_times(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     dirfd: wasm32::__wasi_fd_t,
     dirflags: wasm32::__wasi_lookupflags_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: GuestAddr,
+    path_len: GuestSize,
     st_atim: wasm32::__wasi_timestamp_t,
     st_mtim: wasm32::__wasi_timestamp_t,
     fst_flags: wasm32::__wasi_fstflags_t,
@@ -1081,8 +1186,7 @@ pub fn path_filestat_set_times(
 
     let dirfd = dec_fd(dirfd);
     let dirflags = dec_lookupflags(dirflags);
-    let path = match dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)
-    {
+    let path = match dec_path(memory, path_ptr, path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
@@ -1094,9 +1198,16 @@ pub fn path_filestat_set_times(
     let st_mtim = dec_timestamp(st_mtim);
     let fst_flags = dec_fstflags(fst_flags);
 
-    let ret = match hostcalls_impl::path_filestat_set_times(
-        wasi_ctx, dirfd, dirflags, path, rights, st_atim, st_mtim, fst_flags,
-    ) {
+    let res = if virtfs::is_virtual(wasi_ctx, dirfd) {
+        virtfs::path_filestat_set_times(
+            wasi_ctx, dirfd, dirflags, &path, rights, st_atim, st_mtim, fst_flags,
+        )
+    } else {
+        hostcalls_impl::path_filestat_set_times(
+            wasi_ctx, dirfd, dirflags, &path, rights, st_atim, st_mtim, fst_flags,
+        )
+    };
+    let ret = match res {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -1107,12 +1218,12 @@ pub fn path_filestat_set_times(
 #[wasi_common_cbindgen]
 pub fn path_symlink(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
-    old_path_ptr: wasm32::uintptr_t,
-    old_path_len: wasm32::size_t,
+    memory: &dyn GuestMemory,
+    old_path_ptr: GuestAddr,
+    old_path_len: GuestSize,
     dirfd: wasm32::__wasi_fd_t,
-    new_path_ptr: wasm32::uintptr_t,
-    new_path_len: wasm32::size_t,
+    new_path_ptr: GuestAddr,
+    new_path_len: GuestSize,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "path_symlink(old_path_ptr={:#x?}, old_path_len={}, dirfd={:?}, new_path_ptr={:#x?}, new_path_len={})",
@@ -1124,15 +1235,11 @@ pub fn path_symlink(
     );
 
     let dirfd = dec_fd(dirfd);
-    let old_path = match dec_slice_of::<u8>(memory, old_path_ptr, old_path_len)
-        .and_then(host::path_from_slice)
-    {
+    let old_path = match dec_path(memory, old_path_ptr, old_path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
-    let new_path = match dec_slice_of::<u8>(memory, new_path_ptr, new_path_len)
-        .and_then(host::path_from_slice)
-    {
+    let new_path = match dec_path(memory, new_path_ptr, new_path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
@@ -1142,7 +1249,12 @@ pub fn path_symlink(
 
     let rights = host::__WASI_RIGHT_PATH_SYMLINK;
 
-    let ret = match hostcalls_impl::path_symlink(wasi_ctx, dirfd, rights, old_path, new_path) {
+    let res = if virtfs::is_virtual(wasi_ctx, dirfd) {
+        virtfs::path_symlink(wasi_ctx, dirfd, rights, &old_path, &new_path)
+    } else {
+        hostcalls_impl::path_symlink(wasi_ctx, dirfd, rights, &old_path, &new_path)
+    };
+    let ret = match res {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -1153,10 +1265,10 @@ pub fn path_symlink(
 #[wasi_common_cbindgen]
 pub fn path_unlink_file(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     dirfd: wasm32::__wasi_fd_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: GuestAddr,
+    path_len: GuestSize,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "path_unlink_file(dirfd={:?}, path_ptr={:#x?}, path_len={})",
@@ -1166,20 +1278,21 @@ pub fn path_unlink_file(
     );
 
     let dirfd = dec_fd(dirfd);
-    let path = match dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)
-    {
+    let path = match dec_path(memory, path_ptr, path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
 
     trace!("     | (path_ptr,path_len)='{}'", path);
 
-    let ret = match hostcalls_impl::path_unlink_file(
-        wasi_ctx,
-        dirfd,
-        path,
-        host::__WASI_RIGHT_PATH_UNLINK_FILE,
-    ) {
+    let rights = host::__WASI_RIGHT_PATH_UNLINK_FILE;
+
+    let res = if virtfs::is_virtual(wasi_ctx, dirfd) {
+        virtfs::path_unlink_file(wasi_ctx, dirfd, &path, rights)
+    } else {
+        hostcalls_impl::path_unlink_file(wasi_ctx, dirfd, &path, rights)
+    };
+    let ret = match res {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -1190,10 +1303,10 @@ pub fn path_unlink_file(
 #[wasi_common_cbindgen]
 pub fn path_remove_directory(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     dirfd: wasm32::__wasi_fd_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: GuestAddr,
+    path_len: GuestSize,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "path_remove_directory(dirfd={:?}, path_ptr={:#x?}, path_len={})",
@@ -1203,8 +1316,7 @@ pub fn path_remove_directory(
     );
 
     let dirfd = dec_fd(dirfd);
-    let path = match dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)
-    {
+    let path = match dec_path(memory, path_ptr, path_len) {
         Ok(path) => path,
         Err(e) => return return_enc_errno(e),
     };
@@ -1213,7 +1325,12 @@ pub fn path_remove_directory(
 
     let rights = host::__WASI_RIGHT_PATH_REMOVE_DIRECTORY;
 
-    let ret = match hostcalls_impl::path_remove_directory(wasi_ctx, dirfd, path, rights) {
+    let res = if virtfs::is_virtual(wasi_ctx, dirfd) {
+        virtfs::path_remove_directory(wasi_ctx, dirfd, &path, rights)
+    } else {
+        hostcalls_impl::path_remove_directory(wasi_ctx, dirfd, &path, rights)
+    };
+    let ret = match res {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -1224,9 +1341,9 @@ pub fn path_remove_directory(
 #[wasi_common_cbindgen]
 pub fn fd_prestat_get(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
-    prestat_ptr: wasm32::uintptr_t,
+    prestat_ptr: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "fd_prestat_get(fd={:?}, prestat_ptr={:#x?})",
@@ -1253,8 +1370,8 @@ pub fn fd_prestat_get(
                     prestat_ptr,
                     host::__wasi_prestat_t {
                         pr_type: host::__WASI_PREOPENTYPE_DIR,
-                        u: host::__wasi_prestat_t___wasi_prestat_u {
-                            dir: host::__wasi_prestat_t___wasi_prestat_u___wasi_prestat_u_dir_t {
+                        u: host::__wasi_prestat_u_t {
+                            dir: host::__wasi_prestat_dir_t {
                                 pr_name_len: path.len(),
                             },
                         },
@@ -1275,10 +1392,10 @@ pub fn fd_prestat_get(
 #[wasi_common_cbindgen]
 pub fn fd_prestat_dir_name(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     fd: wasm32::__wasi_fd_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: GuestAddr,
+    path_len: GuestSize,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "fd_prestat_dir_name(fd={:?}, path_ptr={:#x?}, path_len={})",
@@ -1301,7 +1418,7 @@ pub fn fd_prestat_dir_name(
                     Err(e) => return return_enc_errno(e),
                 };
 
-                if path.len() > dec_usize(path_len) {
+                if path.len() as GuestSize > path_len {
                     return return_enc_errno(host::__WASI_ENAMETOOLONG);
                 }
 
@@ -1319,3 +1436,198 @@ pub fn fd_prestat_dir_name(
 
     return_enc_errno(ret)
 }
+
+/// Coarsens file timestamps like the clocks, when the context asks for it; otherwise the guest
+/// could touch a file and read back its modification time as a high-resolution clock.
+fn quantize_filestat(
+    wasi_ctx: &WasiCtx,
+    mut filestat: host::__wasi_filestat_t,
+) -> host::__wasi_filestat_t {
+    if let Some(quantum) = wasi_ctx.clock_quantum {
+        filestat.st_atim = quantum.apply(filestat.st_atim);
+        filestat.st_mtim = quantum.apply(filestat.st_mtim);
+        filestat.st_ctim = quantum.apply(filestat.st_ctim);
+    }
+    filestat
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::memory_with_iovec;
+    use crate::virtfs::MemFs;
+    use crate::{PreopenRights, SliceMemory, WasiCtxBuilder};
+    use std::fs::File;
+
+    fn open(
+        ctx: &WasiCtx,
+        path: &[u8],
+        oflags: host::__wasi_oflags_t,
+        rights: host::__wasi_rights_t,
+    ) -> host::__wasi_errno_t {
+        let mut mem = vec![0u8; 64];
+        mem[16..16 + path.len()].copy_from_slice(path);
+        let memory = SliceMemory::new(&mut mem);
+        path_open(
+            ctx,
+            &memory,
+            3,
+            0,
+            16,
+            path.len() as GuestSize,
+            oflags,
+            rights,
+            0,
+            0,
+            8,
+        )
+    }
+
+    #[test]
+    fn read_only_preopens() {
+        let memfs = MemFs::new();
+        memfs.write_file("file", b"contents").unwrap();
+        let dir =
+            std::env::temp_dir().join(format!("wasi-common-read-only-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("file"), b"contents").unwrap();
+        let builders = vec![
+            WasiCtxBuilder::new().unwrap().preopened_memfs(
+                "/memfs",
+                memfs.clone(),
+                PreopenRights::ReadOnly,
+            ),
+            WasiCtxBuilder::new().unwrap().preopened_dir_with_rights(
+                File::open(&dir).unwrap(),
+                "/dir",
+                PreopenRights::ReadOnly,
+            ),
+        ];
+        for builder in builders {
+            let ctx = builder.build().unwrap();
+            assert_eq!(
+                open(&ctx, b"file", 0, host::__WASI_RIGHT_FD_READ),
+                host::__WASI_ESUCCESS
+            );
+            assert_eq!(
+                open(&ctx, b"file", 0, host::__WASI_RIGHT_FD_WRITE),
+                host::__WASI_ENOTCAPABLE
+            );
+            assert_eq!(
+                open(
+                    &ctx,
+                    b"new",
+                    host::__WASI_O_CREAT,
+                    host::__WASI_RIGHT_FD_READ
+                ),
+                host::__WASI_ENOTCAPABLE
+            );
+            let mut mem = b"file".to_vec();
+            let errno = path_unlink_file(&ctx, &SliceMemory::new(&mut mem), 3, 0, 4);
+            assert_eq!(errno, host::__WASI_ENOTCAPABLE);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(memfs.read_dir("/").unwrap(), vec!["file"]);
+    }
+
+    #[test]
+    fn read_only_preopen_rejects_writes() {
+        let memfs = MemFs::new();
+        memfs.write_file("file", b"contents").unwrap();
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .preopened_memfs("/memfs", memfs.clone(), PreopenRights::ReadOnly)
+            .build()
+            .unwrap();
+        let mut mem = memory_with_iovec(b"EVIL", 4);
+        mem[48..52].copy_from_slice(b"file");
+        let rights = host::__WASI_RIGHT_FD_READ | host::__WASI_RIGHT_FD_SEEK;
+        let errno = path_open(
+            &ctx,
+            &SliceMemory::new(&mut mem),
+            3,
+            0,
+            48,
+            4,
+            0,
+            rights,
+            0,
+            0,
+            8,
+        );
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        let fd = u32::from_le_bytes([mem[8], mem[9], mem[10], mem[11]]);
+
+        let memory = SliceMemory::new(&mut mem);
+        let errno = fd_write(&ctx, &memory, fd, 0, 1, 8);
+        assert_eq!(errno, host::__WASI_ENOTCAPABLE);
+        let errno = fd_pwrite(&ctx, &memory, fd, 0, 1, 0, 8);
+        assert_eq!(errno, host::__WASI_ENOTCAPABLE);
+        let errno = fd_filestat_set_size(&ctx, fd, 0);
+        assert_eq!(errno, host::__WASI_ENOTCAPABLE);
+        let errno = fd_allocate(&ctx, fd, 0, 16);
+        assert_eq!(errno, host::__WASI_ENOTCAPABLE);
+        assert_eq!(memfs.read_file("file").unwrap(), b"contents");
+    }
+
+    #[test]
+    fn custom_preopen_rights() {
+        let rights = PreopenRights::Custom {
+            base: host::__WASI_RIGHT_PATH_OPEN,
+            inheriting: host::__WASI_RIGHT_FD_READ,
+        };
+        let memfs = MemFs::new();
+        memfs.write_file("file", b"contents").unwrap();
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .preopened_memfs("/memfs", memfs, rights)
+            .build()
+            .unwrap();
+        assert_eq!(
+            open(&ctx, b"file", 0, host::__WASI_RIGHT_FD_READ),
+            host::__WASI_ESUCCESS
+        );
+        assert_eq!(
+            open(&ctx, b"file", 0, host::__WASI_RIGHT_FD_SEEK),
+            host::__WASI_ENOTCAPABLE
+        );
+    }
+
+    #[test]
+    fn deterministic_readdir() {
+        let dir = std::env::temp_dir().join(format!("wasi-common-readdir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in &["zeta", "alpha", "mid", "beta"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .deterministic(0)
+            .preopened_dir(File::open(&dir).unwrap(), "/dir")
+            .build()
+            .unwrap();
+        let mut mem = vec![0u8; 1024];
+        let errno = fd_readdir(&ctx, &SliceMemory::new(&mut mem), 3, 16, 1000, 0, 8);
+        std::fs::remove_dir_all(&dir).unwrap();
+        if cfg!(windows) {
+            assert_eq!(errno, host::__WASI_ENOTSUP);
+            return;
+        }
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        let used = u32::from_le_bytes([mem[8], mem[9], mem[10], mem[11]]) as usize;
+        let mut names = Vec::new();
+        let mut offset = 16;
+        while offset < 16 + used {
+            // each dirent is followed by its name
+            let len = u32::from_le_bytes([
+                mem[offset + 16],
+                mem[offset + 17],
+                mem[offset + 18],
+                mem[offset + 19],
+            ]) as usize;
+            names.push(String::from_utf8(mem[offset + 24..offset + 24 + len].to_vec()).unwrap());
+            offset += 24 + len;
+        }
+        assert_eq!(names, vec![".", "..", "alpha", "beta", "mid", "zeta"]);
+    }
+}
@@ -1,20 +1,28 @@
 #![allow(non_camel_case_types)]
 use super::return_enc_errno;
 use crate::ctx::WasiCtx;
+use crate::fdentry::{Descriptor, FdEntry};
+use crate::guest_memory::{GuestAddr, GuestMemory, GuestSize};
 use crate::memory::*;
 use crate::sys::hostcalls_impl;
+use crate::virtfs::VirtualFile;
 use crate::{host, wasm32};
 use log::trace;
+use std::cmp;
 use std::convert::{identity, TryFrom};
+use std::time::Duration;
 
 use wasi_common_cbindgen::wasi_common_cbindgen;
 
+/// How often `poll_oneoff` checks the virtual files which aren't ready yet, in nanoseconds.
+const VIRTUAL_FILE_POLL_INTERVAL: host::__wasi_timestamp_t = 1_000_000;
+
 #[wasi_common_cbindgen]
 pub fn args_get(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
-    argv_ptr: wasm32::uintptr_t,
-    argv_buf: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    argv_ptr: GuestAddr,
+    argv_buf: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "args_get(argv_ptr={:#x?}, argv_buf={:#x?})",
@@ -36,7 +44,7 @@ pub fn args_get(
         argv.push(arg_ptr);
 
         argv_buf_offset = if let Some(new_offset) =
-            argv_buf_offset.checked_add(match wasm32::uintptr_t::try_from(arg_bytes.len()) {
+            argv_buf_offset.checked_add(match GuestAddr::try_from(arg_bytes.len()) {
                 Ok(len) => len,
                 Err(_) => return return_enc_errno(host::__WASI_EOVERFLOW),
             }) {
@@ -46,7 +54,7 @@ pub fn args_get(
         }
     }
 
-    let ret = enc_slice_of(memory, argv.as_slice(), argv_ptr)
+    let ret = enc_ptr_slice(memory, &argv, argv_ptr)
         .map(|_| host::__WASI_ESUCCESS)
         .unwrap_or_else(identity);
 
@@ -56,9 +64,9 @@ pub fn args_get(
 #[wasi_common_cbindgen]
 pub fn args_sizes_get(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
-    argc_ptr: wasm32::uintptr_t,
-    argv_buf_size_ptr: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    argc_ptr: GuestAddr,
+    argv_buf_size_ptr: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "args_sizes_get(argc_ptr={:#x?}, argv_buf_size_ptr={:#x?})",
@@ -91,9 +99,9 @@ pub fn args_sizes_get(
 #[wasi_common_cbindgen]
 pub fn environ_get(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
-    environ_ptr: wasm32::uintptr_t,
-    environ_buf: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    environ_ptr: GuestAddr,
+    environ_buf: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "environ_get(environ_ptr={:#x?}, environ_buf={:#x?})",
@@ -115,7 +123,7 @@ pub fn environ_get(
         environ.push(env_ptr);
 
         environ_buf_offset = if let Some(new_offset) =
-            environ_buf_offset.checked_add(match wasm32::uintptr_t::try_from(env_bytes.len()) {
+            environ_buf_offset.checked_add(match GuestAddr::try_from(env_bytes.len()) {
                 Ok(len) => len,
                 Err(_) => return return_enc_errno(host::__WASI_EOVERFLOW),
             }) {
@@ -125,7 +133,7 @@ pub fn environ_get(
         }
     }
 
-    let ret = enc_slice_of(memory, environ.as_slice(), environ_ptr)
+    let ret = enc_ptr_slice(memory, &environ, environ_ptr)
         .map(|_| host::__WASI_ESUCCESS)
         .unwrap_or_else(identity);
 
@@ -135,9 +143,9 @@ pub fn environ_get(
 #[wasi_common_cbindgen]
 pub fn environ_sizes_get(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
-    environ_count_ptr: wasm32::uintptr_t,
-    environ_size_ptr: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    environ_count_ptr: GuestAddr,
+    environ_size_ptr: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "environ_sizes_get(environ_count_ptr={:#x?}, environ_size_ptr={:#x?})",
@@ -146,8 +154,8 @@ pub fn environ_sizes_get(
     );
 
     let environ_count = wasi_ctx.env.len();
-    let ret = if let Some(environ_size) = wasi_ctx.env.iter().try_fold(0, |acc: u32, pair| {
-        acc.checked_add(pair.as_bytes_with_nul().len() as u32)
+    let ret = if let Some(environ_size) = wasi_ctx.env.iter().try_fold(0, |acc: usize, pair| {
+        acc.checked_add(pair.as_bytes_with_nul().len())
     }) {
         trace!("     | *environ_count_ptr={:?}", environ_count);
 
@@ -157,7 +165,7 @@ pub fn environ_sizes_get(
 
         trace!("     | *environ_size_ptr={:?}", environ_size);
 
-        if let Err(e) = enc_usize_byref(memory, environ_size_ptr, environ_size as usize) {
+        if let Err(e) = enc_usize_byref(memory, environ_size_ptr, environ_size) {
             return return_enc_errno(e);
         }
 
@@ -169,48 +177,70 @@ pub fn environ_sizes_get(
     return_enc_errno(ret)
 }
 
+/// The outcome of `proc_exit`.
+///
+/// Rather than terminating the host process, `proc_exit` records the exit code in the `WasiCtx`
+/// and returns this value. The embedder must not resume the guest afterwards; it should unwind
+/// back to its own code, typically by raising a trap, and can then read the code back through
+/// `WasiCtx::exit_status`.
+#[must_use]
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ProcExit(pub host::__wasi_exitcode_t);
+
 #[wasi_common_cbindgen]
-pub fn proc_exit(rval: wasm32::__wasi_exitcode_t) -> () {
+pub fn proc_exit(wasi_ctx: &WasiCtx, rval: wasm32::__wasi_exitcode_t) -> ProcExit {
     trace!("proc_exit(rval={:?})", rval);
-    // TODO: Rather than call std::process::exit here, we should trigger a
-    // stack unwind similar to a trap.
-    std::process::exit(dec_exitcode(rval) as i32);
+
+    let rval = dec_exitcode(rval);
+    *wasi_ctx
+        .exit_status
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = Some(rval);
+
+    ProcExit(rval)
 }
 
 #[wasi_common_cbindgen]
 pub fn proc_raise(
     _wasi_ctx: &WasiCtx,
-    _memory: &mut [u8],
-    _sig: wasm32::__wasi_signal_t,
+    _memory: &dyn GuestMemory,
+    sig: wasm32::__wasi_signal_t,
 ) -> wasm32::__wasi_errno_t {
-    unimplemented!("proc_raise")
+    trace!("proc_raise(sig={:?})", sig);
+
+    // signals aren't supported, and this may be called through the C API, so it mustn't panic
+    return_enc_errno(host::__WASI_ENOSYS)
 }
 
 #[wasi_common_cbindgen]
 pub fn random_get(
-    memory: &mut [u8],
-    buf_ptr: wasm32::uintptr_t,
-    buf_len: wasm32::size_t,
+    wasi_ctx: &WasiCtx,
+    memory: &dyn GuestMemory,
+    buf_ptr: GuestAddr,
+    buf_len: GuestSize,
 ) -> wasm32::__wasi_errno_t {
     trace!("random_get(buf_ptr={:#x?}, buf_len={:?})", buf_ptr, buf_len);
 
-    use rand::{thread_rng, RngCore};
-
-    let buf = match dec_slice_of_mut::<u8>(memory, buf_ptr, buf_len) {
+    let buf = match dec_slice_of::<u8>(memory, buf_ptr, buf_len) {
         Ok(buf) => buf,
         Err(e) => return return_enc_errno(e),
     };
-
-    thread_rng().fill_bytes(buf);
+    // nothing else accesses guest memory until the generator is done with the buffer
+    let res = unsafe { buf.fill_with(|buf| wasi_ctx.rng.fill_bytes(buf).map(|()| buf.len())) };
+    if let Err(e) = res {
+        return return_enc_errno(e);
+    }
 
     return_enc_errno(host::__WASI_ESUCCESS)
 }
 
 #[wasi_common_cbindgen]
 pub fn clock_res_get(
-    memory: &mut [u8],
+    wasi_ctx: &WasiCtx,
+    memory: &dyn GuestMemory,
     clock_id: wasm32::__wasi_clockid_t,
-    resolution_ptr: wasm32::uintptr_t,
+    resolution_ptr: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "clock_res_get(clock_id={:?}, resolution_ptr={:#x?})",
@@ -219,7 +249,7 @@ pub fn clock_res_get(
     );
 
     let clock_id = dec_clockid(clock_id);
-    let resolution = match hostcalls_impl::clock_res_get(clock_id) {
+    let resolution = match wasi_ctx.clocks.res_get(clock_id) {
         Ok(resolution) => resolution,
         Err(e) => return return_enc_errno(e),
     };
@@ -235,12 +265,13 @@ pub fn clock_res_get(
 
 #[wasi_common_cbindgen]
 pub fn clock_time_get(
-    memory: &mut [u8],
+    wasi_ctx: &WasiCtx,
+    memory: &dyn GuestMemory,
     clock_id: wasm32::__wasi_clockid_t,
-    // ignored for now, but will be useful once we put optional limits on precision to reduce side
-    // channels
+    // ignored; limits on precision meant to reduce side channels are set per context with
+    // `WasiCtxBuilder::clock_granularity`
     precision: wasm32::__wasi_timestamp_t,
-    time_ptr: wasm32::uintptr_t,
+    time_ptr: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "clock_time_get(clock_id={:?}, precision={:?}, time_ptr={:#x?})",
@@ -250,7 +281,7 @@ pub fn clock_time_get(
     );
 
     let clock_id = dec_clockid(clock_id);
-    let time = match hostcalls_impl::clock_time_get(clock_id) {
+    let time = match wasi_ctx.clocks.time_get(clock_id) {
         Ok(time) => time,
         Err(e) => return return_enc_errno(e),
     };
@@ -266,11 +297,12 @@ pub fn clock_time_get(
 
 #[wasi_common_cbindgen]
 pub fn poll_oneoff(
-    memory: &mut [u8],
-    input: wasm32::uintptr_t,
-    output: wasm32::uintptr_t,
-    nsubscriptions: wasm32::size_t,
-    nevents: wasm32::uintptr_t,
+    wasi_ctx: &WasiCtx,
+    memory: &dyn GuestMemory,
+    input: GuestAddr,
+    output: GuestAddr,
+    nsubscriptions: GuestSize,
+    nevents: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
     trace!(
         "poll_oneoff(input={:#x?}, output={:#x?}, nsubscriptions={}, nevents={:#x?})",
@@ -280,31 +312,157 @@ pub fn poll_oneoff(
         nevents,
     );
 
-    if nsubscriptions as u64 > wasm32::__wasi_filesize_t::max_value() {
-        return return_enc_errno(host::__WASI_EINVAL);
-    }
-    if let Err(e) = enc_pointee(memory, nevents, 0) {
+    if let Err(e) = enc_usize_byref(memory, nevents, 0) {
         return return_enc_errno(e);
     }
-    let input_slice =
-        match dec_slice_of::<wasm32::__wasi_subscription_t>(memory, input, nsubscriptions) {
-            Ok(input_slice) => input_slice,
+    let subscriptions =
+        match dec_subscription_slice(memory, wasi_ctx.snapshot, input, nsubscriptions) {
+            Ok(subscriptions) => subscriptions,
             Err(e) => return return_enc_errno(e),
         };
-    let input: Vec<_> = input_slice.iter().map(dec_subscription).collect();
-    let output_slice =
-        match dec_slice_of_mut::<wasm32::__wasi_event_t>(memory, output, nsubscriptions) {
-            Ok(output_slice) => output_slice,
+    // The descriptors are looked up beforehand, as the entries have to outlive the wait even if
+    // another thread closes them in the meantime.
+    let input: Vec<_> = subscriptions
+        .into_iter()
+        .map(|subscription| {
+            let fe = match subscription.type_ {
+                host::__WASI_EVENTTYPE_FD_READ | host::__WASI_EVENTTYPE_FD_WRITE => {
+                    let wasi_fd = unsafe { subscription.u.fd_readwrite.fd };
+                    let rights = host::__WASI_RIGHT_POLL_FD_READWRITE;
+                    Some(wasi_ctx.get_fd_entry(wasi_fd, rights, 0))
+                }
+                _ => None,
+            };
+            (subscription, fe)
+        })
+        .collect();
+
+    let mut events = Vec::new();
+    let mut clock_events = Vec::new();
+    let mut fd_events = Vec::new();
+    let mut virtual_events = Vec::new();
+    for (subscription, fe) in &input {
+        match subscription.type_ {
+            host::__WASI_EVENTTYPE_CLOCK => {
+                let clock = unsafe { subscription.u.clock };
+                let now = match wasi_ctx.clocks.now(clock.clock_id) {
+                    Ok(now) => now,
+                    Err(e) => {
+                        events.push(poll_event(subscription.userdata, subscription.type_, e));
+                        continue;
+                    }
+                };
+                let deadline = if clock.flags & host::__WASI_SUBSCRIPTION_CLOCK_ABSTIME != 0 {
+                    clock.timeout
+                } else {
+                    now.saturating_add(clock.timeout)
+                };
+                clock_events.push(ClockEventData {
+                    clock_id: clock.clock_id,
+                    deadline,
+                    precision: clock.precision,
+                    userdata: subscription.userdata,
+                });
+            }
+            host::__WASI_EVENTTYPE_FD_READ | host::__WASI_EVENTTYPE_FD_WRITE => {
+                match fe
+                    .as_ref()
+                    .expect("the entry of a descriptor has been looked up")
+                {
+                    Ok(fe) if is_always_ready(fe.fd_object.file_type) => {
+                        events.push(poll_oneoff_file_event(fe, subscription))
+                    }
+                    Ok(fe) => match &*fe.fd_object.descriptor {
+                        Descriptor::VirtualFile(file) => virtual_events.push(VirtualEventData {
+                            file: &**file,
+                            type_: subscription.type_,
+                            userdata: subscription.userdata,
+                        }),
+                        descriptor => fd_events.push(FdEventData {
+                            descriptor,
+                            type_: subscription.type_,
+                            userdata: subscription.userdata,
+                        }),
+                    },
+                    Err(e) => {
+                        events.push(poll_event(subscription.userdata, subscription.type_, *e))
+                    }
+                }
+            }
+            // `dec_subscription_slice` skips any other event type
+            _ => unreachable!(),
+        }
+    }
+
+    loop {
+        poll_oneoff_virtual_files(&virtual_events, &mut events);
+        let timeout = match poll_oneoff_expire_clocks(wasi_ctx, &clock_events, &mut events) {
+            Ok(timeout) => timeout,
             Err(e) => return return_enc_errno(e),
         };
-    let events_count = match hostcalls_impl::poll_oneoff(input, output_slice) {
-        Ok(events_count) => events_count,
+        // With coarsened clocks, only wake up on a granularity boundary, when the deadline can
+        // have been reached.
+        let timeout = match wasi_ctx.clock_quantum {
+            Some(quantum) => timeout.map(|delay| quantum.round_up(delay)),
+            None => timeout,
+        };
+        // Subscriptions which have already triggered must not be delayed by the rest, so only
+        // check whether the host descriptors are ready without blocking.
+        let timeout = if events.is_empty() { timeout } else { Some(0) };
+        let done = match timeout {
+            Some(timeout) => timeout == 0,
+            None => virtual_events.is_empty(),
+        };
+        if fd_events.is_empty() && done {
+            break;
+        }
+        if let Some(delay) = timeout.filter(|_| wasi_ctx.clocks.is_simulated()) {
+            // sleeping on a simulated clock completes instantly, unless a descriptor is ready
+            if !fd_events.is_empty() {
+                let timeout = Some(Duration::from_secs(0));
+                if let Err(e) = hostcalls_impl::poll_oneoff(timeout, &fd_events, &mut events) {
+                    return return_enc_errno(e);
+                }
+                if !events.is_empty() {
+                    break;
+                }
+            }
+            wasi_ctx.clocks.advance(delay);
+            continue;
+        }
+        // virtual files can't be waited on, so they are checked again periodically instead
+        let timeout = if virtual_events.is_empty() {
+            timeout
+        } else {
+            Some(timeout.map_or(VIRTUAL_FILE_POLL_INTERVAL, |timeout| {
+                cmp::min(timeout, VIRTUAL_FILE_POLL_INTERVAL)
+            }))
+        };
+        let timeout = timeout.map(Duration::from_nanos);
+        if let Err(e) = hostcalls_impl::poll_oneoff(timeout, &fd_events, &mut events) {
+            return return_enc_errno(e);
+        }
+        if !events.is_empty() {
+            break;
+        }
+        // Either the host was interrupted by a signal, woke up before the deadline as measured
+        // by the subscribed clock, or a virtual file needs checking; keep waiting for whatever
+        // is left.
+    }
+
+    let output_slice = match dec_slice_of::<wasm32::__wasi_event_t>(memory, output, nsubscriptions)
+    {
+        Ok(output_slice) => output_slice,
         Err(e) => return return_enc_errno(e),
     };
+    let events_count = events.len();
+    for (output_event, event) in output_slice.iter().zip(events) {
+        output_event.write(enc_event(event));
+    }
 
     trace!("     | *nevents={:?}", events_count);
 
-    let ret = match enc_pointee(memory, nevents, events_count) {
+    let ret = match enc_usize_byref(memory, nevents, events_count) {
         Ok(()) => host::__WASI_ESUCCESS,
         Err(e) => e,
     };
@@ -312,6 +470,136 @@ pub fn poll_oneoff(
     return_enc_errno(ret)
 }
 
+fn poll_event(
+    userdata: host::__wasi_userdata_t,
+    type_: host::__wasi_eventtype_t,
+    error: host::__wasi_errno_t,
+) -> host::__wasi_event_t {
+    host::__wasi_event_t {
+        userdata,
+        type_,
+        error,
+        fd_readwrite: host::__wasi_event_fd_readwrite_t {
+            nbytes: 0,
+            flags: 0,
+        },
+    }
+}
+
+/// Regular files and directories never block, so POSIX has `poll` report them as ready straight
+/// away; there is no point in handing them to the host.
+fn is_always_ready(file_type: host::__wasi_filetype_t) -> bool {
+    file_type == host::__WASI_FILETYPE_REGULAR_FILE || file_type == host::__WASI_FILETYPE_DIRECTORY
+}
+
+/// Builds the event for a subscription on an always-ready descriptor. A read subscription on a
+/// regular file reports the number of bytes between the current offset and the end of file.
+fn poll_oneoff_file_event(
+    fe: &FdEntry,
+    subscription: &host::__wasi_subscription_t,
+) -> host::__wasi_event_t {
+    let mut event = poll_event(
+        subscription.userdata,
+        subscription.type_,
+        host::__WASI_ESUCCESS,
+    );
+    if fe.fd_object.file_type != host::__WASI_FILETYPE_REGULAR_FILE
+        || subscription.type_ != host::__WASI_EVENTTYPE_FD_READ
+    {
+        return event;
+    }
+    let (size, offset) = match &*fe.fd_object.descriptor {
+        Descriptor::VirtualFile(vf) => (
+            vf.filestat_get().map(|filestat| filestat.st_size),
+            vf.seek(0, host::__WASI_WHENCE_CUR),
+        ),
+        _ => (
+            hostcalls_impl::fd_filestat_get(fe).map(|filestat| filestat.st_size),
+            hostcalls_impl::fd_tell(fe),
+        ),
+    };
+    match size.and_then(|size| offset.map(|offset| size.saturating_sub(offset))) {
+        Ok(nbytes) => event.fd_readwrite.nbytes = nbytes,
+        Err(e) => event.error = e,
+    }
+    event
+}
+
+/// Reports every subscription on a virtual file which has triggered.
+fn poll_oneoff_virtual_files(
+    virtual_events: &[VirtualEventData],
+    events: &mut Vec<host::__wasi_event_t>,
+) {
+    for virtual_event in virtual_events {
+        let mut event = poll_event(
+            virtual_event.userdata,
+            virtual_event.type_,
+            host::__WASI_ESUCCESS,
+        );
+        match virtual_event.file.poll_readiness(virtual_event.type_) {
+            Ok(Some(fd_readwrite)) => event.fd_readwrite = fd_readwrite,
+            Ok(None) => continue,
+            Err(e) => event.error = e,
+        }
+        events.push(event);
+    }
+}
+
+/// Reports every clock subscription whose deadline has passed, and returns how long to sleep
+/// before the next one is due.
+///
+/// The `precision` of a subscription lets the wake-up be deferred, so the sleep is stretched to
+/// the latest pending deadline which still falls within every subscription's tolerance. This
+/// batches timers that are close together into a single wake-up.
+fn poll_oneoff_expire_clocks(
+    wasi_ctx: &WasiCtx,
+    clock_events: &[ClockEventData],
+    events: &mut Vec<host::__wasi_event_t>,
+) -> Result<Option<host::__wasi_timestamp_t>, host::__wasi_errno_t> {
+    let mut delays = Vec::with_capacity(clock_events.len());
+    let mut latest_wakeup = host::__wasi_timestamp_t::max_value();
+    for clock_event in clock_events {
+        let now = wasi_ctx.clocks.now(clock_event.clock_id)?;
+        if now >= clock_event.deadline {
+            events.push(poll_event(
+                clock_event.userdata,
+                host::__WASI_EVENTTYPE_CLOCK,
+                host::__WASI_ESUCCESS,
+            ));
+        } else {
+            let delay = clock_event.deadline - now;
+            latest_wakeup = cmp::min(latest_wakeup, delay.saturating_add(clock_event.precision));
+            delays.push(delay);
+        }
+    }
+    Ok(delays
+        .into_iter()
+        .filter(|&delay| delay <= latest_wakeup)
+        .max())
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ClockEventData {
+    clock_id: host::__wasi_clockid_t,
+    deadline: host::__wasi_timestamp_t,
+    precision: host::__wasi_timestamp_t,
+    userdata: host::__wasi_userdata_t,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct VirtualEventData<'a> {
+    file: &'a dyn VirtualFile,
+    type_: host::__wasi_eventtype_t,
+    userdata: host::__wasi_userdata_t,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct FdEventData<'a> {
+    pub(crate) descriptor: &'a Descriptor,
+    pub(crate) type_: host::__wasi_eventtype_t,
+    pub(crate) userdata: host::__wasi_userdata_t,
+}
+
 #[wasi_common_cbindgen]
 pub fn sched_yield() -> wasm32::__wasi_errno_t {
     trace!("sched_yield()");
@@ -320,3 +608,317 @@ pub fn sched_yield() -> wasm32::__wasi_errno_t {
 
     return_enc_errno(host::__WASI_ESUCCESS)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::put_iovec;
+    use crate::{SliceMemory, WasiCtxBuilder};
+    use std::io;
+
+    const SUBSCRIPTIONS: usize = 256;
+    const EVENTS: usize = 512;
+    const NEVENTS: usize = 8;
+
+    fn put(mem: &mut [u8], offset: usize, bytes: &[u8]) {
+        mem[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn subscribe_fd(mem: &mut [u8], index: usize, userdata: u64, fd: u32) {
+        let offset = SUBSCRIPTIONS + index * 56;
+        put(mem, offset, &userdata.to_le_bytes());
+        mem[offset + 8] = host::__WASI_EVENTTYPE_FD_READ;
+        put(mem, offset + 16, &fd.to_le_bytes());
+    }
+
+    fn subscribe_clock(mem: &mut [u8], index: usize, userdata: u64, timeout: u64) {
+        let clock_id = host::__WASI_CLOCK_MONOTONIC;
+        subscribe_clock_with(mem, index, userdata, clock_id, timeout, 0, 0);
+    }
+
+    fn subscribe_clock_with(
+        mem: &mut [u8],
+        index: usize,
+        userdata: u64,
+        clock_id: host::__wasi_clockid_t,
+        timeout: u64,
+        precision: u64,
+        flags: host::__wasi_subclockflags_t,
+    ) {
+        let offset = SUBSCRIPTIONS + index * 56;
+        put(mem, offset, &userdata.to_le_bytes());
+        mem[offset + 8] = host::__WASI_EVENTTYPE_CLOCK;
+        put(mem, offset + 24, &clock_id.to_le_bytes());
+        put(mem, offset + 32, &timeout.to_le_bytes());
+        put(mem, offset + 40, &precision.to_le_bytes());
+        put(mem, offset + 48, &flags.to_le_bytes());
+    }
+
+    /// Returns the userdata, error, nbytes and flags of the events.
+    fn events(mem: &[u8]) -> Vec<(u64, u16, u64, u16)> {
+        let u64_at = |offset: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&mem[offset..offset + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let u16_at = |offset: usize| u16::from_le_bytes([mem[offset], mem[offset + 1]]);
+        (0..mem[NEVENTS] as usize)
+            .map(|index| {
+                let offset = EVENTS + index * 32;
+                (
+                    u64_at(offset),
+                    u16_at(offset + 8),
+                    u64_at(offset + 16),
+                    u16_at(offset + 24),
+                )
+            })
+            .collect()
+    }
+
+    fn poll(ctx: &WasiCtx, mem: &mut [u8], nsubscriptions: GuestSize) {
+        let memory = SliceMemory::new(mem);
+        let errno = poll_oneoff(
+            ctx,
+            &memory,
+            SUBSCRIPTIONS as GuestAddr,
+            EVENTS as GuestAddr,
+            nsubscriptions,
+            NEVENTS as GuestAddr,
+        );
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+    }
+
+    #[test]
+    fn proc_exit_returns_to_the_embedder() {
+        let ctx = WasiCtxBuilder::new().unwrap().build().unwrap();
+        assert_eq!(ctx.exit_status(), None);
+        assert_eq!(proc_exit(&ctx, 3), ProcExit(3));
+        assert_eq!(ctx.exit_status(), Some(3));
+
+        // the C entry point returns the code as is
+        let ctx_ptr = &ctx as *const WasiCtx as *mut WasiCtx;
+        assert_eq!(unsafe { wasi_common_proc_exit(ctx_ptr, 5) }, ProcExit(5));
+        assert_eq!(ctx.exit_status(), Some(5));
+    }
+
+    #[test]
+    fn deterministic_clocks_and_random() {
+        let run = || {
+            let ctx = WasiCtxBuilder::new()
+                .unwrap()
+                .deterministic(42)
+                .clock_step(Duration::from_micros(5))
+                .build()
+                .unwrap();
+            let mut mem = vec![0u8; 64];
+            let mut time = |clock_id| {
+                let errno = clock_time_get(&ctx, &SliceMemory::new(&mut mem), clock_id, 0, 0);
+                assert_eq!(errno, host::__WASI_ESUCCESS);
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&mem[0..8]);
+                u64::from_le_bytes(bytes)
+            };
+            let times = vec![
+                time(host::__WASI_CLOCK_MONOTONIC),
+                time(host::__WASI_CLOCK_REALTIME),
+                time(host::__WASI_CLOCK_MONOTONIC),
+            ];
+            let errno = random_get(&ctx, &SliceMemory::new(&mut mem), 16, 16);
+            assert_eq!(errno, host::__WASI_ESUCCESS);
+            (times, mem[16..32].to_vec())
+        };
+        let (times, random) = run();
+        assert_eq!(times, vec![0, 5000, 10000]);
+        assert_eq!(run(), (times, random));
+    }
+
+    #[test]
+    fn proc_raise_is_not_supported() {
+        let ctx = WasiCtxBuilder::new().unwrap().build().unwrap();
+        let mut mem = vec![0; 8];
+        let errno = proc_raise(&ctx, &SliceMemory::new(&mut mem), host::__WASI_SIGTERM);
+        assert_eq!(errno, host::__WASI_ENOSYS);
+    }
+
+    fn userdata(mem: &[u8]) -> Vec<u64> {
+        events(mem).iter().map(|event| event.0).collect()
+    }
+
+    #[test]
+    fn clock_subscriptions() {
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .deterministic(0)
+            .build()
+            .unwrap();
+        let (realtime, monotonic) = (host::__WASI_CLOCK_REALTIME, host::__WASI_CLOCK_MONOTONIC);
+        let now = || ctx.clocks.now(monotonic).unwrap();
+        let mut mem = vec![0; 1024];
+
+        // only the earliest of several deadlines triggers
+        subscribe_clock(&mut mem, 0, 1, 20_000_000);
+        subscribe_clock(&mut mem, 1, 2, 10_000_000);
+        poll(&ctx, &mut mem, 2);
+        assert_eq!(userdata(&mem), vec![2]);
+        assert_eq!(now(), 10_000_000);
+
+        // the wake-up is deferred to batch deadlines within the precision of the earliest one
+        subscribe_clock_with(&mut mem, 0, 1, monotonic, 10_000_000, 20_000_000, 0);
+        subscribe_clock(&mut mem, 1, 2, 20_000_000);
+        subscribe_clock(&mut mem, 2, 3, 500_000_000);
+        poll(&ctx, &mut mem, 3);
+        assert_eq!(userdata(&mem), vec![1, 2]);
+        assert_eq!(now(), 30_000_000);
+
+        // absolute deadlines are honoured to the nanosecond, or trigger straight away if past
+        let abstime = host::__WASI_SUBSCRIPTION_CLOCK_ABSTIME;
+        subscribe_clock_with(&mut mem, 0, 1, realtime, 30_000_001, 0, abstime);
+        poll(&ctx, &mut mem, 1);
+        assert_eq!(userdata(&mem), vec![1]);
+        assert_eq!(now(), 30_000_001);
+        subscribe_clock_with(&mut mem, 0, 2, realtime, 5, 0, abstime);
+        poll(&ctx, &mut mem, 1);
+        assert_eq!(userdata(&mem), vec![2]);
+        assert_eq!(now(), 30_000_001);
+
+        subscribe_clock_with(&mut mem, 0, 1, 77, 0, 0, 0);
+        poll(&ctx, &mut mem, 1);
+        assert_eq!(events(&mem), vec![(1, host::__WASI_EINVAL, 0, 0)]);
+    }
+
+    /// Returns the read and write ends of a host pipe.
+    #[cfg(unix)]
+    fn pipe() -> (std::fs::File, std::fs::File) {
+        use std::os::unix::prelude::FromRawFd;
+
+        let (read, write) = nix::unistd::pipe().unwrap();
+        unsafe {
+            (
+                std::fs::File::from_raw_fd(read),
+                std::fs::File::from_raw_fd(write),
+            )
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fd_subscriptions_use_guest_descriptors() {
+        use std::io::Write;
+
+        let ctx = WasiCtxBuilder::new().unwrap().build().unwrap();
+        let (read, mut write) = pipe();
+        let fd = ctx.insert_fd_entry(FdEntry::from(read).unwrap()).unwrap();
+        let mut mem = vec![0; 1024];
+        subscribe_fd(&mut mem, 0, 1, fd);
+        subscribe_clock(&mut mem, 1, 2, 5_000_000);
+        let userdata_and_error =
+            |mem: &[u8]| -> Vec<_> { events(mem).iter().map(|e| (e.0, e.1)).collect() };
+        poll(&ctx, &mut mem, 2);
+        assert_eq!(userdata_and_error(&mem), vec![(2, host::__WASI_ESUCCESS)]);
+
+        write.write_all(b"x").unwrap();
+        poll(&ctx, &mut mem, 2);
+        assert_eq!(userdata_and_error(&mem), vec![(1, host::__WASI_ESUCCESS)]);
+
+        subscribe_fd(&mut mem, 0, 3, 99);
+        poll(&ctx, &mut mem, 2);
+        assert_eq!(userdata_and_error(&mem), vec![(3, host::__WASI_EBADF)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn host_pipe_reports_nbytes_and_hangup() {
+        use std::io::Write;
+
+        let ctx = WasiCtxBuilder::new().unwrap().build().unwrap();
+        let (read, mut write) = pipe();
+        let fd = ctx.insert_fd_entry(FdEntry::from(read).unwrap()).unwrap();
+        let mut mem = vec![0; 1024];
+        subscribe_fd(&mut mem, 0, 1, fd);
+        write.write_all(b"abc").unwrap();
+        poll(&ctx, &mut mem, 1);
+        assert_eq!(events(&mem), vec![(1, host::__WASI_ESUCCESS, 3, 0)]);
+
+        // what was sent before the hangup can still be read
+        drop(write);
+        poll(&ctx, &mut mem, 1);
+        let hangup = host::__WASI_EVENT_FD_READWRITE_HANGUP;
+        assert_eq!(events(&mem), vec![(1, host::__WASI_ESUCCESS, 3, hangup)]);
+    }
+
+    #[test]
+    fn regular_file_reports_bytes_left() {
+        let path = std::env::temp_dir().join(format!("wasi-common-poll-{}", std::process::id()));
+        std::fs::write(&path, b"0123456789").unwrap();
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .stdin_path(&path)
+            .unwrap()
+            .build()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut mem = vec![0; 1024];
+        put_iovec(&mut mem, 16, 32, 3);
+        let errno = crate::hostcalls::fd_read(&ctx, &SliceMemory::new(&mut mem), 0, 16, 1, 24);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        subscribe_fd(&mut mem, 0, 1, 0);
+        poll(&ctx, &mut mem, 1);
+        assert_eq!(events(&mem), vec![(1, host::__WASI_ESUCCESS, 7, 0)]);
+    }
+
+    /// A reader which never has any data available.
+    struct Empty;
+
+    impl io::Read for Empty {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    #[test]
+    fn empty_pipe_is_not_ready() {
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .stdin(Box::new(Empty))
+            .build()
+            .unwrap();
+        let mut mem = vec![0; 1024];
+        subscribe_fd(&mut mem, 0, 1, 0);
+        subscribe_clock(&mut mem, 1, 2, 5_000_000);
+        let start = std::time::Instant::now();
+        poll(&ctx, &mut mem, 2);
+        assert!(start.elapsed() >= Duration::from_millis(5));
+        assert_eq!(events(&mem), vec![(2, host::__WASI_ESUCCESS, 0, 0)]);
+    }
+
+    #[test]
+    fn pipe_reports_available_bytes_and_eof() {
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .stdin_bytes(&b"abc"[..])
+            .build()
+            .unwrap();
+        let mut mem = vec![0; 1024];
+        subscribe_fd(&mut mem, 0, 1, 0);
+        poll(&ctx, &mut mem, 1);
+        assert_eq!(events(&mem), vec![(1, host::__WASI_ESUCCESS, 3, 0)]);
+
+        // the bytes peeked by `poll_oneoff` are still read by the guest
+        put_iovec(&mut mem, 16, 32, 16);
+        let errno = crate::hostcalls::fd_read(&ctx, &SliceMemory::new(&mut mem), 0, 16, 1, 24);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        assert_eq!(mem[24], 3);
+        assert_eq!(&mem[32..35], b"abc");
+
+        poll(&ctx, &mut mem, 1);
+        assert_eq!(
+            events(&mem),
+            vec![(
+                1,
+                host::__WASI_ESUCCESS,
+                0,
+                host::__WASI_EVENT_FD_READWRITE_HANGUP
+            )]
+        );
+    }
+}
@@ -1,44 +1,231 @@
 #![allow(non_camel_case_types)]
-#![allow(unused_unsafe)]
-#![allow(unused)]
-
+use super::return_enc_errno;
 use crate::ctx::WasiCtx;
-use crate::wasm32;
+use crate::fdentry::Descriptor;
+use crate::guest_memory::{
+    read_into_iovecs, write_from_ciovecs, GuestAddr, GuestBorrows, GuestMemory, GuestSize,
+};
+use crate::memory::*;
+use crate::sys::hostcalls_impl;
+use crate::{host, wasm32};
+use log::trace;
+use std::convert::identity;
+
 use wasi_common_cbindgen::wasi_common_cbindgen;
 
 #[wasi_common_cbindgen]
 pub fn sock_recv(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     sock: wasm32::__wasi_fd_t,
-    ri_data: wasm32::uintptr_t,
-    ri_data_len: wasm32::size_t,
+    ri_data: GuestAddr,
+    ri_data_len: GuestSize,
     ri_flags: wasm32::__wasi_riflags_t,
-    ro_datalen: wasm32::uintptr_t,
-    ro_flags: wasm32::uintptr_t,
+    ro_datalen: GuestAddr,
+    ro_flags: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
-    unimplemented!("sock_recv")
+    trace!(
+        "sock_recv(sock={:?}, ri_data={:#x?}, ri_data_len={:?}, ri_flags={:#x?}, ro_datalen={:#x?}, ro_flags={:#x?})",
+        sock,
+        ri_data,
+        ri_data_len,
+        ri_flags,
+        ro_datalen,
+        ro_flags
+    );
+
+    let sock = dec_fd(sock);
+    let ri_flags = dec_riflags(ri_flags);
+    let iovs = match dec_iovec_slice(memory, &mut GuestBorrows::new(), ri_data, ri_data_len) {
+        Ok(iovs) => iovs,
+        Err(e) => return return_enc_errno(e),
+    };
+    let fe = match wasi_ctx.get_fd_entry(sock, host::__WASI_RIGHT_FD_READ, 0) {
+        Ok(fe) => fe,
+        Err(e) => return return_enc_errno(e),
+    };
+
+    let mut host_roflags = 0;
+    // the buffers have been checked not to overlap
+    let maybe_host_datalen = unsafe {
+        read_into_iovecs(memory, &iovs, |iovs| match &*fe.fd_object.descriptor {
+            Descriptor::VirtualFile(_) => Err(host::__WASI_ENOTSOCK),
+            _ => hostcalls_impl::sock_recv(&fe, iovs, ri_flags).map(|(datalen, roflags)| {
+                host_roflags = roflags;
+                datalen
+            }),
+        })
+    };
+    let host_datalen = match maybe_host_datalen {
+        Ok(host_datalen) => host_datalen,
+        Err(e) => return return_enc_errno(e),
+    };
+
+    trace!("     | *ro_datalen={:?}", host_datalen);
+    trace!("     | *ro_flags={:#x?}", host_roflags);
+
+    let ret = enc_usize_byref(memory, ro_datalen, host_datalen)
+        .and_then(|_| enc_roflags_byref(memory, ro_flags, host_roflags))
+        .map(|_| host::__WASI_ESUCCESS)
+        .unwrap_or_else(identity);
+
+    return_enc_errno(ret)
 }
 
 #[wasi_common_cbindgen]
 pub fn sock_send(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    memory: &dyn GuestMemory,
     sock: wasm32::__wasi_fd_t,
-    si_data: wasm32::uintptr_t,
-    si_data_len: wasm32::size_t,
+    si_data: GuestAddr,
+    si_data_len: GuestSize,
     si_flags: wasm32::__wasi_siflags_t,
-    so_datalen: wasm32::uintptr_t,
+    so_datalen: GuestAddr,
 ) -> wasm32::__wasi_errno_t {
-    unimplemented!("sock_send")
+    trace!(
+        "sock_send(sock={:?}, si_data={:#x?}, si_data_len={:?}, si_flags={:#x?}, so_datalen={:#x?})",
+        sock,
+        si_data,
+        si_data_len,
+        si_flags,
+        so_datalen
+    );
+
+    let sock = dec_fd(sock);
+    // no send flags are defined yet, so `si_flags` carries no information
+    let _si_flags = dec_siflags(si_flags);
+    let iovs = match dec_ciovec_slice(memory, &mut GuestBorrows::new(), si_data, si_data_len) {
+        Ok(iovs) => iovs,
+        Err(e) => return return_enc_errno(e),
+    };
+    let fe = match wasi_ctx.get_fd_entry(sock, host::__WASI_RIGHT_FD_WRITE, 0) {
+        Ok(fe) => fe,
+        Err(e) => return return_enc_errno(e),
+    };
+
+    // the buffers are only read from
+    let maybe_host_datalen = unsafe {
+        write_from_ciovecs(memory, &iovs, |iovs| match &*fe.fd_object.descriptor {
+            Descriptor::VirtualFile(_) => Err(host::__WASI_ENOTSOCK),
+            _ => hostcalls_impl::sock_send(&fe, iovs),
+        })
+    };
+    let host_datalen = match maybe_host_datalen {
+        Ok(host_datalen) => host_datalen,
+        Err(e) => return return_enc_errno(e),
+    };
+
+    trace!("     | *so_datalen={:?}", host_datalen);
+
+    let ret = enc_usize_byref(memory, so_datalen, host_datalen)
+        .map(|_| host::__WASI_ESUCCESS)
+        .unwrap_or_else(identity);
+
+    return_enc_errno(ret)
 }
 
 #[wasi_common_cbindgen]
 pub fn sock_shutdown(
     wasi_ctx: &WasiCtx,
-    memory: &mut [u8],
+    _memory: &dyn GuestMemory,
     sock: wasm32::__wasi_fd_t,
     how: wasm32::__wasi_sdflags_t,
 ) -> wasm32::__wasi_errno_t {
-    unimplemented!("sock_shutdown")
+    trace!("sock_shutdown(sock={:?}, how={:#x?})", sock, how);
+
+    let sock = dec_fd(sock);
+    let how = dec_sdflags(how);
+    let fe = match wasi_ctx.get_fd_entry(sock, host::__WASI_RIGHT_SOCK_SHUTDOWN, 0) {
+        Ok(fe) => fe,
+        Err(e) => return return_enc_errno(e),
+    };
+
+    let ret = match &*fe.fd_object.descriptor {
+        Descriptor::VirtualFile(_) => Err(host::__WASI_ENOTSOCK),
+        _ => hostcalls_impl::sock_shutdown(&fe, how),
+    }
+    .map(|_| host::__WASI_ESUCCESS)
+    .unwrap_or_else(identity);
+
+    return_enc_errno(ret)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::fdentry::FdEntry;
+    use crate::test_utils::memory_with_iovec;
+    use crate::{SliceMemory, WasiCtxBuilder};
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::net::{UnixDatagram, UnixStream};
+    use std::os::unix::prelude::{FromRawFd, IntoRawFd};
+
+    fn insert<S: IntoRawFd>(ctx: &WasiCtx, sock: S) -> wasm32::__wasi_fd_t {
+        let file = unsafe { File::from_raw_fd(sock.into_raw_fd()) };
+        ctx.insert_fd_entry(FdEntry::from(file).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn stream_send_recv_shutdown() {
+        let ctx = WasiCtxBuilder::new().unwrap().build().unwrap();
+        let (sock, mut peer) = UnixStream::pair().unwrap();
+        let fd = insert(&ctx, sock);
+
+        let mut mem = memory_with_iovec(b"hello", 5);
+        let errno = sock_send(&ctx, &SliceMemory::new(&mut mem), fd, 0, 1, 0, 8);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        assert_eq!(mem[8], 5);
+        let mut buf = [0; 5];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // peeking leaves the data in place for the next read
+        peer.write_all(b"world").unwrap();
+        let mut mem = memory_with_iovec(b"", 3);
+        let peek = host::__WASI_SOCK_RECV_PEEK;
+        let errno = sock_recv(&ctx, &SliceMemory::new(&mut mem), fd, 0, 1, peek, 8, 12);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        assert_eq!((mem[8], &mem[32..35]), (3, &b"wor"[..]));
+        let mut mem = memory_with_iovec(b"", 5);
+        let waitall = host::__WASI_SOCK_RECV_WAITALL;
+        let errno = sock_recv(&ctx, &SliceMemory::new(&mut mem), fd, 0, 1, waitall, 8, 12);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        assert_eq!((mem[8], &mem[32..37]), (5, &b"world"[..]));
+
+        let memory = SliceMemory::new(&mut mem);
+        assert_eq!(sock_shutdown(&ctx, &memory, fd, 0), host::__WASI_EINVAL);
+        let both = host::__WASI_SHUT_RD | host::__WASI_SHUT_WR;
+        assert_eq!(
+            sock_shutdown(&ctx, &memory, fd, both),
+            host::__WASI_ESUCCESS
+        );
+        let errno = sock_send(&ctx, &memory, fd, 0, 1, 0, 8);
+        assert_eq!(errno, host::__WASI_EPIPE);
+    }
+
+    #[test]
+    fn datagram_truncation() {
+        let ctx = WasiCtxBuilder::new().unwrap().build().unwrap();
+        let (sock, peer) = UnixDatagram::pair().unwrap();
+        let fd = insert(&ctx, sock);
+        peer.send(b"abcdef").unwrap();
+        let mut mem = memory_with_iovec(b"", 3);
+        let errno = sock_recv(&ctx, &SliceMemory::new(&mut mem), fd, 0, 1, 0, 8, 12);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        assert_eq!(mem[8], 3);
+        assert_eq!(mem[12], host::__WASI_SOCK_RECV_DATA_TRUNCATED as u8);
+    }
+
+    #[test]
+    fn not_a_socket() {
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .stdin_bytes(&b""[..])
+            .build()
+            .unwrap();
+        let mut mem = memory_with_iovec(b"", 1);
+        let errno = sock_recv(&ctx, &SliceMemory::new(&mut mem), 0, 0, 1, 0, 8, 12);
+        assert_eq!(errno, host::__WASI_ENOTSOCK);
+    }
 }
@@ -22,12 +22,24 @@
 
 mod ctx;
 mod fdentry;
+mod path_walk;
 mod sys;
+#[cfg(test)]
+mod test_utils;
 
+pub mod clocks;
+pub mod guest_memory;
 pub mod host;
 pub mod hostcalls;
 pub mod memory;
+pub mod random;
+pub mod virtfs;
+pub mod wasi_snapshot_preview1;
 pub mod wasm32;
+pub mod wasm64;
 
-pub use ctx::{WasiCtx, WasiCtxBuilder};
+pub use clocks::WasiClocks;
+pub use ctx::{PreopenRights, WasiCtx, WasiCtxBuilder, WasiSnapshot};
+pub use guest_memory::{GuestAbi, GuestMemory, GuestPtr, GuestSlice, SliceMemory};
+pub use random::WasiRng;
 pub use sys::preopen_dir;
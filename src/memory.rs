@@ -1,264 +1,157 @@
-//! Functions to go back and forth between WASI types in host and wasm32 representations.
+//! Functions to go back and forth between WASI types in host and guest representations.
+//!
+//! Types containing pointers or sizes are laid out according to the ABI of the guest memory,
+//! all the others are the same for wasm32 and wasm64. The few types which differ between WASI
+//! snapshots are laid out according to the snapshot of the guest.
+//!
+//! The conversions of scalar types represented the same way in the host and the guest are
+//! generated from the witx definitions, the others are written by hand.
 #![allow(unused)]
-use crate::{host, wasm32};
+use crate::ctx::WasiSnapshot;
+use crate::guest_memory::{
+    GuestAbi, GuestAddr, GuestBorrows, GuestMemory, GuestPtr, GuestSize, GuestSlice, GuestType,
+};
+use crate::{host, wasi_snapshot_preview1 as preview1, wasm32, wasm64};
 use std::convert::TryFrom;
-use std::mem::{align_of, size_of};
-use std::ptr;
-use std::slice;
+use std::mem;
+use wasi_common_witx::witx_scalar_codecs;
 
-macro_rules! bail_errno {
-    ( $errno:ident ) => {
-        return Err(host::$errno);
-    };
-}
-
-fn dec_ptr(
-    memory: &[u8],
-    ptr: wasm32::uintptr_t,
-    len: usize,
-) -> Result<*const u8, host::__wasi_errno_t> {
-    // check for overflow
-    let checked_len = (ptr as usize).checked_add(len).ok_or(host::__WASI_EFAULT)?;
-
-    // translate the pointer
-    memory
-        .get(ptr as usize..checked_len)
-        .ok_or(host::__WASI_EFAULT)
-        .map(|mem| mem.as_ptr())
-}
-
-fn dec_ptr_mut(
-    memory: &mut [u8],
-    ptr: wasm32::uintptr_t,
-    len: usize,
-) -> Result<*mut u8, host::__wasi_errno_t> {
-    // check for overflow
-    let checked_len = (ptr as usize).checked_add(len).ok_or(host::__WASI_EFAULT)?;
-
-    // translate the pointer
-    memory
-        .get_mut(ptr as usize..checked_len)
-        .ok_or(host::__WASI_EFAULT)
-        .map(|mem| mem.as_mut_ptr())
-}
-
-fn dec_ptr_to<'memory, T>(
-    memory: &'memory [u8],
-    ptr: wasm32::uintptr_t,
-) -> Result<&'memory T, host::__wasi_errno_t> {
-    // check that the ptr is aligned
-    if ptr as usize % align_of::<T>() != 0 {
-        bail_errno!(__WASI_EINVAL);
-    }
-
-    dec_ptr(memory, ptr, size_of::<T>()).map(|p| unsafe { &*(p as *const T) })
-}
-
-fn dec_ptr_to_mut<'memory, T>(
-    memory: &'memory mut [u8],
-    ptr: wasm32::uintptr_t,
-) -> Result<&'memory mut T, host::__wasi_errno_t> {
-    // check that the ptr is aligned
-    if ptr as usize % align_of::<T>() != 0 {
-        bail_errno!(__WASI_EINVAL);
-    }
-
-    dec_ptr_mut(memory, ptr, size_of::<T>()).map(|p| unsafe { &mut *(p as *mut T) })
-}
-
-pub fn dec_pointee<T>(memory: &[u8], ptr: wasm32::uintptr_t) -> Result<T, host::__wasi_errno_t> {
-    dec_ptr_to::<T>(memory, ptr).map(|p| unsafe { ptr::read(p) })
+pub fn dec_pointee<T: GuestType>(
+    memory: &dyn GuestMemory,
+    ptr: GuestAddr,
+) -> Result<T, host::__wasi_errno_t> {
+    GuestPtr::<T>::new(memory, ptr).map(|p| p.read())
 }
 
-pub fn enc_pointee<T>(
-    memory: &mut [u8],
-    ptr: wasm32::uintptr_t,
+pub fn enc_pointee<T: GuestType>(
+    memory: &dyn GuestMemory,
+    ptr: GuestAddr,
     t: T,
 ) -> Result<(), host::__wasi_errno_t> {
-    dec_ptr_to_mut::<T>(memory, ptr).map(|p| unsafe { ptr::write(p, t) })
+    GuestPtr::<T>::new(memory, ptr).map(|p| p.write(t))
 }
 
-fn check_slice_of<T>(
-    ptr: wasm32::uintptr_t,
-    len: wasm32::size_t,
-) -> Result<(usize, usize), host::__wasi_errno_t> {
-    // check alignment, and that length doesn't overflow
-    if ptr as usize % align_of::<T>() != 0 {
-        return Err(host::__WASI_EINVAL);
-    }
-    let len = dec_usize(len);
-    let len_bytes = if let Some(len) = size_of::<T>().checked_mul(len) {
-        len
-    } else {
-        return Err(host::__WASI_EOVERFLOW);
-    };
-
-    Ok((len, len_bytes))
+pub fn dec_slice_of<T: GuestType>(
+    memory: &dyn GuestMemory,
+    ptr: GuestAddr,
+    len: GuestSize,
+) -> Result<GuestSlice<'_, T>, host::__wasi_errno_t> {
+    GuestSlice::new(memory, ptr, len)
 }
 
-pub fn dec_slice_of<'memory, T>(
-    memory: &'memory [u8],
-    ptr: wasm32::uintptr_t,
-    len: wasm32::size_t,
-) -> Result<&'memory [T], host::__wasi_errno_t> {
-    let (len, len_bytes) = check_slice_of::<T>(ptr, len)?;
-    let ptr = dec_ptr(memory, ptr, len_bytes)? as *const T;
-    Ok(unsafe { slice::from_raw_parts(ptr, len) })
-}
-
-pub fn dec_slice_of_mut<'memory, T>(
-    memory: &'memory mut [u8],
-    ptr: wasm32::uintptr_t,
-    len: wasm32::size_t,
-) -> Result<&'memory mut [T], host::__wasi_errno_t> {
-    let (len, len_bytes) = check_slice_of::<T>(ptr, len)?;
-    let ptr = dec_ptr_mut(memory, ptr, len_bytes)? as *mut T;
-    Ok(unsafe { slice::from_raw_parts_mut(ptr, len) })
-}
-
-pub fn enc_slice_of<T>(
-    memory: &mut [u8],
+pub fn enc_slice_of<T: GuestType>(
+    memory: &dyn GuestMemory,
     slice: &[T],
-    ptr: wasm32::uintptr_t,
+    ptr: GuestAddr,
 ) -> Result<(), host::__wasi_errno_t> {
-    // check alignment
-    if ptr as usize % align_of::<T>() != 0 {
-        return Err(host::__WASI_EINVAL);
-    }
-    // check that length doesn't overflow
-    let len_bytes = if let Some(len) = size_of::<T>().checked_mul(slice.len()) {
-        len
-    } else {
-        return Err(host::__WASI_EOVERFLOW);
-    };
-
-    // get the pointer into guest memory, and copy the bytes
-    let ptr = dec_ptr_mut(memory, ptr, len_bytes)? as *mut libc::c_void;
-    unsafe {
-        libc::memcpy(ptr, slice.as_ptr() as *const libc::c_void, len_bytes);
-    }
-
+    let len = GuestSize::try_from(slice.len()).map_err(|_| host::__WASI_EOVERFLOW)?;
+    GuestSlice::new(memory, ptr, len)?.copy_from_slice(slice);
     Ok(())
 }
 
-macro_rules! dec_enc_scalar {
-    ( $ty:ident, $dec:ident, $dec_byref:ident, $enc:ident, $enc_byref:ident) => {
-        pub fn $dec(x: wasm32::$ty) -> host::$ty {
-            host::$ty::from_le(x)
-        }
-
-        pub fn $dec_byref(
-            memory: &mut [u8],
-            ptr: wasm32::uintptr_t,
-        ) -> Result<host::$ty, host::__wasi_errno_t> {
-            dec_pointee::<wasm32::$ty>(memory, ptr).map($dec)
-        }
-
-        pub fn $enc(x: host::$ty) -> wasm32::$ty {
-            x.to_le()
-        }
-
-        pub fn $enc_byref(
-            memory: &mut [u8],
-            ptr: wasm32::uintptr_t,
-            x: host::$ty,
-        ) -> Result<(), host::__wasi_errno_t> {
-            enc_pointee::<wasm32::$ty>(memory, ptr, $enc(x))
-        }
-    };
-}
-
-pub fn dec_ciovec(
-    memory: &[u8],
-    ciovec: &wasm32::__wasi_ciovec_t,
-) -> Result<host::__wasi_ciovec_t, host::__wasi_errno_t> {
-    let len = dec_usize(ciovec.buf_len);
-    Ok(host::__wasi_ciovec_t {
-        buf: dec_ptr(memory, ciovec.buf, len)? as *const host::void,
-        buf_len: len,
-    })
+/// Reads a path from guest memory.
+///
+/// NB WASI spec requires paths to be valid UTF-8. Otherwise, `__WASI_EILSEQ` error is returned.
+pub fn dec_path(
+    memory: &dyn GuestMemory,
+    ptr: GuestAddr,
+    len: GuestSize,
+) -> Result<String, host::__wasi_errno_t> {
+    dec_slice_of::<u8>(memory, ptr, len).and_then(|path| host::path_from_vec(path.to_vec()))
 }
 
-pub fn dec_ciovec_slice(
-    memory: &[u8],
-    ptr: wasm32::uintptr_t,
-    len: wasm32::size_t,
-) -> Result<Vec<host::__wasi_ciovec_t>, host::__wasi_errno_t> {
-    let slice = dec_slice_of::<wasm32::__wasi_ciovec_t>(memory, ptr, len)?;
-    slice.iter().map(|iov| dec_ciovec(memory, iov)).collect()
-}
+witx_scalar_codecs!(
+    host: "witx/preview1/typenames.witx",
+    wasm32: "witx/preview0/typenames.witx"
+);
 
-pub fn dec_iovec(
-    memory: &[u8],
-    iovec: &wasm32::__wasi_iovec_t,
-) -> Result<host::__wasi_iovec_t, host::__wasi_errno_t> {
-    let len = dec_usize(iovec.buf_len);
-    Ok(host::__wasi_iovec_t {
-        buf: dec_ptr(memory, iovec.buf, len)? as *mut host::void,
-        buf_len: len,
-    })
+/// Reads an array of `__wasi_ciovec_t` or `__wasi_iovec_t`, which have the same layout, as pairs
+/// of a buffer and its length.
+fn dec_bufs(
+    memory: &dyn GuestMemory,
+    ptr: GuestAddr,
+    len: GuestSize,
+) -> Result<Vec<(GuestAddr, GuestSize)>, host::__wasi_errno_t> {
+    match memory.abi() {
+        GuestAbi::Wasm32 => Ok(dec_slice_of::<wasm32::__wasi_ciovec_t>(memory, ptr, len)?
+            .iter()
+            .map(|iov| iov.read())
+            .map(|iov| {
+                (
+                    GuestAddr::from(u32::from_le(iov.buf)),
+                    GuestSize::from(u32::from_le(iov.buf_len)),
+                )
+            })
+            .collect()),
+        GuestAbi::Wasm64 => Ok(dec_slice_of::<wasm64::__wasi_ciovec_t>(memory, ptr, len)?
+            .iter()
+            .map(|iov| iov.read())
+            .map(|iov| (u64::from_le(iov.buf), u64::from_le(iov.buf_len)))
+            .collect()),
+    }
 }
 
-pub fn dec_iovec_slice(
-    memory: &[u8],
-    ptr: wasm32::uintptr_t,
-    len: wasm32::size_t,
-) -> Result<Vec<host::__wasi_iovec_t>, host::__wasi_errno_t> {
-    let slice = dec_slice_of::<wasm32::__wasi_iovec_t>(memory, ptr, len)?;
-    slice.iter().map(|iov| dec_iovec(memory, iov)).collect()
+pub fn dec_ciovec<'a>(
+    memory: &'a dyn GuestMemory,
+    borrows: &mut GuestBorrows,
+    buf: GuestAddr,
+    buf_len: GuestSize,
+) -> Result<GuestSlice<'a, u8>, host::__wasi_errno_t> {
+    let buf = dec_slice_of::<u8>(memory, buf, buf_len)?;
+    borrows.borrow(&buf)?;
+    Ok(buf)
+}
+
+/// Decodes an array of buffers the host is going to read from.
+pub fn dec_ciovec_slice<'a>(
+    memory: &'a dyn GuestMemory,
+    borrows: &mut GuestBorrows,
+    ptr: GuestAddr,
+    len: GuestSize,
+) -> Result<Vec<GuestSlice<'a, u8>>, host::__wasi_errno_t> {
+    dec_bufs(memory, ptr, len)?
+        .into_iter()
+        .map(|(buf, buf_len)| dec_ciovec(memory, borrows, buf, buf_len))
+        .collect()
+}
+
+pub fn dec_iovec<'a>(
+    memory: &'a dyn GuestMemory,
+    borrows: &mut GuestBorrows,
+    buf: GuestAddr,
+    buf_len: GuestSize,
+) -> Result<GuestSlice<'a, u8>, host::__wasi_errno_t> {
+    let buf = dec_slice_of::<u8>(memory, buf, buf_len)?;
+    borrows.borrow_mut(&buf)?;
+    Ok(buf)
+}
+
+/// Decodes an array of buffers the host is going to write to. The buffers may not overlap,
+/// neither with each other nor with anything else registered in `borrows`.
+pub fn dec_iovec_slice<'a>(
+    memory: &'a dyn GuestMemory,
+    borrows: &mut GuestBorrows,
+    ptr: GuestAddr,
+    len: GuestSize,
+) -> Result<Vec<GuestSlice<'a, u8>>, host::__wasi_errno_t> {
+    dec_bufs(memory, ptr, len)?
+        .into_iter()
+        .map(|(buf, buf_len)| dec_iovec(memory, borrows, buf, buf_len))
+        .collect()
+}
+
+pub fn dec_linkcount(linkcount: wasm32::__wasi_linkcount_t) -> host::__wasi_linkcount_t {
+    host::__wasi_linkcount_t::from(u32::from_le(linkcount))
+}
+
+pub fn enc_linkcount(
+    linkcount: host::__wasi_linkcount_t,
+) -> Result<wasm32::__wasi_linkcount_t, host::__wasi_errno_t> {
+    wasm32::__wasi_linkcount_t::try_from(linkcount)
+        .map(u32::to_le)
+        .map_err(|_| host::__WASI_EOVERFLOW)
 }
 
-dec_enc_scalar!(
-    __wasi_clockid_t,
-    dec_clockid,
-    dec_clockid_byref,
-    enc_clockid,
-    enc_clockid_byref
-);
-dec_enc_scalar!(
-    __wasi_errno_t,
-    dec_errno,
-    dec_errno_byref,
-    enc_errno,
-    enc_errno_byref
-);
-dec_enc_scalar!(
-    __wasi_exitcode_t,
-    dec_exitcode,
-    dec_exitcode_byref,
-    enc_exitcode,
-    enc_exitcode_byref
-);
-dec_enc_scalar!(__wasi_fd_t, dec_fd, dec_fd_byref, enc_fd, enc_fd_byref);
-dec_enc_scalar!(
-    __wasi_fdflags_t,
-    dec_fdflags,
-    dec_fdflags_byref,
-    enc_fdflags,
-    enc_fdflags_byref
-);
-dec_enc_scalar!(
-    __wasi_device_t,
-    dec_device,
-    dev_device_byref,
-    enc_device,
-    enc_device_byref
-);
-dec_enc_scalar!(
-    __wasi_inode_t,
-    dec_inode,
-    dev_inode_byref,
-    enc_inode,
-    enc_inode_byref
-);
-dec_enc_scalar!(
-    __wasi_linkcount_t,
-    dec_linkcount,
-    dev_linkcount_byref,
-    enc_linkcount,
-    enc_linkcount_byref
-);
-
 pub fn dec_filestat(filestat: wasm32::__wasi_filestat_t) -> host::__wasi_filestat_t {
     host::__wasi_filestat_t {
         st_dev: dec_device(filestat.st_dev),
@@ -272,19 +165,56 @@ pub fn dec_filestat(filestat: wasm32::__wasi_filestat_t) -> host::__wasi_filesta
     }
 }
 
+pub fn dec_filestat_preview1(filestat: preview1::__wasi_filestat_t) -> host::__wasi_filestat_t {
+    host::__wasi_filestat_t {
+        st_dev: dec_device(filestat.st_dev),
+        st_ino: dec_inode(filestat.st_ino),
+        st_filetype: dec_filetype(filestat.st_filetype),
+        st_nlink: u64::from_le(filestat.st_nlink),
+        st_size: dec_filesize(filestat.st_size),
+        st_atim: dec_timestamp(filestat.st_atim),
+        st_mtim: dec_timestamp(filestat.st_mtim),
+        st_ctim: dec_timestamp(filestat.st_ctim),
+    }
+}
+
 pub fn dec_filestat_byref(
-    memory: &mut [u8],
-    filestat_ptr: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    snapshot: WasiSnapshot,
+    filestat_ptr: GuestAddr,
 ) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t> {
-    dec_pointee::<wasm32::__wasi_filestat_t>(memory, filestat_ptr).map(dec_filestat)
+    match snapshot {
+        WasiSnapshot::Unstable => {
+            dec_pointee::<wasm32::__wasi_filestat_t>(memory, filestat_ptr).map(dec_filestat)
+        }
+        WasiSnapshot::Preview1 => dec_pointee::<preview1::__wasi_filestat_t>(memory, filestat_ptr)
+            .map(dec_filestat_preview1),
+    }
 }
 
-pub fn enc_filestat(filestat: host::__wasi_filestat_t) -> wasm32::__wasi_filestat_t {
-    wasm32::__wasi_filestat_t {
+pub fn enc_filestat(
+    filestat: host::__wasi_filestat_t,
+) -> Result<wasm32::__wasi_filestat_t, host::__wasi_errno_t> {
+    Ok(wasm32::__wasi_filestat_t {
         st_dev: enc_device(filestat.st_dev),
         st_ino: enc_inode(filestat.st_ino),
         st_filetype: enc_filetype(filestat.st_filetype),
-        st_nlink: enc_linkcount(filestat.st_nlink),
+        __padding_0: [0; 3],
+        st_nlink: enc_linkcount(filestat.st_nlink)?,
+        st_size: enc_filesize(filestat.st_size),
+        st_atim: enc_timestamp(filestat.st_atim),
+        st_mtim: enc_timestamp(filestat.st_mtim),
+        st_ctim: enc_timestamp(filestat.st_ctim),
+    })
+}
+
+pub fn enc_filestat_preview1(filestat: host::__wasi_filestat_t) -> preview1::__wasi_filestat_t {
+    preview1::__wasi_filestat_t {
+        st_dev: enc_device(filestat.st_dev),
+        st_ino: enc_inode(filestat.st_ino),
+        st_filetype: enc_filetype(filestat.st_filetype),
+        __padding_0: [0; 7],
+        st_nlink: filestat.st_nlink.to_le(),
         st_size: enc_filesize(filestat.st_size),
         st_atim: enc_timestamp(filestat.st_atim),
         st_mtim: enc_timestamp(filestat.st_mtim),
@@ -293,12 +223,21 @@ pub fn enc_filestat(filestat: host::__wasi_filestat_t) -> wasm32::__wasi_filesta
 }
 
 pub fn enc_filestat_byref(
-    memory: &mut [u8],
-    filestat_ptr: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    snapshot: WasiSnapshot,
+    filestat_ptr: GuestAddr,
     host_filestat: host::__wasi_filestat_t,
 ) -> Result<(), host::__wasi_errno_t> {
-    let filestat = enc_filestat(host_filestat);
-    enc_pointee::<wasm32::__wasi_filestat_t>(memory, filestat_ptr, filestat)
+    match snapshot {
+        WasiSnapshot::Unstable => {
+            let filestat = enc_filestat(host_filestat)?;
+            enc_pointee::<wasm32::__wasi_filestat_t>(memory, filestat_ptr, filestat)
+        }
+        WasiSnapshot::Preview1 => {
+            let filestat = enc_filestat_preview1(host_filestat);
+            enc_pointee::<preview1::__wasi_filestat_t>(memory, filestat_ptr, filestat)
+        }
+    }
 }
 
 pub fn dec_fdstat(fdstat: wasm32::__wasi_fdstat_t) -> host::__wasi_fdstat_t {
@@ -311,8 +250,8 @@ pub fn dec_fdstat(fdstat: wasm32::__wasi_fdstat_t) -> host::__wasi_fdstat_t {
 }
 
 pub fn dec_fdstat_byref(
-    memory: &mut [u8],
-    fdstat_ptr: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    fdstat_ptr: GuestAddr,
 ) -> Result<host::__wasi_fdstat_t, host::__wasi_errno_t> {
     dec_pointee::<wasm32::__wasi_fdstat_t>(memory, fdstat_ptr).map(dec_fdstat)
 }
@@ -320,68 +259,30 @@ pub fn dec_fdstat_byref(
 pub fn enc_fdstat(fdstat: host::__wasi_fdstat_t) -> wasm32::__wasi_fdstat_t {
     wasm32::__wasi_fdstat_t {
         fs_filetype: enc_filetype(fdstat.fs_filetype),
+        __padding_0: [0; 1],
         fs_flags: enc_fdflags(fdstat.fs_flags),
-        __bindgen_padding_0: 0,
+        __padding_1: [0; 4],
         fs_rights_base: enc_rights(fdstat.fs_rights_base),
         fs_rights_inheriting: enc_rights(fdstat.fs_rights_inheriting),
     }
 }
 
 pub fn enc_fdstat_byref(
-    memory: &mut [u8],
-    fdstat_ptr: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    fdstat_ptr: GuestAddr,
     host_fdstat: host::__wasi_fdstat_t,
 ) -> Result<(), host::__wasi_errno_t> {
     let fdstat = enc_fdstat(host_fdstat);
     enc_pointee::<wasm32::__wasi_fdstat_t>(memory, fdstat_ptr, fdstat)
 }
 
-dec_enc_scalar!(
-    __wasi_filedelta_t,
-    dec_filedelta,
-    dec_filedelta_byref,
-    enc_filedelta,
-    enc_filedelta_byref
-);
-dec_enc_scalar!(
-    __wasi_filesize_t,
-    dec_filesize,
-    dec_filesize_byref,
-    enc_filesize,
-    enc_filesize_byref
-);
-
-dec_enc_scalar!(
-    __wasi_filetype_t,
-    dec_filetype,
-    dec_filetype_byref,
-    enc_filetype,
-    enc_filetype_byref
-);
-
-dec_enc_scalar!(
-    __wasi_lookupflags_t,
-    dec_lookupflags,
-    dec_lookupflags_byref,
-    enc_lookupflags,
-    enc_lookupflags_byref
-);
-
-dec_enc_scalar!(
-    __wasi_oflags_t,
-    dec_oflags,
-    dec_oflags_byref,
-    enc_oflags,
-    enc_oflags_byref
-);
-
 pub fn dec_prestat(
     prestat: wasm32::__wasi_prestat_t,
 ) -> Result<host::__wasi_prestat_t, host::__wasi_errno_t> {
     match prestat.pr_type {
         wasm32::__WASI_PREOPENTYPE_DIR => {
-            let u = host::__wasi_prestat_t___wasi_prestat_u {
-                dir: host::__wasi_prestat_t___wasi_prestat_u___wasi_prestat_u_dir_t {
+            let u = host::__wasi_prestat_u_t {
+                dir: host::__wasi_prestat_dir_t {
                     pr_name_len: dec_usize(unsafe { prestat.u.dir.pr_name_len }),
                 },
             };
@@ -394,11 +295,38 @@ pub fn dec_prestat(
     }
 }
 
+pub fn dec_prestat_wasm64(
+    prestat: wasm64::__wasi_prestat_t,
+) -> Result<host::__wasi_prestat_t, host::__wasi_errno_t> {
+    match prestat.pr_type {
+        wasm64::__WASI_PREOPENTYPE_DIR => {
+            let pr_name_len = u64::from_le(unsafe { prestat.u.dir.pr_name_len });
+            let u = host::__wasi_prestat_u_t {
+                dir: host::__wasi_prestat_dir_t {
+                    pr_name_len: usize::try_from(pr_name_len)
+                        .map_err(|_| host::__WASI_EOVERFLOW)?,
+                },
+            };
+            Ok(host::__wasi_prestat_t {
+                pr_type: host::__WASI_PREOPENTYPE_DIR,
+                u,
+            })
+        }
+        _ => Err(host::__WASI_EINVAL),
+    }
+}
+
 pub fn dec_prestat_byref(
-    memory: &mut [u8],
-    prestat_ptr: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    prestat_ptr: GuestAddr,
 ) -> Result<host::__wasi_prestat_t, host::__wasi_errno_t> {
-    dec_pointee::<wasm32::__wasi_prestat_t>(memory, prestat_ptr).and_then(dec_prestat)
+    match memory.abi() {
+        GuestAbi::Wasm32 => {
+            dec_pointee::<wasm32::__wasi_prestat_t>(memory, prestat_ptr).and_then(dec_prestat)
+        }
+        GuestAbi::Wasm64 => dec_pointee::<wasm64::__wasi_prestat_t>(memory, prestat_ptr)
+            .and_then(dec_prestat_wasm64),
+    }
 }
 
 pub fn enc_prestat(
@@ -406,13 +334,38 @@ pub fn enc_prestat(
 ) -> Result<wasm32::__wasi_prestat_t, host::__wasi_errno_t> {
     match prestat.pr_type {
         host::__WASI_PREOPENTYPE_DIR => {
-            let u = wasm32::__wasi_prestat_t___wasi_prestat_u {
-                dir: wasm32::__wasi_prestat_t___wasi_prestat_u___wasi_prestat_u_dir_t {
-                    pr_name_len: enc_usize(unsafe { prestat.u.dir.pr_name_len }),
-                },
+            // see `GuestType`: the bytes no variant covers must be initialized
+            let mut u: wasm32::__wasi_prestat_u_t = unsafe { mem::zeroed() };
+            u.dir = wasm32::__wasi_prestat_dir_t {
+                pr_name_len: wasm32::size_t::try_from(unsafe { prestat.u.dir.pr_name_len })
+                    .map_err(|_| host::__WASI_EOVERFLOW)?
+                    .to_le(),
             };
             Ok(wasm32::__wasi_prestat_t {
                 pr_type: wasm32::__WASI_PREOPENTYPE_DIR,
+                __padding_0: [0; 3],
+                u,
+            })
+        }
+        _ => Err(host::__WASI_EINVAL),
+    }
+}
+
+pub fn enc_prestat_wasm64(
+    prestat: host::__wasi_prestat_t,
+) -> Result<wasm64::__wasi_prestat_t, host::__wasi_errno_t> {
+    match prestat.pr_type {
+        host::__WASI_PREOPENTYPE_DIR => {
+            let pr_name_len = unsafe { prestat.u.dir.pr_name_len };
+            let mut u: wasm64::__wasi_prestat_u_t = unsafe { mem::zeroed() };
+            u.dir = wasm64::__wasi_prestat_dir_t {
+                pr_name_len: u64::try_from(pr_name_len)
+                    .map_err(|_| host::__WASI_EOVERFLOW)?
+                    .to_le(),
+            };
+            Ok(wasm64::__wasi_prestat_t {
+                pr_type: wasm64::__WASI_PREOPENTYPE_DIR,
+                __padding_0: [0; 7],
                 u,
             })
         }
@@ -421,29 +374,22 @@ pub fn enc_prestat(
 }
 
 pub fn enc_prestat_byref(
-    memory: &mut [u8],
-    prestat_ptr: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    prestat_ptr: GuestAddr,
     host_prestat: host::__wasi_prestat_t,
 ) -> Result<(), host::__wasi_errno_t> {
-    let prestat = enc_prestat(host_prestat)?;
-    enc_pointee::<wasm32::__wasi_prestat_t>(memory, prestat_ptr, prestat)
+    match memory.abi() {
+        GuestAbi::Wasm32 => {
+            let prestat = enc_prestat(host_prestat)?;
+            enc_pointee::<wasm32::__wasi_prestat_t>(memory, prestat_ptr, prestat)
+        }
+        GuestAbi::Wasm64 => {
+            let prestat = enc_prestat_wasm64(host_prestat)?;
+            enc_pointee::<wasm64::__wasi_prestat_t>(memory, prestat_ptr, prestat)
+        }
+    }
 }
 
-dec_enc_scalar!(
-    __wasi_rights_t,
-    dec_rights,
-    dec_rights_byref,
-    enc_rights,
-    enc_rights_byref
-);
-dec_enc_scalar!(
-    __wasi_timestamp_t,
-    dec_timestamp,
-    dec_timestamp_byref,
-    enc_timestamp,
-    enc_timestamp_byref
-);
-
 pub fn dec_u32(x: u32) -> u32 {
     u32::from_le(x)
 }
@@ -460,53 +406,83 @@ pub fn enc_usize(size: usize) -> wasm32::size_t {
     wasm32::size_t::try_from(size).unwrap()
 }
 
+/// Writes a `size_t` of the guest's ABI.
 pub fn enc_usize_byref(
-    memory: &mut [u8],
-    usize_ptr: wasm32::uintptr_t,
+    memory: &dyn GuestMemory,
+    usize_ptr: GuestAddr,
     host_usize: usize,
 ) -> Result<(), host::__wasi_errno_t> {
-    enc_pointee::<wasm32::size_t>(memory, usize_ptr, enc_usize(host_usize))
+    match memory.abi() {
+        GuestAbi::Wasm32 => {
+            let size = wasm32::size_t::try_from(host_usize).map_err(|_| host::__WASI_EOVERFLOW)?;
+            enc_pointee::<wasm32::size_t>(memory, usize_ptr, size.to_le())
+        }
+        GuestAbi::Wasm64 => {
+            let size = wasm64::size_t::try_from(host_usize).map_err(|_| host::__WASI_EOVERFLOW)?;
+            enc_pointee::<wasm64::size_t>(memory, usize_ptr, size.to_le())
+        }
+    }
 }
 
-dec_enc_scalar!(
-    __wasi_whence_t,
-    dec_whence,
-    dec_whence_byref,
-    enc_whence,
-    enc_whence_byref
-);
-
-dec_enc_scalar!(
-    __wasi_subclockflags_t,
-    dec_subclockflags,
-    dec_subclockflags_byref,
-    enc_subclockflags,
-    enc_subclockflags_byref
-);
+/// Registers a `size_t` of the guest's ABI in `borrows` as one the host is going to write to.
+pub fn borrow_usize_mut(
+    memory: &dyn GuestMemory,
+    borrows: &mut GuestBorrows,
+    usize_ptr: GuestAddr,
+) -> Result<(), host::__wasi_errno_t> {
+    match memory.abi() {
+        GuestAbi::Wasm32 => {
+            borrows.borrow_ptr_mut(&GuestPtr::<wasm32::size_t>::new(memory, usize_ptr)?)
+        }
+        GuestAbi::Wasm64 => {
+            borrows.borrow_ptr_mut(&GuestPtr::<wasm64::size_t>::new(memory, usize_ptr)?)
+        }
+    }
+}
 
-dec_enc_scalar!(
-    __wasi_eventrwflags_t,
-    dec_eventrwflags,
-    dec_eventrwflags_byref,
-    enc_eventrwflags,
-    enc_eventrwflags_byref
-);
+/// Writes an array of pointers of the guest's ABI.
+pub fn enc_ptr_slice(
+    memory: &dyn GuestMemory,
+    ptrs: &[GuestAddr],
+    ptr: GuestAddr,
+) -> Result<(), host::__wasi_errno_t> {
+    match memory.abi() {
+        GuestAbi::Wasm32 => {
+            let ptrs = ptrs
+                .iter()
+                .map(|&p| wasm32::uintptr_t::try_from(p).map(u32::to_le))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| host::__WASI_EOVERFLOW)?;
+            enc_slice_of(memory, &ptrs, ptr)
+        }
+        GuestAbi::Wasm64 => {
+            let ptrs: Vec<wasm64::uintptr_t> = ptrs.iter().map(|p| p.to_le()).collect();
+            enc_slice_of(memory, &ptrs, ptr)
+        }
+    }
+}
 
-dec_enc_scalar!(
-    __wasi_eventtype_t,
-    dec_eventtype,
-    dec_eventtype_byref,
-    enc_eventtype,
-    enc_eventtype_byref
-);
+pub fn dec_whence(
+    whence: wasm32::__wasi_whence_t,
+) -> Result<host::__wasi_whence_t, host::__wasi_errno_t> {
+    match whence {
+        wasm32::__WASI_WHENCE_CUR => Ok(host::__WASI_WHENCE_CUR),
+        wasm32::__WASI_WHENCE_END => Ok(host::__WASI_WHENCE_END),
+        wasm32::__WASI_WHENCE_SET => Ok(host::__WASI_WHENCE_SET),
+        _ => Err(host::__WASI_EINVAL),
+    }
+}
 
-dec_enc_scalar!(
-    __wasi_userdata_t,
-    dec_userdata,
-    dec_userdata_byref,
-    enc_userdata,
-    enc_userdata_byref
-);
+pub fn dec_whence_preview1(
+    whence: preview1::__wasi_whence_t,
+) -> Result<host::__wasi_whence_t, host::__wasi_errno_t> {
+    match whence {
+        preview1::__WASI_WHENCE_SET => Ok(host::__WASI_WHENCE_SET),
+        preview1::__WASI_WHENCE_CUR => Ok(host::__WASI_WHENCE_CUR),
+        preview1::__WASI_WHENCE_END => Ok(host::__WASI_WHENCE_END),
+        _ => Err(host::__WASI_EINVAL),
+    }
+}
 
 pub fn dec_subscription(
     subscription: &wasm32::__wasi_subscription_t,
@@ -515,10 +491,9 @@ pub fn dec_subscription(
     let type_ = dec_eventtype(subscription.type_);
     let u_orig = subscription.u;
     let u = match type_ {
-        wasm32::__WASI_EVENTTYPE_CLOCK => host::__wasi_subscription_t___wasi_subscription_u {
+        wasm32::__WASI_EVENTTYPE_CLOCK => host::__wasi_subscription_u_t {
             clock: unsafe {
-                host::__wasi_subscription_t___wasi_subscription_u___wasi_subscription_u_clock_t {
-                    identifier: dec_userdata(u_orig.clock.identifier),
+                host::__wasi_subscription_clock_t {
                     clock_id: dec_clockid(u_orig.clock.clock_id),
                     timeout: dec_timestamp(u_orig.clock.timeout),
                     precision: dec_timestamp(u_orig.clock.precision),
@@ -526,53 +501,80 @@ pub fn dec_subscription(
                 }
             },
         },
-        wasm32::__WASI_EVENTTYPE_FD_READ | wasm32::__WASI_EVENTTYPE_FD_WRITE =>  host::__wasi_subscription_t___wasi_subscription_u {
-            fd_readwrite:  host::__wasi_subscription_t___wasi_subscription_u___wasi_subscription_u_fd_readwrite_t {
-                fd: dec_fd(unsafe{u_orig.fd_readwrite.fd})
+        wasm32::__WASI_EVENTTYPE_FD_READ | wasm32::__WASI_EVENTTYPE_FD_WRITE => {
+            host::__wasi_subscription_u_t {
+                fd_readwrite: host::__wasi_subscription_fd_readwrite_t {
+                    fd: dec_fd(unsafe { u_orig.fd_readwrite.fd }),
+                },
             }
+        }
+        _ => return Err(wasm32::__WASI_EINVAL),
+    };
+    Ok(host::__wasi_subscription_t { userdata, type_, u })
+}
+
+pub fn dec_subscription_preview1(
+    subscription: &preview1::__wasi_subscription_t,
+) -> Result<host::__wasi_subscription_t, host::__wasi_errno_t> {
+    let userdata = dec_userdata(subscription.userdata);
+    let type_ = dec_eventtype(subscription.type_);
+    let u_orig = subscription.u;
+    let u = match type_ {
+        preview1::__WASI_EVENTTYPE_CLOCK => host::__wasi_subscription_u_t {
+            clock: unsafe {
+                host::__wasi_subscription_clock_t {
+                    clock_id: dec_clockid(u_orig.clock.clock_id),
+                    timeout: dec_timestamp(u_orig.clock.timeout),
+                    precision: dec_timestamp(u_orig.clock.precision),
+                    flags: dec_subclockflags(u_orig.clock.flags),
+                }
+            },
         },
-        _  => return Err(wasm32::__WASI_EINVAL)
+        preview1::__WASI_EVENTTYPE_FD_READ | preview1::__WASI_EVENTTYPE_FD_WRITE => {
+            host::__wasi_subscription_u_t {
+                fd_readwrite: host::__wasi_subscription_fd_readwrite_t {
+                    fd: dec_fd(unsafe { u_orig.fd_readwrite.fd }),
+                },
+            }
+        }
+        _ => return Err(host::__WASI_EINVAL),
     };
     Ok(host::__wasi_subscription_t { userdata, type_, u })
 }
 
+/// Decodes an array of subscriptions, skipping those of an unknown event type.
+pub fn dec_subscription_slice(
+    memory: &dyn GuestMemory,
+    snapshot: WasiSnapshot,
+    ptr: GuestAddr,
+    len: GuestSize,
+) -> Result<Vec<host::__wasi_subscription_t>, host::__wasi_errno_t> {
+    match snapshot {
+        WasiSnapshot::Unstable => Ok(dec_slice_of::<wasm32::__wasi_subscription_t>(
+            memory, ptr, len,
+        )?
+        .iter()
+        .filter_map(|subscription| dec_subscription(&subscription.read()).ok())
+        .collect()),
+        WasiSnapshot::Preview1 => Ok(dec_slice_of::<preview1::__wasi_subscription_t>(
+            memory, ptr, len,
+        )?
+        .iter()
+        .filter_map(|subscription| dec_subscription_preview1(&subscription.read()).ok())
+        .collect()),
+    }
+}
+
 pub fn enc_event(event: host::__wasi_event_t) -> wasm32::__wasi_event_t {
-    let fd_readwrite = unsafe { event.u.fd_readwrite };
     wasm32::__wasi_event_t {
         userdata: enc_userdata(event.userdata),
-        type_: enc_eventtype(event.type_),
         error: enc_errno(event.error),
-        u: wasm32::__wasi_event_t___wasi_event_u {
-            fd_readwrite: wasm32::__wasi_event_t___wasi_event_u___wasi_event_u_fd_readwrite_t {
-                nbytes: enc_filesize(fd_readwrite.nbytes),
-                flags: enc_eventrwflags(fd_readwrite.flags),
-                __bindgen_padding_0: [0; 3],
-            },
+        type_: enc_eventtype(event.type_),
+        __padding_0: [0; 5],
+        fd_readwrite: wasm32::__wasi_event_fd_readwrite_t {
+            nbytes: enc_filesize(event.fd_readwrite.nbytes),
+            flags: enc_eventrwflags(event.fd_readwrite.flags),
+            __padding_0: [0; 6],
         },
-        __bindgen_padding_0: 0,
     }
 }
-
-dec_enc_scalar!(
-    __wasi_advice_t,
-    dec_advice,
-    dec_advice_byref,
-    enc_advice,
-    enc_advice_byref
-);
-
-dec_enc_scalar!(
-    __wasi_fstflags_t,
-    dec_fstflags,
-    dec_fstflags_byref,
-    enc_fstflags,
-    enc_fstflags_byref
-);
-
-dec_enc_scalar!(
-    __wasi_dircookie_t,
-    dec_dircookie,
-    dec_dircookie_byref,
-    enc_dircookie,
-    enc_dircookie_byref
-);
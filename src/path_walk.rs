@@ -0,0 +1,147 @@
+//! Resolution of guest paths beneath a directory, shared by host and virtual directories.
+//!
+//! This is a workaround for not having Capsicum support in the OS: paths are resolved one
+//! component at a time, expanding symlinks by hand, so that resolution never escapes the
+//! directory it starts from.
+use crate::host;
+
+/// A directory paths can be resolved in.
+pub(crate) trait Directory: Sized {
+    /// Opens the directory `path`, a single component possibly followed by a slash, without
+    /// following it if it's a symlink, in which case this fails with `__WASI_ELOOP`,
+    /// `__WASI_EMLINK` or `__WASI_ENOTDIR`.
+    fn open_dir(&self, path: &str) -> Result<Self, host::__wasi_errno_t>;
+
+    /// Reads the target of the symlink `path`, failing with `__WASI_EINVAL` if it isn't one.
+    fn read_link(&self, path: &str) -> Result<String, host::__wasi_errno_t>;
+}
+
+/// Resolves `path` relative to `dir`, and returns the directory containing its final component,
+/// along with that component.
+pub(crate) fn path_get_walk<D: Directory>(
+    dir: D,
+    dirflags: host::__wasi_lookupflags_t,
+    path: &str,
+    needs_final_component: bool,
+) -> Result<(D, String), host::__wasi_errno_t> {
+    const MAX_SYMLINK_EXPANSIONS: usize = 128;
+
+    // Stack of directories. Index 0 always corresponds with the directory provided to this
+    // function. Entering a directory causes it to be pushed, while handling ".." entries causes
+    // an entry to be popped. Index 0 cannot be popped, as this would imply escaping the base
+    // directory.
+    let mut dir_stack = vec![dir];
+
+    // Stack of paths left to process. This is initially the `path` argument to this function, but
+    // any symlinks we encounter are processed by pushing them on the stack.
+    let mut path_stack = vec![path.to_owned()];
+
+    // Track the number of symlinks we've expanded, so we can return `ELOOP` after too many.
+    let mut symlink_expansions = 0;
+
+    // Queues the target of the symlink `head` for processing.
+    let mut expand = |path_stack: &mut Vec<String>, head: &str, mut link_path: String| {
+        symlink_expansions += 1;
+        if symlink_expansions > MAX_SYMLINK_EXPANSIONS {
+            return Err(host::__WASI_ELOOP);
+        }
+        if head.ends_with('/') {
+            link_path.push('/');
+        }
+        path_stack.push(link_path);
+        Ok(())
+    };
+
+    loop {
+        let cur_path = match path_stack.pop() {
+            Some(cur_path) => cur_path,
+            None => {
+                // no further components to process. means we've hit a case like "." or "a/..",
+                // or if the input path has trailing slashes and `needs_final_component` is not set
+                return Ok((
+                    dir_stack.pop().ok_or(host::__WASI_ENOTCAPABLE)?,
+                    String::from("."),
+                ));
+            }
+        };
+
+        if cur_path.starts_with('/') {
+            // path is absolute!
+            return Err(host::__WASI_ENOTCAPABLE);
+        }
+
+        let ends_with_slash = cur_path.ends_with('/');
+        let mut components = cur_path.split('/').filter(|c| !c.is_empty());
+        let head = match components.next() {
+            None => return Err(host::__WASI_ENOENT),
+            Some(head) => head,
+        };
+        let tail = components.collect::<Vec<_>>();
+
+        if !tail.is_empty() {
+            let mut tail = tail.join("/");
+            if ends_with_slash {
+                tail.push('/');
+            }
+            path_stack.push(tail);
+        }
+
+        match head {
+            "." => continue,
+            ".." => {
+                let _ = dir_stack.pop().ok_or(host::__WASI_ENOTCAPABLE)?;
+
+                // we're not allowed to pop past the original directory
+                if dir_stack.is_empty() {
+                    return Err(host::__WASI_ENOTCAPABLE);
+                }
+            }
+            head => {
+                let mut head = head.to_owned();
+                if ends_with_slash {
+                    // preserve trailing slash
+                    head.push('/');
+                }
+                let cur_dir = dir_stack.last().ok_or(host::__WASI_ENOTCAPABLE)?;
+
+                if !path_stack.is_empty() || (ends_with_slash && !needs_final_component) {
+                    match cur_dir.open_dir(&head) {
+                        Ok(new_dir) => {
+                            dir_stack.push(new_dir);
+                            continue;
+                        }
+                        // Check to see if it was a symlink. Linux indicates this with ENOTDIR
+                        // because of the O_DIRECTORY flag.
+                        Err(e)
+                            if e == host::__WASI_ELOOP
+                                || e == host::__WASI_EMLINK
+                                || e == host::__WASI_ENOTDIR =>
+                        {
+                            let link_path = cur_dir.read_link(&head)?;
+                            expand(&mut path_stack, &head, link_path)?;
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                } else if ends_with_slash || (dirflags & host::__WASI_LOOKUP_SYMLINK_FOLLOW) != 0 {
+                    // if there's a trailing slash, or if `LOOKUP_SYMLINK_FOLLOW` is set, attempt
+                    // symlink expansion
+                    match cur_dir.read_link(&head) {
+                        Ok(link_path) => {
+                            expand(&mut path_stack, &head, link_path)?;
+                            continue;
+                        }
+                        Err(e) => {
+                            if e != host::__WASI_EINVAL && e != host::__WASI_ENOENT {
+                                return Err(e);
+                            }
+                        }
+                    }
+                }
+
+                // not a symlink, so we're done;
+                return Ok((dir_stack.pop().ok_or(host::__WASI_ENOTCAPABLE)?, head));
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Sources of randomness for `random_get`.
+use crate::host;
+use rand::{CryptoRng, RngCore};
+use std::fmt;
+use std::sync::Mutex;
+
+/// A cryptographically secure random number generator the guest's `random_get` draws from.
+///
+/// Implemented for every `RngCore + CryptoRng` which can be sent across threads, such as
+/// `rand::rngs::OsRng`, the default.
+pub trait WasiRng: RngCore + CryptoRng + Send {}
+
+impl<R: RngCore + CryptoRng + Send> WasiRng for R {}
+
+pub(crate) struct RngProvider(Mutex<Box<dyn WasiRng>>);
+
+impl RngProvider {
+    pub(crate) fn new(rng: Box<dyn WasiRng>) -> Self {
+        RngProvider(Mutex::new(rng))
+    }
+
+    pub(crate) fn fill_bytes(&self, buf: &mut [u8]) -> Result<(), host::__wasi_errno_t> {
+        // a panic while filling a buffer doesn't leave the generator in an unusable state
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .try_fill_bytes(buf)
+            .map_err(|_| host::__WASI_EIO)
+    }
+}
+
+impl fmt::Debug for RngProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RngProvider").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hostcalls, SliceMemory, WasiCtx, WasiCtxBuilder};
+
+    /// Counts the bytes it has produced, and fails once it has produced `limit` of them.
+    struct Counter {
+        produced: u8,
+        limit: u8,
+    }
+
+    impl RngCore for Counter {
+        fn next_u32(&mut self) -> u32 {
+            let mut bytes = [0; 4];
+            self.fill_bytes(&mut bytes);
+            u32::from_le_bytes(bytes)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            let mut bytes = [0; 8];
+            self.fill_bytes(&mut bytes);
+            u64::from_le_bytes(bytes)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.try_fill_bytes(dest).unwrap()
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            if dest.len() > usize::from(self.limit - self.produced) {
+                return Err(rand::Error::new(rand::ErrorKind::Unavailable, "exhausted"));
+            }
+            for byte in dest {
+                *byte = self.produced;
+                self.produced += 1;
+            }
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for Counter {}
+
+    fn random_get(ctx: &WasiCtx, len: usize) -> (host::__wasi_errno_t, Vec<u8>) {
+        let mut mem = vec![0; len];
+        let errno = hostcalls::random_get(ctx, &SliceMemory::new(&mut mem), 0, len as _);
+        (errno, mem)
+    }
+
+    #[test]
+    fn custom_rng() {
+        let rng = Counter {
+            produced: 0,
+            limit: 8,
+        };
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .rng(Box::new(rng))
+            .build()
+            .unwrap();
+        assert_eq!(
+            random_get(&ctx, 4),
+            (host::__WASI_ESUCCESS, vec![0, 1, 2, 3])
+        );
+        assert_eq!(random_get(&ctx, 2), (host::__WASI_ESUCCESS, vec![4, 5]));
+        assert_eq!(random_get(&ctx, 4).0, host::__WASI_EIO);
+    }
+
+    #[test]
+    fn default_rng() {
+        let ctx = WasiCtxBuilder::new().unwrap().build().unwrap();
+        let (errno, first) = random_get(&ctx, 32);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        assert_ne!(first, vec![0; 32]);
+        assert_ne!(random_get(&ctx, 32).1, first);
+    }
+}
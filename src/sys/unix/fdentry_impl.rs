@@ -1,6 +1,7 @@
 use crate::fdentry::Descriptor;
 use crate::host;
 use crate::sys::errno_from_host;
+use nix::libc;
 
 use std::io;
 use std::os::unix::prelude::{AsRawFd, FileTypeExt, FromRawFd, RawFd};
@@ -12,6 +13,8 @@ impl AsRawFd for Descriptor {
             Descriptor::Stdin => io::stdin().as_raw_fd(),
             Descriptor::Stdout => io::stdout().as_raw_fd(),
             Descriptor::Stderr => io::stderr().as_raw_fd(),
+            // virtual files have no host descriptor, so any syscall on it fails with EBADF
+            Descriptor::VirtualFile(_) => -1,
         }
     }
 }
@@ -98,17 +101,31 @@ pub(crate) fn determine_type_rights<Fd: AsRawFd>(
                 host::RIGHTS_REGULAR_FILE_INHERITING,
             )
         } else if ft.is_socket() {
-            use nix::sys::socket;
-            match socket::getsockopt(fd.as_raw_fd(), socket::sockopt::SockType).map_err(|err| {
-                err.as_errno()
-                    .map_or(host::__WASI_EIO, |e| errno_from_host(e as i32))
-            })? {
-                socket::SockType::Datagram => (
+            // query SO_TYPE by hand, as the `nix` wrapper zero-initializes its `SockType` enum,
+            // which isn't a valid value of it
+            let mut sock_type: libc::c_int = 0;
+            let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let res = unsafe {
+                libc::getsockopt(
+                    fd.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    libc::SO_TYPE,
+                    &mut sock_type as *mut libc::c_int as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            if res != 0 {
+                return Err(io::Error::last_os_error()
+                    .raw_os_error()
+                    .map_or(host::__WASI_EIO, errno_from_host));
+            }
+            match sock_type {
+                libc::SOCK_DGRAM => (
                     host::__WASI_FILETYPE_SOCKET_DGRAM,
                     host::RIGHTS_SOCKET_BASE,
                     host::RIGHTS_SOCKET_INHERITING,
                 ),
-                socket::SockType::Stream => (
+                libc::SOCK_STREAM => (
                     host::__WASI_FILETYPE_SOCKET_STREAM,
                     host::RIGHTS_SOCKET_BASE,
                     host::RIGHTS_SOCKET_INHERITING,
@@ -210,7 +210,7 @@ pub fn filestat_from_nix(
     Ok(host::__wasi_filestat_t {
         st_dev: dev,
         st_ino: ino,
-        st_nlink: filestat.st_nlink as host::__wasi_linkcount_t,
+        st_nlink: host::__wasi_linkcount_t::from(filestat.st_nlink),
         st_size: filestat.st_size as host::__wasi_filesize_t,
         st_atim: filestat.st_atime as host::__wasi_timestamp_t,
         st_ctim: filestat.st_ctime as host::__wasi_timestamp_t,
@@ -6,7 +6,7 @@ use crate::fdentry::FdEntry;
 use crate::sys::errno_from_host;
 use crate::sys::fdentry_impl::determine_type_rights;
 use crate::sys::host_impl;
-use crate::{host, wasm32};
+use crate::{host, memory, wasm32};
 use nix::libc::{self, c_long, c_void, off_t};
 use std::ffi::CString;
 use std::fs::File;
@@ -31,39 +31,6 @@ pub(crate) fn fd_pwrite(
         .map_err(|e| e.raw_os_error().map_or(host::__WASI_EIO, errno_from_host))
 }
 
-pub(crate) fn fd_renumber(
-    wasi_ctx: &mut WasiCtx,
-    from: host::__wasi_fd_t,
-    to: host::__wasi_fd_t,
-) -> Result<(), host::__wasi_errno_t> {
-    let fe_from = match wasi_ctx.fds.get(&from) {
-        Some(fe_from) => fe_from,
-        None => return Err(host::__WASI_EBADF),
-    };
-    let fe_to = match wasi_ctx.fds.get(&to) {
-        Some(fe_to) => fe_to,
-        None => return Err(host::__WASI_EBADF),
-    };
-
-    // Don't allow renumbering over a pre-opened resource.
-    // TODO: Eventually, we do want to permit this, once libpreopen in
-    // userspace is capable of removing entries from its tables as well.
-    if fe_from.preopen_path.is_some() || fe_to.preopen_path.is_some() {
-        return Err(host::__WASI_ENOTSUP);
-    }
-
-    let rawfd_from = fe_from.fd_object.descriptor.as_raw_fd();
-    let rawfd_to = fe_to.fd_object.descriptor.as_raw_fd();
-
-    if let Err(e) = nix::unistd::dup2(rawfd_from, rawfd_to) {
-        return Err(host_impl::errno_from_nix(e.as_errno().unwrap()));
-    }
-
-    let _ = wasi_ctx.fds.remove(&(rawfd_from as host::__wasi_fd_t));
-
-    Ok(())
-}
-
 pub(crate) fn fd_seek(
     fd_entry: &FdEntry,
     offset: host::__wasi_filedelta_t,
@@ -342,9 +309,8 @@ pub(crate) fn path_open(
     match determine_type_rights(&file) {
         Err(e) => Err(e),
         Ok((_ty, max_base, max_inheriting)) => {
-            let mut fe = FdEntry::from(file)?;
-            fe.rights_base &= max_base;
-            fe.rights_inheriting &= max_inheriting;
+            let fe = FdEntry::from(file)?;
+            fe.restrict_rights(max_base, max_inheriting);
             Ok(fe)
         }
     }
@@ -409,6 +375,83 @@ pub(crate) fn fd_readdir(
     Ok(host_buf_len - left)
 }
 
+/// Like `fd_readdir`, but lists the entries sorted by name, so that the listing doesn't depend
+/// on how the host filesystem happens to order them. Cookies are positions in that listing.
+pub(crate) fn fd_readdir_sorted(
+    fd_entry: &FdEntry,
+    host_buf: &mut [u8],
+    cookie: host::__wasi_dircookie_t,
+) -> Result<usize, host::__wasi_errno_t> {
+    use libc::{closedir, dirent, fdopendir, readdir_r, rewinddir};
+    use nix::errno::Errno;
+    use std::convert::TryFrom;
+    use std::ffi::CStr;
+
+    // `closedir` closes the descriptor the stream was opened on, so work on a duplicate
+    let rawfd = nix::unistd::dup(fd_entry.fd_object.descriptor.as_raw_fd())
+        .map_err(|e| host_impl::errno_from_nix(e.as_errno().unwrap()))?;
+    let dir = unsafe { fdopendir(rawfd) };
+    if dir.is_null() {
+        let errno = Errno::last();
+        let _ = nix::unistd::close(rawfd);
+        return Err(host_impl::errno_from_nix(errno));
+    }
+    // the duplicate shares its offset with the original descriptor
+    unsafe { rewinddir(dir) };
+    let mut entries = Vec::new();
+    let mut entry_buf = unsafe { std::mem::zeroed::<dirent>() };
+    let res = loop {
+        let mut host_entry: *mut dirent = std::ptr::null_mut();
+        let res = unsafe { readdir_r(dir, &mut entry_buf, &mut host_entry) };
+        if res != 0 {
+            break Err(host_impl::errno_from_nix(Errno::from_i32(res)));
+        }
+        if host_entry.is_null() {
+            break Ok(());
+        }
+        let host_entry = unsafe { &*host_entry };
+        let name = unsafe { CStr::from_ptr(host_entry.d_name.as_ptr()) };
+        entries.push((
+            name.to_bytes().to_vec(),
+            host_entry.d_ino,
+            host_entry.d_type,
+        ));
+    };
+    unsafe { closedir(dir) };
+    res?;
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let skip = usize::try_from(cookie).unwrap_or(usize::max_value());
+    let mut left = host_buf.len();
+    let mut host_buf_offset: usize = 0;
+    for (index, (name, ino, file_type)) in entries.into_iter().enumerate().skip(skip) {
+        if name.len() > u32::max_value() as usize {
+            return Err(host::__WASI_EIO);
+        }
+        let mut entry = unsafe { std::mem::zeroed::<wasm32::__wasi_dirent_t>() };
+        entry.d_ino = memory::enc_inode(ino);
+        entry.d_next = memory::enc_dircookie(index as host::__wasi_dircookie_t + 1);
+        entry.d_namlen = memory::enc_u32(name.len() as u32);
+        entry.d_type = memory::enc_filetype(file_type);
+        let entry_size = std::mem::size_of_val(&entry);
+        let required_space = entry_size + name.len();
+        if required_space > left {
+            break;
+        }
+        unsafe {
+            std::ptr::write_unaligned(
+                host_buf[host_buf_offset..].as_mut_ptr() as *mut wasm32::__wasi_dirent_t,
+                entry,
+            )
+        };
+        host_buf_offset += entry_size;
+        host_buf[host_buf_offset..host_buf_offset + name.len()].copy_from_slice(&name);
+        host_buf_offset += name.len();
+        left -= required_space;
+    }
+    Ok(host_buf.len() - left)
+}
+
 pub(crate) fn path_readlink(
     wasi_ctx: &WasiCtx,
     dirfd: host::__wasi_fd_t,
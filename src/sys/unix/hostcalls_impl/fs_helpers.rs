@@ -4,15 +4,16 @@
 use crate::ctx::WasiCtx;
 use crate::fdentry::Descriptor;
 use crate::host;
+use crate::path_walk::{path_get_walk, Directory};
 use crate::sys::errno_from_host;
 use crate::sys::host_impl;
 use nix::libc::{self, c_long};
 use std::fs::File;
-use std::path::{Component, Path};
 
 /// Normalizes a path to ensure that the target path is located under the directory provided.
 ///
-/// This is a workaround for not having Capsicum support in the OS.
+/// On Linux, the kernel is asked to resolve the path with `openat2`; otherwise, or if that isn't
+/// possible, the path is resolved by `path_get_walk`.
 pub(crate) fn path_get(
     wasi_ctx: &WasiCtx,
     dirfd: host::__wasi_fd_t,
@@ -22,8 +23,6 @@ pub(crate) fn path_get(
     needed_inheriting: host::__wasi_rights_t,
     needs_final_component: bool,
 ) -> Result<(File, String), host::__wasi_errno_t> {
-    const MAX_SYMLINK_EXPANSIONS: usize = 128;
-
     if path.contains("\0") {
         // if contains NUL, return EILSEQ
         return Err(host::__WASI_EILSEQ);
@@ -38,152 +37,169 @@ pub(crate) fn path_get(
         _ => return Err(host::__WASI_EBADF),
     };
 
-    // Stack of directory file descriptors. Index 0 always corresponds with the directory provided
-    // to this function. Entering a directory causes a file descriptor to be pushed, while handling
-    // ".." entries causes an entry to be popped. Index 0 cannot be popped, as this would imply
-    // escaping the base directory.
-    let mut dir_stack = vec![dirfd];
-
-    // Stack of paths left to process. This is initially the `path` argument to this function, but
-    // any symlinks we encounter are processed by pushing them on the stack.
-    let mut path_stack = vec![path.to_owned()];
-
-    // Track the number of symlinks we've expanded, so we can return `ELOOP` after too many.
-    let mut symlink_expansions = 0;
-
-    // TODO: rewrite this using a custom posix path type, with a component iterator that respects
-    // trailing slashes. This version does way too much allocation, and is way too fiddly.
-    loop {
-        match path_stack.pop() {
-            Some(cur_path) => {
-                // eprintln!("cur_path = {:?}", cur_path);
-
-                let ends_with_slash = cur_path.ends_with("/");
-                let mut components = Path::new(&cur_path).components();
-                let head = match components.next() {
-                    None => return Err(host::__WASI_ENOENT),
-                    Some(p) => p,
-                };
-                let tail = components.as_path();
-
-                if tail.components().next().is_some() {
-                    let mut tail = host_impl::path_from_host(tail.as_os_str())?;
-                    if ends_with_slash {
-                        tail.push_str("/");
-                    }
-                    path_stack.push(tail);
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(res) = path_get_beneath(&dirfd, dirflags, path, needs_final_component) {
+            return res;
+        }
+    }
+
+    path_get_walk(dirfd, dirflags, path, needs_final_component)
+}
+
+impl Directory for File {
+    fn open_dir(&self, path: &str) -> Result<Self, host::__wasi_errno_t> {
+        openat(self, path)
+    }
+
+    fn read_link(&self, path: &str) -> Result<String, host::__wasi_errno_t> {
+        readlinkat(self, path)
+    }
+}
+
+/// Number of the `openat2` syscall, which isn't exposed by the `libc` crate we depend on. It is
+/// only known for the architectures sharing the generic syscall table; elsewhere, paths are
+/// always resolved by `path_get_walk`.
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86",
+        all(target_arch = "x86_64", target_pointer_width = "64"),
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "riscv64",
+        target_arch = "s390x",
+    )
+))]
+const SYS_OPENAT2: Option<c_long> = Some(437);
+#[cfg(all(
+    target_os = "linux",
+    not(any(
+        target_arch = "x86",
+        all(target_arch = "x86_64", target_pointer_width = "64"),
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "riscv64",
+        target_arch = "s390x",
+    ))
+))]
+const SYS_OPENAT2: Option<c_long> = None;
+
+/// Resolves the directory containing the final component of `path` with `openat2`, letting the
+/// kernel ensure that resolution never escapes `dirfd`.
+///
+/// Returns `None` if the result has to be computed by `path_get_walk` instead, either because
+/// `openat2` isn't supported by the kernel or the architecture, or because the path ends in a
+/// way the kernel can't resolve on our behalf, such as a symlink which has to be followed.
+#[cfg(target_os = "linux")]
+fn path_get_beneath(
+    dirfd: &File,
+    dirflags: host::__wasi_lookupflags_t,
+    path: &str,
+    needs_final_component: bool,
+) -> Option<Result<(File, String), host::__wasi_errno_t>> {
+    use nix::errno::Errno;
+    use std::ffi::CString;
+    use std::os::unix::prelude::{AsRawFd, FromRawFd};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+    const RESOLVE_BENEATH: u64 = 0x08;
+
+    #[repr(C)]
+    struct open_how {
+        flags: u64,
+        mode: u64,
+        resolve: u64,
+    }
+
+    // set once the kernel turns out not to support `openat2`, so we don't keep trying
+    static OPENAT2_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+    let sys_openat2 = SYS_OPENAT2?;
+    if OPENAT2_UNSUPPORTED.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let ends_with_slash = path.ends_with('/');
+    if path.starts_with('/') || (ends_with_slash && !needs_final_component) {
+        return None;
+    }
+    let trimmed = path.trim_end_matches('/');
+    let (parent, head) = match trimmed.rfind('/') {
+        Some(idx) => (&trimmed[..idx], &trimmed[idx + 1..]),
+        None => ("", trimmed),
+    };
+    if head.is_empty() || head == "." || head == ".." {
+        return None;
+    }
+
+    let dir = if parent.is_empty() {
+        match dirfd.try_clone() {
+            Ok(dir) => dir,
+            Err(err) => {
+                return Some(Err(err
+                    .raw_os_error()
+                    .map_or(host::__WASI_EBADF, errno_from_host)))
+            }
+        }
+    } else {
+        let parent = CString::new(parent).ok()?;
+        let how = open_how {
+            flags: (libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC) as u64,
+            mode: 0,
+            resolve: RESOLVE_BENEATH | RESOLVE_NO_MAGICLINKS,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                sys_openat2,
+                dirfd.as_raw_fd(),
+                parent.as_ptr(),
+                &how as *const open_how,
+                std::mem::size_of::<open_how>(),
+            )
+        };
+        if fd < 0 {
+            return match Errno::last() {
+                Errno::ENOSYS | Errno::EINVAL | Errno::E2BIG => {
+                    OPENAT2_UNSUPPORTED.store(true, Ordering::Relaxed);
+                    None
                 }
+                // EAGAIN signals a concurrent rename the kernel couldn't rule out, and EPERM is
+                // what some seccomp filters return for syscalls they don't know about
+                Errno::EAGAIN | Errno::EPERM => None,
+                // the path escapes the directory
+                Errno::EXDEV => Some(Err(host::__WASI_ENOTCAPABLE)),
+                errno => Some(Err(host_impl::errno_from_nix(errno))),
+            };
+        }
+        unsafe { File::from_raw_fd(fd as libc::c_int) }
+    };
+
+    let mut head = head.to_owned();
+    if ends_with_slash {
+        // preserve trailing slash
+        head.push('/');
+    }
 
-                match head {
-                    Component::Prefix(_) | Component::RootDir => {
-                        // path is absolute!
-                        return Err(host::__WASI_ENOTCAPABLE);
-                    }
-                    Component::CurDir => {
-                        // "." so skip
-                        continue;
-                    }
-                    Component::ParentDir => {
-                        // ".." so pop a dir
-                        let _ = dir_stack.pop().ok_or(host::__WASI_ENOTCAPABLE)?;
-
-                        // we're not allowed to pop past the original directory
-                        if dir_stack.is_empty() {
-                            return Err(host::__WASI_ENOTCAPABLE);
-                        }
-                    }
-                    Component::Normal(head) => {
-                        let mut head = host_impl::path_from_host(head)?;
-                        if ends_with_slash {
-                            // preserve trailing slash
-                            head.push_str("/");
-                        }
-
-                        if !path_stack.is_empty() || (ends_with_slash && !needs_final_component) {
-                            match openat(dir_stack.last().ok_or(host::__WASI_ENOTCAPABLE)?, &head) {
-                                Ok(new_dir) => {
-                                    dir_stack.push(new_dir);
-                                    continue;
-                                }
-                                Err(e)
-                                    if e == host::__WASI_ELOOP
-                                        || e == host::__WASI_EMLINK
-                                        || e == host::__WASI_ENOTDIR =>
-                                // Check to see if it was a symlink. Linux indicates
-                                // this with ENOTDIR because of the O_DIRECTORY flag.
-                                {
-                                    // attempt symlink expansion
-                                    match readlinkat(
-                                        dir_stack.last().ok_or(host::__WASI_ENOTCAPABLE)?,
-                                        &head,
-                                    ) {
-                                        Ok(mut link_path) => {
-                                            symlink_expansions += 1;
-                                            if symlink_expansions > MAX_SYMLINK_EXPANSIONS {
-                                                return Err(host::__WASI_ELOOP);
-                                            }
-
-                                            if head.ends_with("/") {
-                                                link_path.push_str("/");
-                                            }
-
-                                            path_stack.push(link_path);
-                                            continue;
-                                        }
-                                        Err(e) => {
-                                            return Err(e);
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    return Err(e);
-                                }
-                            }
-                        } else if ends_with_slash
-                            || (dirflags & host::__WASI_LOOKUP_SYMLINK_FOLLOW) != 0
-                        {
-                            // if there's a trailing slash, or if `LOOKUP_SYMLINK_FOLLOW` is set, attempt
-                            // symlink expansion
-                            match readlinkat(
-                                dir_stack.last().ok_or(host::__WASI_ENOTCAPABLE)?,
-                                &head,
-                            ) {
-                                Ok(mut link_path) => {
-                                    symlink_expansions += 1;
-                                    if symlink_expansions > MAX_SYMLINK_EXPANSIONS {
-                                        return Err(host::__WASI_ELOOP);
-                                    }
-
-                                    if head.ends_with("/") {
-                                        link_path.push_str("/");
-                                    }
-
-                                    path_stack.push(link_path);
-                                    continue;
-                                }
-                                Err(e) => {
-                                    if e != host::__WASI_EINVAL && e != host::__WASI_ENOENT {
-                                        return Err(e);
-                                    }
-                                }
-                            }
-                        }
-
-                        // not a symlink, so we're done;
-                        return Ok((dir_stack.pop().ok_or(host::__WASI_ENOTCAPABLE)?, head));
-                    }
+    if ends_with_slash || (dirflags & host::__WASI_LOOKUP_SYMLINK_FOLLOW) != 0 {
+        // a symlink in the final component needs to be expanded, and its target resolved from
+        // scratch
+        match readlinkat(&dir, &head) {
+            Ok(_) => return None,
+            Err(e) => {
+                if e != host::__WASI_EINVAL && e != host::__WASI_ENOENT {
+                    return Some(Err(e));
                 }
             }
-            None => {
-                // no further components to process. means we've hit a case like "." or "a/..", or if the
-                // input path has trailing slashes and `needs_final_component` is not set
-                return Ok((
-                    dir_stack.pop().ok_or(host::__WASI_ENOTCAPABLE)?,
-                    String::from("."),
-                ));
-            }
         }
     }
+
+    Some(Ok((dir, head)))
 }
 
 fn openat(dirfd: &File, path: &str) -> Result<File, host::__wasi_errno_t> {
@@ -231,3 +247,69 @@ pub fn utime_omit() -> c_long {
 pub fn utime_omit() -> c_long {
     -2
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    /// A directory holding `sub/file`, along with symlinks `up -> ..`, `abs -> /` and
+    /// `sub/self -> ../sub`.
+    fn sandbox(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wasi-common-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/file"), b"").unwrap();
+        symlink("..", dir.join("up")).unwrap();
+        symlink("/", dir.join("abs")).unwrap();
+        symlink("../sub", dir.join("sub/self")).unwrap();
+        dir
+    }
+
+    const ESCAPES: &[&str] = &[
+        "../x",
+        "sub/../../x",
+        "up/x",
+        "abs/etc/passwd",
+        "/etc/passwd",
+    ];
+
+    #[test]
+    fn walk_stays_beneath_dir() {
+        let dir = sandbox("walk");
+        let resolve =
+            |path| path_get_walk(File::open(&dir).unwrap(), 0, path, true).map(|(_, name)| name);
+        for path in ESCAPES {
+            assert_eq!(resolve(path), Err(host::__WASI_ENOTCAPABLE), "{}", path);
+        }
+        assert_eq!(resolve("sub/self/file"), Ok("file".to_owned()));
+        assert_eq!(resolve("sub/../sub/file"), Ok("file".to_owned()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn openat2_stays_beneath_dir() {
+        let dir = sandbox("openat2");
+        let dirfd = File::open(&dir).unwrap();
+        let resolve =
+            |path| path_get_beneath(&dirfd, 0, path, true).map(|res| res.map(|(_, name)| name));
+        // without kernel support, everything goes through `path_get_walk`
+        if resolve("sub/file").is_some() {
+            for path in &["../x", "sub/../../x", "up/x", "abs/etc/passwd"] {
+                assert_eq!(
+                    resolve(path),
+                    Some(Err(host::__WASI_ENOTCAPABLE)),
+                    "{}",
+                    path
+                );
+            }
+            assert_eq!(resolve("sub/self/file"), Some(Ok("file".to_owned())));
+        }
+        // the paths the kernel can't resolve on our behalf are left to `path_get_walk`
+        assert_eq!(resolve("/etc/passwd"), None);
+        assert_eq!(resolve("sub/.."), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,15 @@
 #![allow(non_camel_case_types)]
 #![allow(unused_unsafe)]
-use crate::memory::*;
+use crate::hostcalls::FdEventData;
 use crate::sys::host_impl;
 use crate::{host, wasm32};
 
 use nix::convert_ioctl_res;
 use nix::libc::{self, c_int};
 use std::cmp;
-use std::time::SystemTime;
+use std::os::unix::prelude::AsRawFd;
+use std::ptr;
+use std::time::Duration;
 
 pub(crate) fn clock_res_get(
     clock_id: host::__wasi_clockid_t,
@@ -73,159 +75,116 @@ pub(crate) fn clock_time_get(
 }
 
 pub(crate) fn poll_oneoff(
-    input: Vec<Result<host::__wasi_subscription_t, host::__wasi_errno_t>>,
-    output_slice: &mut [wasm32::__wasi_event_t],
-) -> Result<wasm32::size_t, host::__wasi_errno_t> {
-    let timeout = input
-        .iter()
-        .filter_map(|event| match event {
-            Ok(event) if event.type_ == wasm32::__WASI_EVENTTYPE_CLOCK => Some(ClockEventData {
-                delay: wasi_clock_to_relative_ns_delay(unsafe { event.u.clock }).ok()? / 1_000_000,
-                userdata: event.userdata,
-            }),
-            _ => None,
-        })
-        .min_by_key(|event| event.delay);
+    timeout: Option<Duration>,
+    fd_events: &[FdEventData],
+    events: &mut Vec<host::__wasi_event_t>,
+) -> Result<(), host::__wasi_errno_t> {
+    use nix::{
+        errno::Errno,
+        poll::{EventFlags, PollFd},
+    };
 
-    let fd_events: Vec<_> = input
-        .iter()
-        .filter_map(|event| match event {
-            Ok(event)
-                if event.type_ == wasm32::__WASI_EVENTTYPE_FD_READ
-                    || event.type_ == wasm32::__WASI_EVENTTYPE_FD_WRITE =>
-            {
-                Some(FdEventData {
-                    fd: unsafe { event.u.fd_readwrite.fd } as c_int,
-                    type_: event.type_,
-                    userdata: event.userdata,
-                })
-            }
-            _ => None,
-        })
-        .collect();
-    if fd_events.is_empty() && timeout.is_none() {
-        return Ok(0);
-    }
     let mut poll_fds: Vec<_> = fd_events
         .iter()
         .map(|event| {
-            let mut flags = nix::poll::EventFlags::empty();
+            let mut flags = EventFlags::empty();
             match event.type_ {
-                wasm32::__WASI_EVENTTYPE_FD_READ => flags.insert(nix::poll::EventFlags::POLLIN),
-                wasm32::__WASI_EVENTTYPE_FD_WRITE => flags.insert(nix::poll::EventFlags::POLLOUT),
+                host::__WASI_EVENTTYPE_FD_READ => flags.insert(EventFlags::POLLIN),
+                host::__WASI_EVENTTYPE_FD_WRITE => flags.insert(EventFlags::POLLOUT),
                 // An event on a file descriptor can currently only be of type FD_READ or FD_WRITE
                 // Nothing else has been defined in the specification, and these are also the only two
                 // events we filtered before. If we get something else here, the code has a serious bug.
                 _ => unreachable!(),
             };
-            nix::poll::PollFd::new(event.fd, flags)
+            PollFd::new(event.descriptor.as_raw_fd(), flags)
         })
         .collect();
-    let timeout = timeout.map(|ClockEventData { delay, userdata }| ClockEventData {
-        delay: cmp::min(delay, c_int::max_value() as u128),
-        userdata,
-    });
-    let poll_timeout = timeout.map_or(-1, |timeout| timeout.delay as c_int);
-    let ready = loop {
-        match nix::poll::poll(&mut poll_fds, poll_timeout) {
-            Err(_) => {
-                if nix::errno::Errno::last() == nix::errno::Errno::EINTR {
-                    continue;
-                }
-                return Err(host_impl::errno_from_nix(nix::errno::Errno::last()));
-            }
-            Ok(ready) => break ready as usize,
-        }
+    let ready = match poll(&mut poll_fds, timeout) {
+        Ok(ready) => ready as usize,
+        // the caller recomputes the remaining timeout and tries again
+        Err(_) if Errno::last() == Errno::EINTR => return Ok(()),
+        Err(_) => return Err(host_impl::errno_from_nix(Errno::last())),
     };
-    let events_count = if ready == 0 {
-        poll_oneoff_handle_timeout_event(output_slice, timeout)
-    } else {
-        let events = fd_events.iter().zip(poll_fds.iter()).take(ready);
-        poll_oneoff_handle_fd_event(output_slice, events)
-    };
-
-    Ok(events_count)
-}
-
-// define the `fionread()` function, equivalent to `ioctl(fd, FIONREAD, *bytes)`
-nix::ioctl_read_bad!(fionread, nix::libc::FIONREAD, c_int);
-
-fn wasi_clock_to_relative_ns_delay(
-    wasi_clock: host::__wasi_subscription_t___wasi_subscription_u___wasi_subscription_u_clock_t,
-) -> Result<u128, host::__wasi_errno_t> {
-    if wasi_clock.flags != wasm32::__WASI_SUBSCRIPTION_CLOCK_ABSTIME {
-        return Ok(wasi_clock.timeout as u128);
+    if ready > 0 {
+        poll_oneoff_handle_fd_event(fd_events.iter().zip(poll_fds.iter()), events);
     }
-    let now: u128 = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map_err(|_| host::__WASI_ENOTCAPABLE)?
-        .as_nanos();
-    let deadline = wasi_clock.timeout as u128;
-    Ok(deadline.saturating_sub(now))
-}
 
-#[derive(Debug, Copy, Clone)]
-struct ClockEventData {
-    delay: u128,
-    userdata: host::__wasi_userdata_t,
+    Ok(())
 }
-#[derive(Debug, Copy, Clone)]
-struct FdEventData {
-    fd: c_int,
-    type_: host::__wasi_eventtype_t,
-    userdata: host::__wasi_userdata_t,
+
+/// Waits for the descriptors with nanosecond resolution, using `ppoll` where available.
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux"
+))]
+fn poll(fds: &mut [nix::poll::PollFd], timeout: Option<Duration>) -> nix::Result<c_int> {
+    let timeout = timeout.map(|timeout| libc::timespec {
+        tv_sec: cmp::min(timeout.as_secs(), libc::time_t::max_value() as u64) as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    });
+    let res = unsafe {
+        libc::ppoll(
+            fds.as_mut_ptr() as *mut libc::pollfd,
+            fds.len() as libc::nfds_t,
+            timeout
+                .as_ref()
+                .map_or(ptr::null(), |timeout| timeout as *const libc::timespec),
+            ptr::null(),
+        )
+    };
+    nix::errno::Errno::result(res)
 }
 
-fn poll_oneoff_handle_timeout_event(
-    output_slice: &mut [wasm32::__wasi_event_t],
-    timeout: Option<ClockEventData>,
-) -> wasm32::size_t {
-    if let Some(ClockEventData { userdata, .. }) = timeout {
-        let output_event = host::__wasi_event_t {
-            userdata,
-            type_: wasm32::__WASI_EVENTTYPE_CLOCK,
-            error: wasm32::__WASI_ESUCCESS,
-            u: host::__wasi_event_t___wasi_event_u {
-                fd_readwrite: host::__wasi_event_t___wasi_event_u___wasi_event_u_fd_readwrite_t {
-                    nbytes: 0,
-                    flags: 0,
-                },
-            },
-        };
-        output_slice[0] = enc_event(output_event);
-        1
-    } else {
-        // shouldn't happen
-        0
+/// Waits for the descriptors. `poll` only has millisecond resolution, so pure sleeps go through
+/// `nanosleep` instead, and otherwise the timeout is rounded up so as never to wake up early.
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux"
+)))]
+fn poll(fds: &mut [nix::poll::PollFd], timeout: Option<Duration>) -> nix::Result<c_int> {
+    match timeout {
+        Some(timeout) if fds.is_empty() => {
+            std::thread::sleep(timeout);
+            Ok(0)
+        }
+        timeout => {
+            let timeout = timeout.map_or(-1, |timeout| {
+                let millis = (timeout.as_nanos() + 999_999) / 1_000_000;
+                cmp::min(millis, c_int::max_value() as u128) as c_int
+            });
+            nix::poll::poll(fds, timeout)
+        }
     }
 }
 
-fn poll_oneoff_handle_fd_event<'t>(
-    output_slice: &mut [wasm32::__wasi_event_t],
-    events: impl Iterator<Item = (&'t FdEventData, &'t nix::poll::PollFd)>,
-) -> wasm32::size_t {
-    let mut output_slice_cur = output_slice.iter_mut();
-    let mut revents_count = 0;
-    for (fd_event, poll_fd) in events {
+// define the `fionread()` function, equivalent to `ioctl(fd, FIONREAD, *bytes)`
+nix::ioctl_read_bad!(fionread, nix::libc::FIONREAD, c_int);
+
+fn poll_oneoff_handle_fd_event<'a, 't: 'a>(
+    ready_events: impl Iterator<Item = (&'a FdEventData<'t>, &'a nix::poll::PollFd)>,
+    events: &mut Vec<host::__wasi_event_t>,
+) {
+    for (fd_event, poll_fd) in ready_events {
         let revents = match poll_fd.revents() {
             Some(revents) => revents,
             None => continue,
         };
         let mut nbytes = 0;
         if fd_event.type_ == wasm32::__WASI_EVENTTYPE_FD_READ {
-            let _ = unsafe { fionread(fd_event.fd, &mut nbytes) };
+            let _ = unsafe { fionread(fd_event.descriptor.as_raw_fd(), &mut nbytes) };
         }
         let output_event = if revents.contains(nix::poll::EventFlags::POLLNVAL) {
             host::__wasi_event_t {
                 userdata: fd_event.userdata,
                 type_: fd_event.type_,
                 error: wasm32::__WASI_EBADF,
-                u: host::__wasi_event_t___wasi_event_u {
-                    fd_readwrite:
-                        host::__wasi_event_t___wasi_event_u___wasi_event_u_fd_readwrite_t {
-                            nbytes: 0,
-                            flags: wasm32::__WASI_EVENT_FD_READWRITE_HANGUP,
-                        },
+                fd_readwrite: host::__wasi_event_fd_readwrite_t {
+                    nbytes: 0,
+                    flags: wasm32::__WASI_EVENT_FD_READWRITE_HANGUP,
                 },
             }
         } else if revents.contains(nix::poll::EventFlags::POLLERR) {
@@ -233,25 +192,20 @@ fn poll_oneoff_handle_fd_event<'t>(
                 userdata: fd_event.userdata,
                 type_: fd_event.type_,
                 error: wasm32::__WASI_EIO,
-                u: host::__wasi_event_t___wasi_event_u {
-                    fd_readwrite:
-                        host::__wasi_event_t___wasi_event_u___wasi_event_u_fd_readwrite_t {
-                            nbytes: 0,
-                            flags: wasm32::__WASI_EVENT_FD_READWRITE_HANGUP,
-                        },
+                fd_readwrite: host::__wasi_event_fd_readwrite_t {
+                    nbytes: 0,
+                    flags: wasm32::__WASI_EVENT_FD_READWRITE_HANGUP,
                 },
             }
         } else if revents.contains(nix::poll::EventFlags::POLLHUP) {
+            // the peer is gone, but whatever it sent before hanging up can still be read
             host::__wasi_event_t {
                 userdata: fd_event.userdata,
                 type_: fd_event.type_,
                 error: wasm32::__WASI_ESUCCESS,
-                u: host::__wasi_event_t___wasi_event_u {
-                    fd_readwrite:
-                        host::__wasi_event_t___wasi_event_u___wasi_event_u_fd_readwrite_t {
-                            nbytes: 0,
-                            flags: wasm32::__WASI_EVENT_FD_READWRITE_HANGUP,
-                        },
+                fd_readwrite: host::__wasi_event_fd_readwrite_t {
+                    nbytes: nbytes as host::__wasi_filesize_t,
+                    flags: wasm32::__WASI_EVENT_FD_READWRITE_HANGUP,
                 },
             }
         } else if revents.contains(nix::poll::EventFlags::POLLIN)
@@ -261,19 +215,14 @@ fn poll_oneoff_handle_fd_event<'t>(
                 userdata: fd_event.userdata,
                 type_: fd_event.type_,
                 error: wasm32::__WASI_ESUCCESS,
-                u: host::__wasi_event_t___wasi_event_u {
-                    fd_readwrite:
-                        host::__wasi_event_t___wasi_event_u___wasi_event_u_fd_readwrite_t {
-                            nbytes: nbytes as host::__wasi_filesize_t,
-                            flags: 0,
-                        },
+                fd_readwrite: host::__wasi_event_fd_readwrite_t {
+                    nbytes: nbytes as host::__wasi_filesize_t,
+                    flags: 0,
                 },
             }
         } else {
             continue;
         };
-        *output_slice_cur.next().unwrap() = enc_event(output_event);
-        revents_count += 1;
+        events.push(output_event);
     }
-    revents_count
 }
@@ -0,0 +1,96 @@
+#![allow(non_camel_case_types)]
+use crate::fdentry::FdEntry;
+use crate::host;
+use crate::sys::host_impl;
+use nix::libc;
+use std::io;
+use std::os::unix::prelude::AsRawFd;
+
+// Writing to a socket whose peer has gone away must surface as EPIPE rather than SIGPIPE, which
+// would kill the whole host.
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+const SEND_FLAGS: libc::c_int = libc::MSG_NOSIGNAL;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const SEND_FLAGS: libc::c_int = 0;
+
+// the type of `msg_iovlen` differs between platforms
+#[allow(trivial_numeric_casts)]
+fn msghdr(iov: *mut libc::iovec, iovlen: usize) -> libc::msghdr {
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = iov;
+    msg.msg_iovlen = iovlen as _;
+    msg
+}
+
+pub(crate) fn sock_recv(
+    fd_entry: &FdEntry,
+    iovs: &mut [io::IoSliceMut],
+    ri_flags: host::__wasi_riflags_t,
+) -> Result<(usize, host::__wasi_roflags_t), host::__wasi_errno_t> {
+    let rawfd = fd_entry.fd_object.descriptor.as_raw_fd();
+    let mut flags = 0;
+    if ri_flags & host::__WASI_SOCK_RECV_PEEK != 0 {
+        flags |= libc::MSG_PEEK;
+    }
+    if ri_flags & host::__WASI_SOCK_RECV_WAITALL != 0 {
+        flags |= libc::MSG_WAITALL;
+    }
+
+    // `IoSliceMut` is guaranteed to be ABI compatible with `iovec` on Unix
+    let mut msg = msghdr(iovs.as_mut_ptr() as *mut libc::iovec, iovs.len());
+    let nread = loop {
+        let res = unsafe { libc::recvmsg(rawfd, &mut msg, flags) };
+        if res >= 0 {
+            break res as usize;
+        }
+        let errno = nix::errno::Errno::last();
+        if errno != nix::errno::Errno::EINTR {
+            return Err(host_impl::errno_from_nix(errno));
+        }
+    };
+
+    let mut ro_flags = 0;
+    if msg.msg_flags & libc::MSG_TRUNC != 0 {
+        ro_flags |= host::__WASI_SOCK_RECV_DATA_TRUNCATED;
+    }
+
+    Ok((nread, ro_flags))
+}
+
+pub(crate) fn sock_send(
+    fd_entry: &FdEntry,
+    iovs: &[io::IoSlice],
+) -> Result<usize, host::__wasi_errno_t> {
+    let rawfd = fd_entry.fd_object.descriptor.as_raw_fd();
+
+    // `IoSlice` is guaranteed to be ABI compatible with `iovec` on Unix; `sendmsg` never writes
+    // through the buffers despite the `*mut` in the signature
+    let msg = msghdr(iovs.as_ptr() as *mut libc::iovec, iovs.len());
+    loop {
+        let res = unsafe { libc::sendmsg(rawfd, &msg, SEND_FLAGS) };
+        if res >= 0 {
+            return Ok(res as usize);
+        }
+        let errno = nix::errno::Errno::last();
+        if errno != nix::errno::Errno::EINTR {
+            return Err(host_impl::errno_from_nix(errno));
+        }
+    }
+}
+
+pub(crate) fn sock_shutdown(
+    fd_entry: &FdEntry,
+    how: host::__wasi_sdflags_t,
+) -> Result<(), host::__wasi_errno_t> {
+    use nix::sys::socket::{shutdown, Shutdown};
+
+    let how = match how {
+        host::__WASI_SHUT_RD => Shutdown::Read,
+        host::__WASI_SHUT_WR => Shutdown::Write,
+        how if how == host::__WASI_SHUT_RD | host::__WASI_SHUT_WR => Shutdown::Both,
+        _ => return Err(host::__WASI_EINVAL),
+    };
+    let rawfd = fd_entry.fd_object.descriptor.as_raw_fd();
+
+    shutdown(rawfd, how).map_err(|e| host_impl::errno_from_nix(e.as_errno().unwrap()))
+}
@@ -15,3 +15,99 @@ pub(crate) fn dev_null() -> Result<File, host::__wasi_errno_t> {
 pub fn preopen_dir<P: AsRef<Path>>(path: P) -> Result<File, host::__wasi_errno_t> {
     File::open(path).map_err(|err| err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host))
 }
+
+/// Takes over the descriptors passed in by a supervisor following the systemd socket activation
+/// protocol, together with their names.
+///
+/// As with `sd_listen_fds(1)`, the `LISTEN_*` variables are removed from the environment once
+/// the descriptors have been adopted, so they cannot be adopted a second time. Nothing is
+/// returned if they weren't meant for this process.
+pub(crate) fn listen_fds() -> Result<Vec<(File, String)>, host::__wasi_errno_t> {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+    use std::env;
+    use std::os::unix::prelude::{FromRawFd, RawFd};
+
+    const SD_LISTEN_FDS_START: RawFd = 3;
+
+    let pid = env::var("LISTEN_PID").ok();
+    if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+        return Ok(vec![]);
+    }
+    let count = match env::var("LISTEN_FDS").ok() {
+        Some(count) => count
+            .parse::<RawFd>()
+            .ok()
+            .filter(|&count| count >= 0)
+            .ok_or(host::__WASI_EINVAL)?,
+        None => return Ok(vec![]),
+    };
+    let last_fd = SD_LISTEN_FDS_START
+        .checked_add(count)
+        .ok_or(host::__WASI_EINVAL)?;
+
+    // Every descriptor is checked before taking ownership of any, so that a bogus count doesn't
+    // get us to close descriptors which belong to someone else.
+    for fd in SD_LISTEN_FDS_START..last_fd {
+        fcntl(fd, FcntlArg::F_GETFD)
+            .map_err(|e| host_impl::errno_from_nix(e.as_errno().unwrap()))?;
+    }
+    for fd in SD_LISTEN_FDS_START..last_fd {
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+            .map_err(|e| host_impl::errno_from_nix(e.as_errno().unwrap()))?;
+    }
+
+    let names = env::var("LISTEN_FDNAMES").ok();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let mut names = names.as_ref().map(|names| names.split(':'));
+    Ok((SD_LISTEN_FDS_START..last_fd)
+        .map(|fd| {
+            let name = names
+                .as_mut()
+                .and_then(Iterator::next)
+                .unwrap_or("unknown")
+                .to_owned();
+            (unsafe { File::from_raw_fd(fd) }, name)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn set_listen_env(pid: u32, count: &str) {
+        env::set_var("LISTEN_PID", pid.to_string());
+        env::set_var("LISTEN_FDS", count);
+        env::set_var("LISTEN_FDNAMES", "http");
+    }
+
+    // the environment is shared by the whole process, so the cases must run one after the other
+    #[test]
+    fn listen_fds_validates_before_adopting() {
+        let pid = std::process::id();
+
+        set_listen_env(pid + 1, "1");
+        assert_eq!(listen_fds().unwrap().len(), 0);
+        assert!(env::var("LISTEN_FDS").is_ok());
+
+        set_listen_env(pid, "-1");
+        assert_eq!(listen_fds().err(), Some(host::__WASI_EINVAL));
+        assert!(env::var("LISTEN_FDS").is_ok());
+
+        // more descriptors than the process can have open, so some of them are bound to be
+        // invalid; none of the valid ones may be closed
+        set_listen_env(pid, "1000000");
+        assert_eq!(listen_fds().err(), Some(host::__WASI_EBADF));
+        assert!(env::var("LISTEN_FDS").is_ok());
+
+        set_listen_env(pid, "0");
+        assert_eq!(listen_fds().unwrap().len(), 0);
+        assert!(env::var("LISTEN_PID").is_err());
+        assert!(env::var("LISTEN_FDS").is_err());
+        assert!(env::var("LISTEN_FDNAMES").is_err());
+    }
+}
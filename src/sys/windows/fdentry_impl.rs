@@ -13,6 +13,8 @@ impl AsRawHandle for Descriptor {
             Descriptor::Stdin => io::stdin().as_raw_handle(),
             Descriptor::Stdout => io::stdout().as_raw_handle(),
             Descriptor::Stderr => io::stderr().as_raw_handle(),
+            // virtual files have no host handle, so any syscall on it fails
+            Descriptor::VirtualFile(_) => std::ptr::null_mut(),
         }
     }
 }
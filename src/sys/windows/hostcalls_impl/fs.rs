@@ -50,14 +50,6 @@ pub(crate) fn fd_pwrite(
         .map_err(|err| err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host))
 }
 
-pub(crate) fn fd_renumber(
-    wasi_ctx: &mut WasiCtx,
-    from: host::__wasi_fd_t,
-    to: host::__wasi_fd_t,
-) -> Result<(), host::__wasi_errno_t> {
-    unimplemented!("fd_renumber")
-}
-
 pub(crate) fn fd_seek(
     fd_entry: &FdEntry,
     offset: host::__wasi_filedelta_t,
@@ -188,9 +180,8 @@ pub(crate) fn path_open(
     match determine_type_rights(&file) {
         Err(e) => Err(e),
         Ok((_ty, max_base, max_inheriting)) => {
-            let mut fe = FdEntry::from(file)?;
-            fe.rights_base &= max_base;
-            fe.rights_inheriting &= max_inheriting;
+            let fe = FdEntry::from(file)?;
+            fe.restrict_rights(max_base, max_inheriting);
             Ok(fe)
         }
     }
@@ -204,6 +195,14 @@ pub(crate) fn fd_readdir(
     unimplemented!("fd_readdir")
 }
 
+pub(crate) fn fd_readdir_sorted(
+    fd_entry: &FdEntry,
+    host_buf: &mut [u8],
+    cookie: host::__wasi_dircookie_t,
+) -> Result<usize, host::__wasi_errno_t> {
+    Err(host::__WASI_ENOTSUP)
+}
+
 pub(crate) fn path_readlink(
     wasi_ctx: &WasiCtx,
     dirfd: host::__wasi_fd_t,
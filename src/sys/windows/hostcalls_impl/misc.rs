@@ -1,9 +1,11 @@
 #![allow(non_camel_case_types)]
 #![allow(unused_unsafe)]
 #![allow(unused)]
+use crate::hostcalls::FdEventData;
 use crate::memory::*;
 use crate::sys::host_impl;
 use crate::{host, wasm32};
+use std::time::Duration;
 
 use wasi_common_cbindgen::wasi_common_cbindgen;
 
@@ -20,8 +22,9 @@ pub(crate) fn clock_time_get(
 }
 
 pub(crate) fn poll_oneoff(
-    input: Vec<Result<host::__wasi_subscription_t, host::__wasi_errno_t>>,
-    output_slice: &mut [wasm32::__wasi_event_t],
-) -> Result<wasm32::size_t, host::__wasi_errno_t> {
+    timeout: Option<Duration>,
+    fd_events: &[FdEventData],
+    events: &mut Vec<host::__wasi_event_t>,
+) -> Result<(), host::__wasi_errno_t> {
     unimplemented!("poll_oneoff")
 }
@@ -3,6 +3,8 @@
 mod fs;
 mod fs_helpers;
 mod misc;
+mod sock;
 
 pub(crate) use self::fs::*;
 pub(crate) use self::misc::*;
+pub(crate) use self::sock::*;
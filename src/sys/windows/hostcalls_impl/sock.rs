@@ -0,0 +1,27 @@
+#![allow(non_camel_case_types)]
+#![allow(unused)]
+use crate::fdentry::FdEntry;
+use crate::host;
+use std::io;
+
+pub(crate) fn sock_recv(
+    fd_entry: &FdEntry,
+    iovs: &mut [io::IoSliceMut],
+    ri_flags: host::__wasi_riflags_t,
+) -> Result<(usize, host::__wasi_roflags_t), host::__wasi_errno_t> {
+    Err(host::__WASI_ENOTSUP)
+}
+
+pub(crate) fn sock_send(
+    fd_entry: &FdEntry,
+    iovs: &[io::IoSlice],
+) -> Result<usize, host::__wasi_errno_t> {
+    Err(host::__WASI_ENOTSUP)
+}
+
+pub(crate) fn sock_shutdown(
+    fd_entry: &FdEntry,
+    how: host::__wasi_sdflags_t,
+) -> Result<(), host::__wasi_errno_t> {
+    Err(host::__WASI_ENOTSUP)
+}
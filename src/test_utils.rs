@@ -0,0 +1,16 @@
+//! Guest memory fixtures shared by the hostcall tests.
+
+/// Writes the wasm32 iovec `{ buf, buf_len }` at `offset` in `mem`.
+pub(crate) fn put_iovec(mem: &mut [u8], offset: usize, buf: u32, buf_len: u32) {
+    mem[offset..offset + 4].copy_from_slice(&buf.to_le_bytes());
+    mem[offset + 4..offset + 8].copy_from_slice(&buf_len.to_le_bytes());
+}
+
+/// Returns a memory holding an iovec of `buf_len` bytes at offset 0, pointing at `data` at
+/// offset 32, with room for the number of bytes transferred at offset 8.
+pub(crate) fn memory_with_iovec(data: &[u8], buf_len: u32) -> Vec<u8> {
+    let mut mem = vec![0; 64];
+    put_iovec(&mut mem, 0, 32, buf_len);
+    mem[32..32 + data.len()].copy_from_slice(data);
+    mem
+}
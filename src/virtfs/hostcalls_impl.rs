@@ -0,0 +1,274 @@
+//! Hostcalls dispatched to `VirtualFile` implementations. The path-based functions mirror the
+//! signatures of their counterparts in `sys::hostcalls_impl`.
+#![allow(non_camel_case_types)]
+use super::{Dirent, VirtualFile};
+use crate::ctx::WasiCtx;
+use crate::fdentry::{Descriptor, FdEntry};
+use crate::path_walk::{path_get_walk, Directory};
+use crate::{host, memory, wasm32};
+
+/// Returns `true` if `fd` refers to a virtual file, in which case all hostcalls operating on it
+/// need to be dispatched to this module rather than to `sys::hostcalls_impl`.
+pub(crate) fn is_virtual(wasi_ctx: &WasiCtx, fd: host::__wasi_fd_t) -> bool {
+    match wasi_ctx.get_fd_entry(fd, 0, 0) {
+        Ok(fe) => match &*fe.fd_object.descriptor {
+            Descriptor::VirtualFile(_) => true,
+            _ => false,
+        },
+        Err(_) => false,
+    }
+}
+
+/// Normalizes a path to ensure that the target path is located under the virtual directory
+/// provided.
+pub(crate) fn path_get(
+    wasi_ctx: &WasiCtx,
+    dirfd: host::__wasi_fd_t,
+    dirflags: host::__wasi_lookupflags_t,
+    path: &str,
+    needed_base: host::__wasi_rights_t,
+    needed_inheriting: host::__wasi_rights_t,
+    needs_final_component: bool,
+) -> Result<(Box<dyn VirtualFile>, String), host::__wasi_errno_t> {
+    if path.contains('\0') {
+        // if contains NUL, return EILSEQ
+        return Err(host::__WASI_EILSEQ);
+    }
+
+    let dirfe = wasi_ctx.get_fd_entry(dirfd, needed_base, needed_inheriting)?;
+    let dir = match &*dirfe.fd_object.descriptor {
+        Descriptor::VirtualFile(vf) => vf.try_clone()?,
+        _ => return Err(host::__WASI_EBADF),
+    };
+
+    path_get_walk(dir, dirflags, path, needs_final_component)
+}
+
+impl Directory for Box<dyn VirtualFile> {
+    fn open_dir(&self, path: &str) -> Result<Self, host::__wasi_errno_t> {
+        self.openat(path, true, false, host::__WASI_O_DIRECTORY, 0)
+    }
+
+    fn read_link(&self, path: &str) -> Result<String, host::__wasi_errno_t> {
+        self.readlinkat(path)
+    }
+}
+
+pub(crate) fn fd_readdir(
+    file: &dyn VirtualFile,
+    host_buf: &mut [u8],
+    cookie: host::__wasi_dircookie_t,
+) -> Result<usize, host::__wasi_errno_t> {
+    let mut left = host_buf.len();
+    let mut host_buf_offset: usize = 0;
+    for Dirent {
+        name,
+        ino,
+        file_type,
+        next,
+    } in file.readdir(cookie)?
+    {
+        let name = name.as_bytes();
+        if name.len() > u32::max_value() as usize {
+            return Err(host::__WASI_EIO);
+        }
+        let mut entry = unsafe { std::mem::zeroed::<wasm32::__wasi_dirent_t>() };
+        entry.d_ino = memory::enc_inode(ino);
+        entry.d_next = memory::enc_dircookie(next);
+        entry.d_namlen = memory::enc_u32(name.len() as u32);
+        entry.d_type = memory::enc_filetype(file_type);
+        let entry_size = std::mem::size_of_val(&entry);
+        let required_space = entry_size + name.len();
+        if required_space > left {
+            break;
+        }
+        unsafe {
+            std::ptr::write_unaligned(
+                host_buf[host_buf_offset..].as_mut_ptr() as *mut wasm32::__wasi_dirent_t,
+                entry,
+            )
+        };
+        host_buf_offset += entry_size;
+        host_buf[host_buf_offset..host_buf_offset + name.len()].copy_from_slice(name);
+        host_buf_offset += name.len();
+        left -= required_space;
+    }
+    Ok(host_buf.len() - left)
+}
+
+pub(crate) fn path_create_directory(
+    ctx: &WasiCtx,
+    dirfd: host::__wasi_fd_t,
+    path: &str,
+) -> Result<(), host::__wasi_errno_t> {
+    let (dir, path) = path_get(
+        ctx,
+        dirfd,
+        0,
+        path,
+        host::__WASI_RIGHT_PATH_OPEN | host::__WASI_RIGHT_PATH_CREATE_DIRECTORY,
+        0,
+        false,
+    )?;
+    dir.create_directory(&path)
+}
+
+pub(crate) fn path_link(
+    ctx: &WasiCtx,
+    old_dirfd: host::__wasi_fd_t,
+    new_dirfd: host::__wasi_fd_t,
+    old_path: &str,
+    new_path: &str,
+    source_rights: host::__wasi_rights_t,
+    target_rights: host::__wasi_rights_t,
+) -> Result<(), host::__wasi_errno_t> {
+    if !is_virtual(ctx, old_dirfd) || !is_virtual(ctx, new_dirfd) {
+        return Err(host::__WASI_EXDEV);
+    }
+    let (old_dir, old_path) = path_get(ctx, old_dirfd, 0, old_path, source_rights, 0, false)?;
+    let (new_dir, new_path) = path_get(ctx, new_dirfd, 0, new_path, target_rights, 0, false)?;
+    old_dir.link(&old_path, &*new_dir, &new_path)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn path_open(
+    ctx: &WasiCtx,
+    dirfd: host::__wasi_fd_t,
+    dirflags: host::__wasi_lookupflags_t,
+    path: &str,
+    oflags: host::__wasi_oflags_t,
+    read: bool,
+    write: bool,
+    mut needed_base: host::__wasi_rights_t,
+    mut needed_inheriting: host::__wasi_rights_t,
+    fs_flags: host::__wasi_fdflags_t,
+) -> Result<FdEntry, host::__wasi_errno_t> {
+    if oflags & host::__WASI_O_CREAT != 0 {
+        needed_base |= host::__WASI_RIGHT_PATH_CREATE_FILE;
+    }
+    if oflags & host::__WASI_O_TRUNC != 0 {
+        needed_base |= host::__WASI_RIGHT_PATH_FILESTAT_SET_SIZE;
+    }
+    if fs_flags & host::__WASI_FDFLAG_DSYNC != 0 {
+        needed_inheriting |= host::__WASI_RIGHT_FD_DATASYNC;
+    }
+    if fs_flags & (host::__WASI_FDFLAG_RSYNC | host::__WASI_FDFLAG_SYNC) != 0 {
+        needed_inheriting |= host::__WASI_RIGHT_FD_SYNC;
+    }
+
+    let (dir, path) = path_get(
+        ctx,
+        dirfd,
+        dirflags,
+        path,
+        needed_base,
+        needed_inheriting,
+        oflags & host::__WASI_O_CREAT != 0,
+    )?;
+
+    let file = dir.openat(&path, read, write, oflags, fs_flags)?;
+    let fe = FdEntry::from_virtual(file);
+    // mirror the access mode restrictions applied to host files
+    if !write {
+        fe.restrict_rights(!host::__WASI_RIGHT_FD_WRITE, host::RIGHTS_ALL);
+    } else if !read {
+        fe.restrict_rights(!host::__WASI_RIGHT_FD_READ, host::RIGHTS_ALL);
+    }
+    Ok(fe)
+}
+
+pub(crate) fn path_readlink(
+    wasi_ctx: &WasiCtx,
+    dirfd: host::__wasi_fd_t,
+    path: &str,
+    rights: host::__wasi_rights_t,
+    buf: &mut [u8],
+) -> Result<usize, host::__wasi_errno_t> {
+    let (dir, path) = path_get(wasi_ctx, dirfd, 0, path, rights, 0, false)?;
+    let link = dir.readlinkat(&path)?;
+    let link = link.as_bytes();
+    let len = std::cmp::min(link.len(), buf.len());
+    buf[..len].copy_from_slice(&link[..len]);
+    Ok(len)
+}
+
+pub(crate) fn path_rename(
+    wasi_ctx: &WasiCtx,
+    old_dirfd: host::__wasi_fd_t,
+    old_path: &str,
+    old_rights: host::__wasi_rights_t,
+    new_dirfd: host::__wasi_fd_t,
+    new_path: &str,
+    new_rights: host::__wasi_rights_t,
+) -> Result<(), host::__wasi_errno_t> {
+    if !is_virtual(wasi_ctx, old_dirfd) || !is_virtual(wasi_ctx, new_dirfd) {
+        return Err(host::__WASI_EXDEV);
+    }
+    let (old_dir, old_path) = path_get(wasi_ctx, old_dirfd, 0, old_path, old_rights, 0, false)?;
+    let (new_dir, new_path) = path_get(wasi_ctx, new_dirfd, 0, new_path, new_rights, 0, false)?;
+    old_dir.rename(&old_path, &*new_dir, &new_path)
+}
+
+pub(crate) fn path_filestat_get(
+    wasi_ctx: &WasiCtx,
+    dirfd: host::__wasi_fd_t,
+    dirflags: host::__wasi_lookupflags_t,
+    path: &str,
+) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t> {
+    let (dir, path) = path_get(
+        wasi_ctx,
+        dirfd,
+        dirflags,
+        path,
+        host::__WASI_RIGHT_PATH_FILESTAT_GET,
+        0,
+        false,
+    )?;
+    dir.path_filestat_get(&path)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn path_filestat_set_times(
+    wasi_ctx: &WasiCtx,
+    dirfd: host::__wasi_fd_t,
+    dirflags: host::__wasi_lookupflags_t,
+    path: &str,
+    rights: host::__wasi_rights_t,
+    st_atim: host::__wasi_timestamp_t,
+    st_mtim: host::__wasi_timestamp_t,
+    fst_flags: host::__wasi_fstflags_t,
+) -> Result<(), host::__wasi_errno_t> {
+    let (dir, path) = path_get(wasi_ctx, dirfd, dirflags, path, rights, 0, false)?;
+    dir.path_filestat_set_times(&path, st_atim, st_mtim, fst_flags)
+}
+
+pub(crate) fn path_symlink(
+    wasi_ctx: &WasiCtx,
+    dirfd: host::__wasi_fd_t,
+    rights: host::__wasi_rights_t,
+    old_path: &str,
+    new_path: &str,
+) -> Result<(), host::__wasi_errno_t> {
+    let (dir, new_path) = path_get(wasi_ctx, dirfd, 0, new_path, rights, 0, false)?;
+    dir.symlink(old_path, &new_path)
+}
+
+pub(crate) fn path_unlink_file(
+    wasi_ctx: &WasiCtx,
+    dirfd: host::__wasi_fd_t,
+    path: &str,
+    rights: host::__wasi_rights_t,
+) -> Result<(), host::__wasi_errno_t> {
+    let (dir, path) = path_get(wasi_ctx, dirfd, 0, path, rights, 0, false)?;
+    dir.unlink_file(&path)
+}
+
+pub(crate) fn path_remove_directory(
+    wasi_ctx: &WasiCtx,
+    dirfd: host::__wasi_fd_t,
+    path: &str,
+    rights: host::__wasi_rights_t,
+) -> Result<(), host::__wasi_errno_t> {
+    let (dir, path) = path_get(wasi_ctx, dirfd, 0, path, rights, 0, false)?;
+    dir.remove_directory(&path)
+}
@@ -0,0 +1,1200 @@
+//! An in-memory filesystem which can be mounted as a preopen.
+use super::{Dirent, VirtualFile};
+use crate::clocks::{HostClocks, WasiClocks};
+use crate::host;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const ROOT_INO: host::__wasi_inode_t = 1;
+
+/// A handle to an in-memory directory tree.
+///
+/// Cloning a `MemFs` yields another handle to the same tree, which makes it possible to seed the
+/// filesystem before passing it to `WasiCtxBuilder::preopened_memfs`, and to inspect what the
+/// guest left behind once it has finished. The contents are discarded when the last handle,
+/// including the ones held by the `WasiCtx`, is dropped.
+///
+/// The total size of the regular files in the tree is capped, so that a guest can't exhaust the
+/// memory of the host: growing a file past the cap fails with `__WASI_EFBIG`, and growing it while
+/// the other files already take up the remaining space fails with `__WASI_ENOSPC`.
+///
+/// Timestamps are taken from the host's realtime clock until the filesystem is preopened, and
+/// from the clocks of the `WasiCtx` it's preopened in from then on.
+#[derive(Debug, Clone)]
+pub struct MemFs(Arc<Mutex<Tree>>);
+
+#[derive(Debug)]
+struct Tree {
+    nodes: HashMap<host::__wasi_inode_t, Node>,
+    next_ino: host::__wasi_inode_t,
+    // total size of the regular files, which may not exceed `limit`
+    used: host::__wasi_filesize_t,
+    limit: host::__wasi_filesize_t,
+    clocks: Arc<dyn WasiClocks>,
+}
+
+#[derive(Debug)]
+struct Node {
+    content: Content,
+    nlink: host::__wasi_linkcount_t,
+    // number of open `MemFile`s referring to this node, which keep it alive once unlinked
+    open: usize,
+    atim: host::__wasi_timestamp_t,
+    mtim: host::__wasi_timestamp_t,
+    ctim: host::__wasi_timestamp_t,
+}
+
+#[derive(Debug)]
+enum Content {
+    File(Vec<u8>),
+    Directory {
+        entries: BTreeMap<String, host::__wasi_inode_t>,
+        parent: host::__wasi_inode_t,
+    },
+    Symlink(String),
+}
+
+impl Content {
+    fn file_type(&self) -> host::__wasi_filetype_t {
+        match self {
+            Content::File(_) => host::__WASI_FILETYPE_REGULAR_FILE,
+            Content::Directory { .. } => host::__WASI_FILETYPE_DIRECTORY,
+            Content::Symlink(_) => host::__WASI_FILETYPE_SYMBOLIC_LINK,
+        }
+    }
+
+    fn size(&self) -> host::__wasi_filesize_t {
+        match self {
+            Content::File(data) => data.len() as host::__wasi_filesize_t,
+            Content::Directory { entries, .. } => entries.len() as host::__wasi_filesize_t,
+            Content::Symlink(target) => target.len() as host::__wasi_filesize_t,
+        }
+    }
+}
+
+/// Strips the trailing slashes off a path component, returning whether there were any.
+fn split_name(path: &str) -> Result<(&str, bool), host::__wasi_errno_t> {
+    let name = path.trim_end_matches('/');
+    if name.is_empty() {
+        return Err(host::__WASI_ENOENT);
+    }
+    Ok((name, name.len() != path.len()))
+}
+
+impl Tree {
+    fn new(limit: host::__wasi_filesize_t) -> Self {
+        let mut tree = Self {
+            nodes: HashMap::new(),
+            next_ino: ROOT_INO + 1,
+            used: 0,
+            limit,
+            clocks: Arc::new(HostClocks),
+        };
+        let root = Node::new(
+            Content::Directory {
+                entries: BTreeMap::new(),
+                parent: ROOT_INO,
+            },
+            tree.now(),
+        );
+        tree.nodes.insert(ROOT_INO, root);
+        tree
+    }
+
+    fn now(&self) -> host::__wasi_timestamp_t {
+        self.clocks.now(host::__WASI_CLOCK_REALTIME).unwrap_or(0)
+    }
+
+    fn node(&self, ino: host::__wasi_inode_t) -> Result<&Node, host::__wasi_errno_t> {
+        self.nodes.get(&ino).ok_or(host::__WASI_ENOENT)
+    }
+
+    fn node_mut(&mut self, ino: host::__wasi_inode_t) -> Result<&mut Node, host::__wasi_errno_t> {
+        self.nodes.get_mut(&ino).ok_or(host::__WASI_ENOENT)
+    }
+
+    fn is_dir(&self, ino: host::__wasi_inode_t) -> bool {
+        match self.nodes.get(&ino) {
+            Some(Node {
+                content: Content::Directory { .. },
+                ..
+            }) => true,
+            _ => false,
+        }
+    }
+
+    fn entries(
+        &self,
+        dir: host::__wasi_inode_t,
+    ) -> Result<&BTreeMap<String, host::__wasi_inode_t>, host::__wasi_errno_t> {
+        match &self.node(dir)?.content {
+            Content::Directory { entries, .. } => Ok(entries),
+            _ => Err(host::__WASI_ENOTDIR),
+        }
+    }
+
+    fn entries_mut(
+        &mut self,
+        dir: host::__wasi_inode_t,
+    ) -> Result<&mut BTreeMap<String, host::__wasi_inode_t>, host::__wasi_errno_t> {
+        let node = self.node_mut(dir)?;
+        // a removed directory cannot gain new entries
+        if node.nlink == 0 {
+            return Err(host::__WASI_ENOENT);
+        }
+        match &mut node.content {
+            Content::Directory { entries, .. } => Ok(entries),
+            _ => Err(host::__WASI_ENOTDIR),
+        }
+    }
+
+    fn parent(
+        &self,
+        dir: host::__wasi_inode_t,
+    ) -> Result<host::__wasi_inode_t, host::__wasi_errno_t> {
+        match &self.node(dir)?.content {
+            Content::Directory { parent, .. } => Ok(*parent),
+            _ => Err(host::__WASI_ENOTDIR),
+        }
+    }
+
+    fn lookup(
+        &self,
+        dir: host::__wasi_inode_t,
+        name: &str,
+    ) -> Result<Option<host::__wasi_inode_t>, host::__wasi_errno_t> {
+        match name {
+            "." => self.entries(dir).map(|_| Some(dir)),
+            ".." => self.parent(dir).map(Some),
+            name => Ok(self.entries(dir)?.get(name).cloned()),
+        }
+    }
+
+    fn insert(
+        &mut self,
+        dir: host::__wasi_inode_t,
+        name: &str,
+        content: Content,
+    ) -> Result<host::__wasi_inode_t, host::__wasi_errno_t> {
+        let ino = self.next_ino;
+        let entries = self.entries_mut(dir)?;
+        if entries.contains_key(name) {
+            return Err(host::__WASI_EEXIST);
+        }
+        entries.insert(name.to_owned(), ino);
+        let mut node = Node::new(content, self.now());
+        if let Content::Directory { parent, .. } = &mut node.content {
+            *parent = dir;
+        }
+        self.nodes.insert(ino, node);
+        self.next_ino += 1;
+        self.touch(dir);
+        Ok(ino)
+    }
+
+    /// Removes the entry `name` from `dir`, freeing the node it referred to if that was its last
+    /// link and no handle keeps it open.
+    fn unlink(
+        &mut self,
+        dir: host::__wasi_inode_t,
+        name: &str,
+    ) -> Result<host::__wasi_inode_t, host::__wasi_errno_t> {
+        let ino = self
+            .entries_mut(dir)?
+            .remove(name)
+            .ok_or(host::__WASI_ENOENT)?;
+        self.touch(dir);
+        let now = self.now();
+        if let Some(node) = self.nodes.get_mut(&ino) {
+            node.nlink = node.nlink.saturating_sub(1);
+            node.ctim = now;
+        }
+        self.release(ino);
+        Ok(ino)
+    }
+
+    fn release(&mut self, ino: host::__wasi_inode_t) {
+        let unused = match self.nodes.get(&ino) {
+            Some(node) => node.nlink == 0 && node.open == 0,
+            None => false,
+        };
+        if unused {
+            if let Some(Node {
+                content: Content::File(data),
+                ..
+            }) = self.nodes.remove(&ino)
+            {
+                self.used -= data.len() as host::__wasi_filesize_t;
+            }
+        }
+    }
+
+    fn file_mut(
+        &mut self,
+        ino: host::__wasi_inode_t,
+    ) -> Result<&mut Vec<u8>, host::__wasi_errno_t> {
+        match &mut self.node_mut(ino)?.content {
+            Content::File(data) => Ok(data),
+            Content::Directory { .. } => Err(host::__WASI_EISDIR),
+            Content::Symlink(_) => Err(host::__WASI_EBADF),
+        }
+    }
+
+    /// Truncates or zero-extends a regular file, within the size limit of the tree.
+    fn resize(
+        &mut self,
+        ino: host::__wasi_inode_t,
+        len: host::__wasi_filesize_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        if len > self.limit || len > isize::max_value() as u64 {
+            return Err(host::__WASI_EFBIG);
+        }
+        let limit = self.limit;
+        let used = self.used;
+        let data = self.file_mut(ino)?;
+        let used = used - data.len() as host::__wasi_filesize_t + len;
+        if used > limit {
+            return Err(host::__WASI_ENOSPC);
+        }
+        data.resize(len as usize, 0);
+        self.used = used;
+        Ok(())
+    }
+
+    fn touch(&mut self, ino: host::__wasi_inode_t) {
+        let now = self.now();
+        if let Some(node) = self.nodes.get_mut(&ino) {
+            node.mtim = now;
+            node.ctim = now;
+        }
+    }
+
+    fn filestat(
+        &self,
+        ino: host::__wasi_inode_t,
+    ) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t> {
+        let node = self.node(ino)?;
+        Ok(host::__wasi_filestat_t {
+            st_dev: 0,
+            st_ino: ino,
+            st_filetype: node.content.file_type(),
+            st_nlink: node.nlink,
+            st_size: node.content.size(),
+            st_atim: node.atim,
+            st_mtim: node.mtim,
+            st_ctim: node.ctim,
+        })
+    }
+
+    fn set_times(
+        &mut self,
+        ino: host::__wasi_inode_t,
+        st_atim: host::__wasi_timestamp_t,
+        st_mtim: host::__wasi_timestamp_t,
+        fst_flags: host::__wasi_fstflags_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        let set_atim = fst_flags & host::__WASI_FILESTAT_SET_ATIM != 0;
+        let set_atim_now = fst_flags & host::__WASI_FILESTAT_SET_ATIM_NOW != 0;
+        let set_mtim = fst_flags & host::__WASI_FILESTAT_SET_MTIM != 0;
+        let set_mtim_now = fst_flags & host::__WASI_FILESTAT_SET_MTIM_NOW != 0;
+        if (set_atim && set_atim_now) || (set_mtim && set_mtim_now) {
+            return Err(host::__WASI_EINVAL);
+        }
+        let now = self.now();
+        let node = self.node_mut(ino)?;
+        if set_atim {
+            node.atim = st_atim;
+        } else if set_atim_now {
+            node.atim = now;
+        }
+        if set_mtim {
+            node.mtim = st_mtim;
+        } else if set_mtim_now {
+            node.mtim = now;
+        }
+        node.ctim = now;
+        Ok(())
+    }
+
+    /// Resolves a path relative to the root, without following symlinks. Used by the embedder
+    /// facing API of `MemFs`.
+    fn resolve(&self, path: &str) -> Result<host::__wasi_inode_t, host::__wasi_errno_t> {
+        path.split('/')
+            .filter(|c| !c.is_empty())
+            .try_fold(ROOT_INO, |dir, name| {
+                self.lookup(dir, name)?.ok_or(host::__WASI_ENOENT)
+            })
+    }
+
+    /// Resolves all but the last component of a path relative to the root.
+    fn resolve_parent<'a>(
+        &self,
+        path: &'a str,
+    ) -> Result<(host::__wasi_inode_t, &'a str), host::__wasi_errno_t> {
+        let path = path.trim_end_matches('/');
+        let (dir, name) = match path.rfind('/') {
+            Some(idx) => (self.resolve(&path[..idx])?, &path[idx + 1..]),
+            None => (ROOT_INO, path),
+        };
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(host::__WASI_EINVAL);
+        }
+        Ok((dir, name))
+    }
+}
+
+impl Node {
+    fn new(content: Content, now: host::__wasi_timestamp_t) -> Self {
+        Self {
+            content,
+            nlink: 1,
+            open: 0,
+            atim: now,
+            mtim: now,
+            ctim: now,
+        }
+    }
+}
+
+impl Default for MemFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemFs {
+    /// Size limit of a filesystem created with `MemFs::new`, in bytes.
+    pub const DEFAULT_SIZE_LIMIT: host::__wasi_filesize_t = 256 << 20;
+
+    /// Creates a new, empty in-memory filesystem holding at most `DEFAULT_SIZE_LIMIT` bytes.
+    pub fn new() -> Self {
+        Self::with_size_limit(Self::DEFAULT_SIZE_LIMIT)
+    }
+
+    /// Creates a new, empty in-memory filesystem whose regular files may take up at most `limit`
+    /// bytes in total.
+    pub fn with_size_limit(limit: host::__wasi_filesize_t) -> Self {
+        MemFs(Arc::new(Mutex::new(Tree::new(limit))))
+    }
+
+    fn tree(&self) -> MutexGuard<'_, Tree> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes the timestamps of the files from now on from `clocks`. If time is simulated, the
+    /// files already in the tree are stamped with the current time, as their timestamps come
+    /// from the host.
+    pub(crate) fn use_clocks(&self, clocks: Arc<dyn WasiClocks>) {
+        let mut tree = self.tree();
+        tree.clocks = clocks;
+        if tree.clocks.is_simulated() {
+            let now = tree.now();
+            for node in tree.nodes.values_mut() {
+                node.atim = now;
+                node.mtim = now;
+                node.ctim = now;
+            }
+        }
+    }
+
+    fn open(&self, ino: host::__wasi_inode_t, read: bool, write: bool, append: bool) -> MemFile {
+        if let Some(node) = self.tree().nodes.get_mut(&ino) {
+            node.open += 1;
+        }
+        MemFile {
+            fs: self.clone(),
+            ino,
+            read,
+            write,
+            cursor: Mutex::new(0),
+            append: AtomicBool::new(append),
+        }
+    }
+
+    /// Returns a handle to the root directory, suitable for `WasiCtxBuilder::preopened_virt`.
+    pub fn root(&self) -> Box<dyn VirtualFile> {
+        Box::new(self.open(ROOT_INO, true, false, false))
+    }
+
+    /// Creates a directory, along with any missing parent directories.
+    pub fn create_dir_all(&self, path: &str) -> Result<(), host::__wasi_errno_t> {
+        let mut tree = self.tree();
+        let mut dir = ROOT_INO;
+        for name in path.split('/').filter(|c| !c.is_empty()) {
+            dir = match tree.lookup(dir, name)? {
+                Some(ino) if tree.is_dir(ino) => ino,
+                Some(_) => return Err(host::__WASI_ENOTDIR),
+                None => tree.insert(
+                    dir,
+                    name,
+                    Content::Directory {
+                        entries: BTreeMap::new(),
+                        parent: dir,
+                    },
+                )?,
+            };
+        }
+        Ok(())
+    }
+
+    /// Creates a file with the given contents, replacing the contents of an existing file.
+    pub fn write_file(
+        &self,
+        path: &str,
+        contents: impl AsRef<[u8]>,
+    ) -> Result<(), host::__wasi_errno_t> {
+        let mut tree = self.tree();
+        let (dir, name) = tree.resolve_parent(path)?;
+        let ino = match tree.lookup(dir, name)? {
+            Some(ino) => ino,
+            None => tree.insert(dir, name, Content::File(Vec::new()))?,
+        };
+        if let Content::Symlink(_) = tree.node(ino)?.content {
+            return Err(host::__WASI_ELOOP);
+        }
+        let contents = contents.as_ref();
+        tree.resize(ino, 0)?;
+        tree.resize(ino, contents.len() as host::__wasi_filesize_t)?;
+        tree.file_mut(ino)?.copy_from_slice(contents);
+        tree.touch(ino);
+        Ok(())
+    }
+
+    /// Creates a symlink at `path` pointing to `target`.
+    pub fn symlink(&self, target: &str, path: &str) -> Result<(), host::__wasi_errno_t> {
+        let mut tree = self.tree();
+        let (dir, name) = tree.resolve_parent(path)?;
+        tree.insert(dir, name, Content::Symlink(target.to_owned()))
+            .map(|_| ())
+    }
+
+    /// Sets the access and modification times of the file at `path`.
+    pub fn set_times(
+        &self,
+        path: &str,
+        st_atim: host::__wasi_timestamp_t,
+        st_mtim: host::__wasi_timestamp_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        let mut tree = self.tree();
+        let ino = tree.resolve(path)?;
+        tree.set_times(
+            ino,
+            st_atim,
+            st_mtim,
+            host::__WASI_FILESTAT_SET_ATIM | host::__WASI_FILESTAT_SET_MTIM,
+        )
+    }
+
+    /// Returns the contents of the file at `path`.
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, host::__wasi_errno_t> {
+        let tree = self.tree();
+        match &tree.node(tree.resolve(path)?)?.content {
+            Content::File(data) => Ok(data.clone()),
+            Content::Directory { .. } => Err(host::__WASI_EISDIR),
+            Content::Symlink(_) => Err(host::__WASI_ELOOP),
+        }
+    }
+
+    /// Returns the names of the entries of the directory at `path`, in lexicographic order.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<String>, host::__wasi_errno_t> {
+        let tree = self.tree();
+        let entries = tree.entries(tree.resolve(path)?)?;
+        Ok(entries.keys().cloned().collect())
+    }
+
+    /// Returns the target of the symlink at `path`.
+    pub fn read_link(&self, path: &str) -> Result<String, host::__wasi_errno_t> {
+        let tree = self.tree();
+        match &tree.node(tree.resolve(path)?)?.content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(host::__WASI_EINVAL),
+        }
+    }
+
+    /// Returns the metadata of the file at `path`, without following a final symlink.
+    pub fn filestat(&self, path: &str) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t> {
+        let tree = self.tree();
+        tree.filestat(tree.resolve(path)?)
+    }
+}
+
+/// An open file or directory of a `MemFs`.
+#[derive(Debug)]
+struct MemFile {
+    fs: MemFs,
+    ino: host::__wasi_inode_t,
+    /// The access mode the handle was opened with, checked like the host checks `O_RDONLY` and
+    /// `O_WRONLY` descriptors.
+    read: bool,
+    write: bool,
+    cursor: Mutex<host::__wasi_filesize_t>,
+    append: AtomicBool,
+}
+
+impl Drop for MemFile {
+    fn drop(&mut self) {
+        let mut tree = self.fs.tree();
+        if let Some(node) = tree.nodes.get_mut(&self.ino) {
+            node.open -= 1;
+        }
+        tree.release(self.ino);
+    }
+}
+
+impl MemFile {
+    fn cursor(&self) -> MutexGuard<'_, host::__wasi_filesize_t> {
+        self.cursor.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn check_writable(&self) -> Result<(), host::__wasi_errno_t> {
+        if self.write {
+            Ok(())
+        } else {
+            Err(host::__WASI_EBADF)
+        }
+    }
+
+    /// Downcasts the target directory of `link` and `rename`, which must belong to the same tree.
+    fn same_fs<'a>(&self, dir: &'a dyn VirtualFile) -> Result<&'a MemFile, host::__wasi_errno_t> {
+        match dir.as_any().downcast_ref::<MemFile>() {
+            Some(dir) if Arc::ptr_eq(&self.fs.0, &dir.fs.0) => Ok(dir),
+            _ => Err(host::__WASI_EXDEV),
+        }
+    }
+
+    fn read_at(
+        &self,
+        iovs: &mut [io::IoSliceMut],
+        offset: host::__wasi_filesize_t,
+    ) -> Result<usize, host::__wasi_errno_t> {
+        if !self.read {
+            return Err(host::__WASI_EBADF);
+        }
+        let mut tree = self.fs.tree();
+        let now = tree.now();
+        let node = tree.node_mut(self.ino)?;
+        let data = match &node.content {
+            Content::File(data) => data,
+            Content::Directory { .. } => return Err(host::__WASI_EISDIR),
+            Content::Symlink(_) => return Err(host::__WASI_EBADF),
+        };
+        let mut pos = std::cmp::min(offset, data.len() as u64) as usize;
+        let mut nread = 0;
+        for iov in iovs.iter_mut() {
+            let len = std::cmp::min(iov.len(), data.len() - pos);
+            iov[..len].copy_from_slice(&data[pos..pos + len]);
+            pos += len;
+            nread += len;
+        }
+        node.atim = now;
+        Ok(nread)
+    }
+
+    fn write_at(
+        &self,
+        iovs: &[io::IoSlice],
+        offset: Option<host::__wasi_filesize_t>,
+    ) -> Result<(usize, host::__wasi_filesize_t), host::__wasi_errno_t> {
+        self.check_writable()?;
+        let mut tree = self.fs.tree();
+        let len = tree.file_mut(self.ino)?.len() as u64;
+        // `None` means the write happens at the end of the file
+        let pos = offset.unwrap_or(len);
+        let nwritten: usize = iovs.iter().map(|iov| iov.len()).sum();
+        let end = pos.checked_add(nwritten as u64).ok_or(host::__WASI_EFBIG)?;
+        if end > len {
+            tree.resize(self.ino, end)?;
+        }
+        let data = tree.file_mut(self.ino)?;
+        let mut pos = pos as usize;
+        for iov in iovs {
+            data[pos..pos + iov.len()].copy_from_slice(iov);
+            pos += iov.len();
+        }
+        tree.touch(self.ino);
+        Ok((nwritten, end))
+    }
+}
+
+impl VirtualFile for MemFile {
+    fn file_type(&self) -> host::__wasi_filetype_t {
+        self.fs
+            .tree()
+            .node(self.ino)
+            .map(|node| node.content.file_type())
+            .unwrap_or(host::__WASI_FILETYPE_UNKNOWN)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn VirtualFile>, host::__wasi_errno_t> {
+        Ok(Box::new(self.fs.open(
+            self.ino,
+            self.read,
+            self.write,
+            self.append.load(Ordering::Relaxed),
+        )))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn openat(
+        &self,
+        path: &str,
+        read: bool,
+        write: bool,
+        oflags: host::__wasi_oflags_t,
+        fs_flags: host::__wasi_fdflags_t,
+    ) -> Result<Box<dyn VirtualFile>, host::__wasi_errno_t> {
+        let (name, must_be_dir) = split_name(path)?;
+        let ino = {
+            let mut tree = self.fs.tree();
+            let ino = match tree.lookup(self.ino, name)? {
+                Some(ino) => {
+                    if oflags & host::__WASI_O_CREAT != 0 && oflags & host::__WASI_O_EXCL != 0 {
+                        return Err(host::__WASI_EEXIST);
+                    }
+                    ino
+                }
+                None if oflags & host::__WASI_O_CREAT != 0 => {
+                    if must_be_dir {
+                        return Err(host::__WASI_EISDIR);
+                    }
+                    tree.insert(self.ino, name, Content::File(Vec::new()))?
+                }
+                None => return Err(host::__WASI_ENOENT),
+            };
+            match &tree.node(ino)?.content {
+                Content::Symlink(_) => return Err(host::__WASI_ELOOP),
+                Content::Directory { .. } => {
+                    if write {
+                        return Err(host::__WASI_EISDIR);
+                    }
+                }
+                Content::File(_) => {
+                    if must_be_dir || oflags & host::__WASI_O_DIRECTORY != 0 {
+                        return Err(host::__WASI_ENOTDIR);
+                    }
+                    if oflags & host::__WASI_O_TRUNC != 0 {
+                        tree.resize(ino, 0)?;
+                        tree.touch(ino);
+                    }
+                }
+            }
+            ino
+        };
+        // like `O_RDONLY`, asking for neither reading nor writing allows reading
+        Ok(Box::new(self.fs.open(
+            ino,
+            read || !write,
+            write,
+            fs_flags & host::__WASI_FDFLAG_APPEND != 0,
+        )))
+    }
+
+    fn readlinkat(&self, path: &str) -> Result<String, host::__wasi_errno_t> {
+        let (name, _) = split_name(path)?;
+        let tree = self.fs.tree();
+        let ino = tree.lookup(self.ino, name)?.ok_or(host::__WASI_ENOENT)?;
+        match &tree.node(ino)?.content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(host::__WASI_EINVAL),
+        }
+    }
+
+    fn create_directory(&self, path: &str) -> Result<(), host::__wasi_errno_t> {
+        let (name, _) = split_name(path)?;
+        let mut tree = self.fs.tree();
+        if tree.lookup(self.ino, name)?.is_some() {
+            return Err(host::__WASI_EEXIST);
+        }
+        tree.insert(
+            self.ino,
+            name,
+            Content::Directory {
+                entries: BTreeMap::new(),
+                parent: self.ino,
+            },
+        )
+        .map(|_| ())
+    }
+
+    fn remove_directory(&self, path: &str) -> Result<(), host::__wasi_errno_t> {
+        let (name, _) = split_name(path)?;
+        match name {
+            "." => return Err(host::__WASI_EINVAL),
+            ".." => return Err(host::__WASI_ENOTEMPTY),
+            _ => {}
+        }
+        let mut tree = self.fs.tree();
+        let ino = tree.lookup(self.ino, name)?.ok_or(host::__WASI_ENOENT)?;
+        if !tree.entries(ino)?.is_empty() {
+            return Err(host::__WASI_ENOTEMPTY);
+        }
+        tree.unlink(self.ino, name).map(|_| ())
+    }
+
+    fn unlink_file(&self, path: &str) -> Result<(), host::__wasi_errno_t> {
+        let (name, must_be_dir) = split_name(path)?;
+        let mut tree = self.fs.tree();
+        let ino = tree.lookup(self.ino, name)?.ok_or(host::__WASI_ENOENT)?;
+        if tree.is_dir(ino) {
+            return Err(host::__WASI_EISDIR);
+        }
+        if must_be_dir {
+            return Err(host::__WASI_ENOTDIR);
+        }
+        tree.unlink(self.ino, name).map(|_| ())
+    }
+
+    fn symlink(&self, old_path: &str, new_path: &str) -> Result<(), host::__wasi_errno_t> {
+        let (name, _) = split_name(new_path)?;
+        let mut tree = self.fs.tree();
+        if tree.lookup(self.ino, name)?.is_some() {
+            return Err(host::__WASI_EEXIST);
+        }
+        tree.insert(self.ino, name, Content::Symlink(old_path.to_owned()))
+            .map(|_| ())
+    }
+
+    fn link(
+        &self,
+        old_path: &str,
+        new_dir: &dyn VirtualFile,
+        new_path: &str,
+    ) -> Result<(), host::__wasi_errno_t> {
+        let new_dir = self.same_fs(new_dir)?;
+        let (old_name, _) = split_name(old_path)?;
+        let (new_name, _) = split_name(new_path)?;
+        let mut tree = self.fs.tree();
+        let ino = tree
+            .lookup(self.ino, old_name)?
+            .ok_or(host::__WASI_ENOENT)?;
+        if tree.is_dir(ino) {
+            return Err(host::__WASI_EPERM);
+        }
+        let entries = tree.entries_mut(new_dir.ino)?;
+        if entries.contains_key(new_name) {
+            return Err(host::__WASI_EEXIST);
+        }
+        entries.insert(new_name.to_owned(), ino);
+        tree.touch(new_dir.ino);
+        let now = tree.now();
+        let node = tree.node_mut(ino)?;
+        node.nlink += 1;
+        node.ctim = now;
+        Ok(())
+    }
+
+    fn rename(
+        &self,
+        old_path: &str,
+        new_dir: &dyn VirtualFile,
+        new_path: &str,
+    ) -> Result<(), host::__wasi_errno_t> {
+        let new_dir = self.same_fs(new_dir)?;
+        let (old_name, old_slash) = split_name(old_path)?;
+        let (new_name, new_slash) = split_name(new_path)?;
+        if old_name == "." || old_name == ".." || new_name == "." || new_name == ".." {
+            return Err(host::__WASI_EINVAL);
+        }
+        let mut tree = self.fs.tree();
+        let src = tree
+            .lookup(self.ino, old_name)?
+            .ok_or(host::__WASI_ENOENT)?;
+        let src_is_dir = tree.is_dir(src);
+        if (old_slash || new_slash) && !src_is_dir {
+            return Err(host::__WASI_ENOTDIR);
+        }
+        let dst = tree.lookup(new_dir.ino, new_name)?;
+        if let Some(dst) = dst {
+            if dst == src {
+                return Ok(());
+            }
+            match (src_is_dir, tree.is_dir(dst)) {
+                (true, true) => {
+                    if !tree.entries(dst)?.is_empty() {
+                        return Err(host::__WASI_ENOTEMPTY);
+                    }
+                }
+                (true, false) => return Err(host::__WASI_ENOTDIR),
+                (false, true) => return Err(host::__WASI_EISDIR),
+                (false, false) => {}
+            }
+        }
+        if src_is_dir {
+            // a directory cannot be moved beneath itself
+            let mut ancestor = new_dir.ino;
+            loop {
+                if ancestor == src {
+                    return Err(host::__WASI_EINVAL);
+                }
+                if ancestor == ROOT_INO {
+                    break;
+                }
+                ancestor = tree.parent(ancestor)?;
+            }
+        }
+
+        if dst.is_some() {
+            tree.unlink(new_dir.ino, new_name)?;
+        }
+        tree.entries_mut(self.ino)?.remove(old_name);
+        tree.entries_mut(new_dir.ino)?
+            .insert(new_name.to_owned(), src);
+        tree.touch(self.ino);
+        tree.touch(new_dir.ino);
+        let now = tree.now();
+        let node = tree.node_mut(src)?;
+        if let Content::Directory { parent, .. } = &mut node.content {
+            *parent = new_dir.ino;
+        }
+        node.ctim = now;
+        Ok(())
+    }
+
+    fn path_filestat_get(
+        &self,
+        path: &str,
+    ) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t> {
+        let (name, _) = split_name(path)?;
+        let tree = self.fs.tree();
+        let ino = tree.lookup(self.ino, name)?.ok_or(host::__WASI_ENOENT)?;
+        tree.filestat(ino)
+    }
+
+    fn path_filestat_set_times(
+        &self,
+        path: &str,
+        st_atim: host::__wasi_timestamp_t,
+        st_mtim: host::__wasi_timestamp_t,
+        fst_flags: host::__wasi_fstflags_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        let (name, _) = split_name(path)?;
+        let mut tree = self.fs.tree();
+        let ino = tree.lookup(self.ino, name)?.ok_or(host::__WASI_ENOENT)?;
+        tree.set_times(ino, st_atim, st_mtim, fst_flags)
+    }
+
+    fn readdir(
+        &self,
+        cookie: host::__wasi_dircookie_t,
+    ) -> Result<Vec<Dirent>, host::__wasi_errno_t> {
+        let tree = self.fs.tree();
+        let entries = tree.entries(self.ino)?;
+        let parent = tree.parent(self.ino)?;
+        let dot = [(".", self.ino), ("..", parent)];
+        let all = dot
+            .iter()
+            .cloned()
+            .chain(entries.iter().map(|(name, ino)| (name.as_str(), *ino)));
+        let mut dirents = Vec::new();
+        // the cookie of an entry is simply its position in the listing
+        for (idx, (name, ino)) in all.enumerate().skip(cookie as usize) {
+            dirents.push(Dirent {
+                name: name.to_owned(),
+                ino,
+                file_type: tree.node(ino)?.content.file_type(),
+                next: idx as host::__wasi_dircookie_t + 1,
+            });
+        }
+        Ok(dirents)
+    }
+
+    fn read_vectored(&self, iovs: &mut [io::IoSliceMut]) -> Result<usize, host::__wasi_errno_t> {
+        let mut cursor = self.cursor();
+        let nread = self.read_at(iovs, *cursor)?;
+        *cursor += nread as host::__wasi_filesize_t;
+        Ok(nread)
+    }
+
+    fn write_vectored(&self, iovs: &[io::IoSlice]) -> Result<usize, host::__wasi_errno_t> {
+        let mut cursor = self.cursor();
+        let offset = if self.append.load(Ordering::Relaxed) {
+            None
+        } else {
+            Some(*cursor)
+        };
+        let (nwritten, end) = self.write_at(iovs, offset)?;
+        *cursor = end;
+        Ok(nwritten)
+    }
+
+    fn pread(
+        &self,
+        buf: &mut [u8],
+        offset: host::__wasi_filesize_t,
+    ) -> Result<usize, host::__wasi_errno_t> {
+        self.read_at(&mut [io::IoSliceMut::new(buf)], offset)
+    }
+
+    fn pwrite(
+        &self,
+        buf: &[u8],
+        offset: host::__wasi_filesize_t,
+    ) -> Result<usize, host::__wasi_errno_t> {
+        self.write_at(&[io::IoSlice::new(buf)], Some(offset))
+            .map(|(nwritten, _)| nwritten)
+    }
+
+    fn seek(
+        &self,
+        offset: host::__wasi_filedelta_t,
+        whence: host::__wasi_whence_t,
+    ) -> Result<u64, host::__wasi_errno_t> {
+        let mut cursor = self.cursor();
+        let base = match whence {
+            host::__WASI_WHENCE_CUR => *cursor,
+            host::__WASI_WHENCE_END => self.fs.tree().node(self.ino)?.content.size(),
+            host::__WASI_WHENCE_SET => 0,
+            _ => return Err(host::__WASI_EINVAL),
+        };
+        let new_offset = if offset < 0 {
+            base.checked_sub(offset.wrapping_neg() as u64)
+        } else {
+            base.checked_add(offset as u64)
+        };
+        *cursor = new_offset.ok_or(host::__WASI_EINVAL)?;
+        Ok(*cursor)
+    }
+
+    fn fdstat_get(&self) -> Result<host::__wasi_fdflags_t, host::__wasi_errno_t> {
+        if self.append.load(Ordering::Relaxed) {
+            Ok(host::__WASI_FDFLAG_APPEND)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn fdstat_set_flags(
+        &self,
+        fdflags: host::__wasi_fdflags_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        self.append
+            .store(fdflags & host::__WASI_FDFLAG_APPEND != 0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t> {
+        self.fs.tree().filestat(self.ino)
+    }
+
+    fn filestat_set_times(
+        &self,
+        st_atim: host::__wasi_timestamp_t,
+        st_mtim: host::__wasi_timestamp_t,
+        fst_flags: host::__wasi_fstflags_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        self.fs
+            .tree()
+            .set_times(self.ino, st_atim, st_mtim, fst_flags)
+    }
+
+    fn filestat_set_size(
+        &self,
+        st_size: host::__wasi_filesize_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        self.check_writable()?;
+        let mut tree = self.fs.tree();
+        match tree.node(self.ino)?.content {
+            Content::File(_) => tree.resize(self.ino, st_size)?,
+            _ => return Err(host::__WASI_EINVAL),
+        }
+        tree.touch(self.ino);
+        Ok(())
+    }
+
+    fn allocate(
+        &self,
+        offset: host::__wasi_filesize_t,
+        len: host::__wasi_filesize_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        self.check_writable()?;
+        let wanted_size = offset.checked_add(len).ok_or(host::__WASI_EFBIG)?;
+        let mut tree = self.fs.tree();
+        match &tree.node(self.ino)?.content {
+            Content::File(data) if wanted_size > data.len() as u64 => {
+                tree.resize(self.ino, wanted_size)
+            }
+            Content::File(_) => Ok(()),
+            _ => Err(host::__WASI_EBADF),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hostcalls, PreopenRights, SliceMemory, WasiCtxBuilder};
+    use std::time::Duration;
+
+    fn create(dir: &dyn VirtualFile, name: &str) -> Box<dyn VirtualFile> {
+        dir.openat(name, true, true, host::__WASI_O_CREAT, 0)
+            .unwrap()
+    }
+
+    fn read_all(file: &dyn VirtualFile) -> Vec<u8> {
+        let mut buf = [0; 64];
+        let nread = file
+            .read_vectored(&mut [io::IoSliceMut::new(&mut buf)])
+            .unwrap();
+        buf[..nread].to_vec()
+    }
+
+    #[test]
+    fn read_write_seek() {
+        let fs = MemFs::new();
+        let file = create(&*fs.root(), "file");
+        let iovs = [io::IoSlice::new(b"hello "), io::IoSlice::new(b"world")];
+        assert_eq!(file.write_vectored(&iovs), Ok(11));
+        assert_eq!(read_all(&*file), b"");
+        assert_eq!(file.seek(-5, host::__WASI_WHENCE_CUR), Ok(6));
+        assert_eq!(read_all(&*file), b"world");
+        assert_eq!(file.seek(0, host::__WASI_WHENCE_SET), Ok(0));
+        assert_eq!(file.pwrite(b"J", 0), Ok(1));
+        assert_eq!(read_all(&*file), b"Jello world");
+        assert_eq!(
+            file.seek(-1, host::__WASI_WHENCE_SET),
+            Err(host::__WASI_EINVAL)
+        );
+
+        // writing past the end leaves a hole of zeroes
+        assert_eq!(file.pwrite(b"!", 13), Ok(1));
+        assert_eq!(fs.read_file("file").unwrap(), b"Jello world\0\0!");
+    }
+
+    #[test]
+    fn truncate() {
+        let fs = MemFs::new();
+        fs.write_file("file", b"some contents").unwrap();
+        let file = fs.root().openat("file", true, true, 0, 0).unwrap();
+        assert_eq!(file.filestat_set_size(4), Ok(()));
+        assert_eq!(read_all(&*file), b"some");
+        assert_eq!(file.filestat_set_size(6), Ok(()));
+        assert_eq!(fs.read_file("file").unwrap(), b"some\0\0");
+        assert_eq!(file.allocate(2, 2), Ok(()));
+        assert_eq!(file.filestat_get().unwrap().st_size, 6);
+
+        let file = fs
+            .root()
+            .openat("file", true, true, host::__WASI_O_TRUNC, 0)
+            .unwrap();
+        assert_eq!(file.filestat_get().unwrap().st_size, 0);
+    }
+
+    #[test]
+    fn access_mode() {
+        let fs = MemFs::new();
+        fs.write_file("file", b"contents").unwrap();
+        let root = fs.root();
+
+        let file = root.openat("file", true, false, 0, 0).unwrap();
+        let file = file.try_clone().unwrap();
+        let iovs = [io::IoSlice::new(b"EVIL")];
+        assert_eq!(file.write_vectored(&iovs), Err(host::__WASI_EBADF));
+        assert_eq!(file.pwrite(b"EVIL", 0), Err(host::__WASI_EBADF));
+        assert_eq!(file.filestat_set_size(0), Err(host::__WASI_EBADF));
+        assert_eq!(file.allocate(0, 16), Err(host::__WASI_EBADF));
+        assert_eq!(read_all(&*file), b"contents");
+
+        let file = root.openat("file", false, true, 0, 0).unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(
+            file.read_vectored(&mut [io::IoSliceMut::new(&mut buf)]),
+            Err(host::__WASI_EBADF)
+        );
+        assert_eq!(file.pread(&mut buf, 0), Err(host::__WASI_EBADF));
+        assert_eq!(file.pwrite(b"C", 0), Ok(1));
+        assert_eq!(fs.read_file("file").unwrap(), b"Contents");
+    }
+
+    #[test]
+    fn readdir() {
+        let fs = MemFs::new();
+        fs.create_dir_all("dir/sub").unwrap();
+        fs.write_file("dir/file", b"").unwrap();
+        let dir = fs.root().openat("dir", true, false, 0, 0).unwrap();
+        let names = |cookie| {
+            dir.readdir(cookie)
+                .unwrap()
+                .into_iter()
+                .map(|dirent| dirent.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(0), vec![".", "..", "file", "sub"]);
+        // resuming from the cookie of an entry yields the following ones
+        let cookie = dir.readdir(0).unwrap()[2].next;
+        assert_eq!(names(cookie), vec!["sub"]);
+
+        dir.unlink_file("file").unwrap();
+        assert_eq!(names(0), vec![".", "..", "sub"]);
+        assert_eq!(fs.read_dir("dir").unwrap(), vec!["sub"]);
+    }
+
+    #[test]
+    fn timestamps_follow_ctx_clocks() {
+        let fs = MemFs::new();
+        fs.write_file("seeded", b"").unwrap();
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .deterministic(0)
+            .clock_step(Duration::from_micros(5))
+            .preopened_memfs("/memfs", fs.clone(), PreopenRights::ReadWrite)
+            .build()
+            .unwrap();
+        let mtim = |path| {
+            let file = fs.root().openat(path, true, false, 0, 0).unwrap();
+            file.filestat_get().unwrap().st_mtim
+        };
+        assert_eq!(mtim("seeded"), 0);
+        fs.write_file("file", b"contents").unwrap();
+        assert_eq!(mtim("file"), 0);
+
+        // the clock only moves when the guest reads it
+        let mut mem = vec![0; 8];
+        let errno = hostcalls::clock_time_get(
+            &ctx,
+            &SliceMemory::new(&mut mem),
+            host::__WASI_CLOCK_REALTIME,
+            0,
+            0,
+        );
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        fs.write_file("file", b"updated").unwrap();
+        assert_eq!(mtim("file"), 5000);
+        assert_eq!(mtim("."), 0);
+    }
+
+    #[test]
+    fn size_limit() {
+        let fs = MemFs::with_size_limit(16);
+        let root = fs.root();
+        let a = create(&*root, "a");
+        let b = create(&*root, "b");
+
+        assert_eq!(a.filestat_set_size(17), Err(host::__WASI_EFBIG));
+        assert_eq!(a.allocate(16, 1), Err(host::__WASI_EFBIG));
+        assert_eq!(a.pwrite(b"x", u64::max_value()), Err(host::__WASI_EFBIG));
+        assert_eq!(a.filestat_set_size(12), Ok(()));
+        assert_eq!(
+            b.write_vectored(&[io::IoSlice::new(&[1; 5])]),
+            Err(host::__WASI_ENOSPC)
+        );
+        assert_eq!(b.allocate(0, 5), Err(host::__WASI_ENOSPC));
+        assert_eq!(b.write_vectored(&[io::IoSlice::new(&[1; 4])]), Ok(4));
+        assert_eq!(fs.write_file("c", b"x"), Err(host::__WASI_ENOSPC));
+
+        // shrinking a file frees its space
+        assert_eq!(a.filestat_set_size(8), Ok(()));
+        assert_eq!(fs.write_file("c", [2; 4]), Ok(()));
+
+        // so does dropping the last link and handle to it
+        root.unlink_file("a").unwrap();
+        assert_eq!(fs.write_file("d", b"x"), Err(host::__WASI_ENOSPC));
+        drop(a);
+        assert_eq!(fs.write_file("d", [3; 8]), Ok(()));
+    }
+}
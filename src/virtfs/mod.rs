@@ -0,0 +1,411 @@
+//! Virtual filesystem backends for preopened directories and stdio.
+//!
+//! A preopen is normally a host directory, and every hostcall performed on it (or on anything
+//! opened beneath it) ends up in `sys::hostcalls_impl`. A preopen can instead be backed by any
+//! type implementing `VirtualFile`, in which case the hostcalls dispatch to it without ever
+//! touching the host filesystem. The same mechanism is used to connect the guest's stdio to
+//! Rust readers and writers.
+use crate::host;
+use std::any::Any;
+use std::fmt;
+use std::io;
+
+pub(crate) mod hostcalls_impl;
+mod memfs;
+mod pipe;
+
+pub use self::memfs::MemFs;
+pub(crate) use self::pipe::{CaptureBuffer, ReadPipe, WritePipe};
+
+/// A directory entry as returned by `VirtualFile::readdir`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dirent {
+    pub name: String,
+    pub ino: host::__wasi_inode_t,
+    pub file_type: host::__wasi_filetype_t,
+    /// Cookie which resumes reading the directory right after this entry.
+    pub next: host::__wasi_dircookie_t,
+}
+
+/// A file or directory which isn't backed by a host file descriptor.
+///
+/// Path-based operations are invoked on the directory containing the target, after the
+/// sandboxed path resolution has been performed by the hostcall layer. Hence, the `path`
+/// argument is always a single path component (or `"."`), which may carry a trailing slash
+/// meaning that the target is required to be a directory. Symlinks are never followed by
+/// the implementation itself: `openat` on a symlink is expected to fail with `__WASI_ELOOP`,
+/// and resolution is driven through `readlinkat` instead.
+///
+/// All methods take `&self` and may be called from several threads at once; implementations
+/// that need mutable state (such as a file cursor) should use interior mutability. Operations
+/// which make no sense for a given kind of file have default implementations returning the same
+/// errors the host would.
+pub trait VirtualFile: fmt::Debug + Send + Sync {
+    /// Returns the WASI file type of this file.
+    fn file_type(&self) -> host::__wasi_filetype_t;
+
+    /// Creates a new handle referring to the same underlying file.
+    fn try_clone(&self) -> Result<Box<dyn VirtualFile>, host::__wasi_errno_t>;
+
+    /// Used to downcast the directory argument of `link` and `rename`.
+    fn as_any(&self) -> &dyn Any;
+
+    fn openat(
+        &self,
+        _path: &str,
+        _read: bool,
+        _write: bool,
+        _oflags: host::__wasi_oflags_t,
+        _fs_flags: host::__wasi_fdflags_t,
+    ) -> Result<Box<dyn VirtualFile>, host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn readlinkat(&self, _path: &str) -> Result<String, host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn create_directory(&self, _path: &str) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn remove_directory(&self, _path: &str) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn unlink_file(&self, _path: &str) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn link(
+        &self,
+        _old_path: &str,
+        _new_dir: &dyn VirtualFile,
+        _new_path: &str,
+    ) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn rename(
+        &self,
+        _old_path: &str,
+        _new_dir: &dyn VirtualFile,
+        _new_path: &str,
+    ) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn path_filestat_get(
+        &self,
+        _path: &str,
+    ) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn path_filestat_set_times(
+        &self,
+        _path: &str,
+        _st_atim: host::__wasi_timestamp_t,
+        _st_mtim: host::__wasi_timestamp_t,
+        _fst_flags: host::__wasi_fstflags_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn readdir(
+        &self,
+        _cookie: host::__wasi_dircookie_t,
+    ) -> Result<Vec<Dirent>, host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTDIR)
+    }
+
+    fn read_vectored(&self, _iovs: &mut [io::IoSliceMut]) -> Result<usize, host::__wasi_errno_t> {
+        Err(host::__WASI_EBADF)
+    }
+
+    fn write_vectored(&self, _iovs: &[io::IoSlice]) -> Result<usize, host::__wasi_errno_t> {
+        Err(host::__WASI_EBADF)
+    }
+
+    fn pread(
+        &self,
+        _buf: &mut [u8],
+        _offset: host::__wasi_filesize_t,
+    ) -> Result<usize, host::__wasi_errno_t> {
+        Err(host::__WASI_ESPIPE)
+    }
+
+    fn pwrite(
+        &self,
+        _buf: &[u8],
+        _offset: host::__wasi_filesize_t,
+    ) -> Result<usize, host::__wasi_errno_t> {
+        Err(host::__WASI_ESPIPE)
+    }
+
+    fn seek(
+        &self,
+        _offset: host::__wasi_filedelta_t,
+        _whence: host::__wasi_whence_t,
+    ) -> Result<u64, host::__wasi_errno_t> {
+        Err(host::__WASI_ESPIPE)
+    }
+
+    fn fdstat_get(&self) -> Result<host::__wasi_fdflags_t, host::__wasi_errno_t> {
+        Ok(0)
+    }
+
+    fn fdstat_set_flags(
+        &self,
+        _fdflags: host::__wasi_fdflags_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTSUP)
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t>;
+
+    fn filestat_set_times(
+        &self,
+        _st_atim: host::__wasi_timestamp_t,
+        _st_mtim: host::__wasi_timestamp_t,
+        _fst_flags: host::__wasi_fstflags_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_ENOTSUP)
+    }
+
+    fn filestat_set_size(
+        &self,
+        _st_size: host::__wasi_filesize_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_EINVAL)
+    }
+
+    fn allocate(
+        &self,
+        _offset: host::__wasi_filesize_t,
+        _len: host::__wasi_filesize_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        Err(host::__WASI_EINVAL)
+    }
+
+    fn advise(
+        &self,
+        _advice: host::__wasi_advice_t,
+        _offset: host::__wasi_filesize_t,
+        _len: host::__wasi_filesize_t,
+    ) -> Result<(), host::__wasi_errno_t> {
+        Ok(())
+    }
+
+    fn datasync(&self) -> Result<(), host::__wasi_errno_t> {
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), host::__wasi_errno_t> {
+        Ok(())
+    }
+
+    /// Checks whether a `poll_oneoff` subscription of type `type_` on this file has triggered,
+    /// without blocking. Returns `None` if the file isn't ready yet, in which case it is checked
+    /// again later, or the number of bytes available along with the event flags.
+    ///
+    /// By default, files never block, so they are always ready with an unknown number of bytes.
+    fn poll_readiness(
+        &self,
+        _type_: host::__wasi_eventtype_t,
+    ) -> Result<Option<host::__wasi_event_fd_readwrite_t>, host::__wasi_errno_t> {
+        Ok(Some(host::__wasi_event_fd_readwrite_t {
+            nbytes: 0,
+            flags: 0,
+        }))
+    }
+}
+
+/// Returns the maximal base and inheriting rights for a file of the given type, mirroring what
+/// `determine_type_rights` computes for host files.
+pub(crate) fn type_rights(
+    file_type: host::__wasi_filetype_t,
+) -> (host::__wasi_rights_t, host::__wasi_rights_t) {
+    match file_type {
+        host::__WASI_FILETYPE_DIRECTORY => (
+            host::RIGHTS_DIRECTORY_BASE,
+            host::RIGHTS_DIRECTORY_INHERITING,
+        ),
+        host::__WASI_FILETYPE_REGULAR_FILE => (
+            host::RIGHTS_REGULAR_FILE_BASE,
+            host::RIGHTS_REGULAR_FILE_INHERITING,
+        ),
+        host::__WASI_FILETYPE_SOCKET_DGRAM | host::__WASI_FILETYPE_SOCKET_STREAM => {
+            (host::RIGHTS_SOCKET_BASE, host::RIGHTS_SOCKET_INHERITING)
+        }
+        host::__WASI_FILETYPE_BLOCK_DEVICE => (
+            host::RIGHTS_BLOCK_DEVICE_BASE,
+            host::RIGHTS_BLOCK_DEVICE_INHERITING,
+        ),
+        host::__WASI_FILETYPE_CHARACTER_DEVICE => (
+            host::RIGHTS_CHARACTER_DEVICE_BASE,
+            host::RIGHTS_CHARACTER_DEVICE_INHERITING,
+        ),
+        _ => (0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::put_iovec;
+    use crate::{hostcalls, PreopenRights, SliceMemory, WasiCtxBuilder};
+    use std::io::Read;
+
+    /// A directory holding a single read-only file named `hello`.
+    #[derive(Debug, Clone)]
+    struct Greeting {
+        file: bool,
+        cursor: std::sync::Arc<std::sync::Mutex<usize>>,
+    }
+
+    const CONTENTS: &[u8] = b"hello, world";
+
+    impl VirtualFile for Greeting {
+        fn file_type(&self) -> host::__wasi_filetype_t {
+            if self.file {
+                host::__WASI_FILETYPE_REGULAR_FILE
+            } else {
+                host::__WASI_FILETYPE_DIRECTORY
+            }
+        }
+
+        fn try_clone(&self) -> Result<Box<dyn VirtualFile>, host::__wasi_errno_t> {
+            Ok(Box::new(self.clone()))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn openat(
+            &self,
+            path: &str,
+            _read: bool,
+            write: bool,
+            _oflags: host::__wasi_oflags_t,
+            _fs_flags: host::__wasi_fdflags_t,
+        ) -> Result<Box<dyn VirtualFile>, host::__wasi_errno_t> {
+            match path {
+                _ if self.file => Err(host::__WASI_ENOTDIR),
+                "hello" if write => Err(host::__WASI_EROFS),
+                "hello" => Ok(Box::new(Greeting {
+                    file: true,
+                    cursor: Default::default(),
+                })),
+                _ => Err(host::__WASI_ENOENT),
+            }
+        }
+
+        fn read_vectored(
+            &self,
+            iovs: &mut [io::IoSliceMut],
+        ) -> Result<usize, host::__wasi_errno_t> {
+            let mut cursor = self.cursor.lock().unwrap();
+            let mut rest = &CONTENTS[*cursor..];
+            let nread = rest.read_vectored(iovs).map_err(|_| host::__WASI_EIO)?;
+            *cursor += nread;
+            Ok(nread)
+        }
+
+        fn filestat_get(&self) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t> {
+            Ok(host::__wasi_filestat_t {
+                st_dev: 0,
+                st_ino: self.file as host::__wasi_inode_t,
+                st_filetype: self.file_type(),
+                st_nlink: 1,
+                st_size: if self.file { CONTENTS.len() as u64 } else { 0 },
+                st_atim: 0,
+                st_mtim: 0,
+                st_ctim: 0,
+            })
+        }
+    }
+
+    fn read_u32(mem: &[u8], offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&mem[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    #[test]
+    fn hostcalls_dispatch_to_virtual_preopen() {
+        let dir = Greeting {
+            file: false,
+            cursor: Default::default(),
+        };
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .preopened_virt(Box::new(dir), "/greeting", PreopenRights::ReadWrite)
+            .build()
+            .unwrap();
+        let mut mem = vec![0u8; 256];
+        mem[0..5].copy_from_slice(b"hello");
+        put_iovec(&mut mem, 16, 64, 32);
+
+        let errno = hostcalls::path_open(
+            &ctx,
+            &SliceMemory::new(&mut mem),
+            3,
+            0,
+            0,
+            5,
+            0,
+            host::__WASI_RIGHT_FD_WRITE,
+            0,
+            0,
+            8,
+        );
+        assert_eq!(errno, host::__WASI_EROFS);
+
+        let errno = hostcalls::path_open(
+            &ctx,
+            &SliceMemory::new(&mut mem),
+            3,
+            0,
+            0,
+            5,
+            0,
+            host::__WASI_RIGHT_FD_READ,
+            0,
+            0,
+            8,
+        );
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        let fd = read_u32(&mem, 8);
+
+        let errno = hostcalls::fd_read(&ctx, &SliceMemory::new(&mut mem), fd, 16, 1, 24);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        assert_eq!(read_u32(&mem, 24) as usize, CONTENTS.len());
+        assert_eq!(&mem[64..64 + CONTENTS.len()], CONTENTS);
+
+        let errno = hostcalls::fd_read(&ctx, &SliceMemory::new(&mut mem), fd, 16, 1, 24);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        assert_eq!(read_u32(&mem, 24), 0);
+
+        mem[0..7].copy_from_slice(b"missing");
+        let errno = hostcalls::path_open(
+            &ctx,
+            &SliceMemory::new(&mut mem),
+            3,
+            0,
+            0,
+            7,
+            0,
+            host::__WASI_RIGHT_FD_READ,
+            0,
+            0,
+            8,
+        );
+        assert_eq!(errno, host::__WASI_ENOENT);
+    }
+}
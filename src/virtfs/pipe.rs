@@ -0,0 +1,247 @@
+//! Virtual files connecting the guest's stdio to Rust readers and writers.
+use super::VirtualFile;
+use crate::host;
+use crate::sys::errno_from_host;
+use std::any::Any;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+fn errno_from_ioerror(err: io::Error) -> host::__wasi_errno_t {
+    match err.kind() {
+        io::ErrorKind::WouldBlock => host::__WASI_EAGAIN,
+        _ => err.raw_os_error().map_or(host::__WASI_EIO, errno_from_host),
+    }
+}
+
+fn filestat() -> host::__wasi_filestat_t {
+    host::__wasi_filestat_t {
+        st_dev: 0,
+        st_ino: 0,
+        st_filetype: host::__WASI_FILETYPE_CHARACTER_DEVICE,
+        st_nlink: 1,
+        st_size: 0,
+        st_atim: 0,
+        st_mtim: 0,
+        st_ctim: 0,
+    }
+}
+
+/// A buffer collecting everything the guest writes to a captured output stream.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CaptureBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl CaptureBuffer {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    fn buf(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn contents(&self) -> Vec<u8> {
+        self.buf().clone()
+    }
+}
+
+impl Write for CaptureBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A read-only pipe feeding the guest from a `Read` implementation. Reaching the end of the
+/// reader is reported to the guest as end-of-file.
+///
+/// A reader which has no data available yet, but may have some later, should fail with
+/// `io::ErrorKind::WouldBlock` rather than block, so that `poll_oneoff` can tell it isn't ready.
+#[derive(Clone)]
+pub(crate) struct ReadPipe(Arc<Mutex<ReadPipeState>>);
+
+struct ReadPipeState {
+    source: Box<dyn Read + Send>,
+    // bytes read from the source by `poll_readiness`, which the guest hasn't consumed yet
+    peeked: Vec<u8>,
+}
+
+impl ReadPipe {
+    pub(crate) fn new(source: Box<dyn Read + Send>) -> Self {
+        ReadPipe(Arc::new(Mutex::new(ReadPipeState {
+            source,
+            peeked: Vec::new(),
+        })))
+    }
+
+    fn state(&self) -> MutexGuard<'_, ReadPipeState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ReadPipeState {
+    fn read_source(&mut self, iovs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        loop {
+            match self.source.read_vectored(iovs) {
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                res => return res,
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ReadPipe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReadPipe").finish()
+    }
+}
+
+impl VirtualFile for ReadPipe {
+    fn file_type(&self) -> host::__wasi_filetype_t {
+        host::__WASI_FILETYPE_CHARACTER_DEVICE
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn VirtualFile>, host::__wasi_errno_t> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_vectored(&self, iovs: &mut [io::IoSliceMut]) -> Result<usize, host::__wasi_errno_t> {
+        let mut state = self.state();
+        if state.peeked.is_empty() {
+            return state.read_source(iovs).map_err(errno_from_ioerror);
+        }
+        let nread = (&state.peeked[..])
+            .read_vectored(iovs)
+            .map_err(errno_from_ioerror)?;
+        state.peeked.drain(..nread);
+        Ok(nread)
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t> {
+        Ok(filestat())
+    }
+
+    fn poll_readiness(
+        &self,
+        type_: host::__wasi_eventtype_t,
+    ) -> Result<Option<host::__wasi_event_fd_readwrite_t>, host::__wasi_errno_t> {
+        if type_ != host::__WASI_EVENTTYPE_FD_READ {
+            return Err(host::__WASI_EBADF);
+        }
+        let mut state = self.state();
+        let mut flags = 0;
+        if state.peeked.is_empty() {
+            let mut buf = [0; 4096];
+            match state.read_source(&mut [io::IoSliceMut::new(&mut buf)]) {
+                Ok(0) => flags = host::__WASI_EVENT_FD_READWRITE_HANGUP,
+                Ok(nread) => state.peeked.extend_from_slice(&buf[..nread]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(err) => return Err(errno_from_ioerror(err)),
+            }
+        }
+        Ok(Some(host::__wasi_event_fd_readwrite_t {
+            nbytes: state.peeked.len() as host::__wasi_filesize_t,
+            flags,
+        }))
+    }
+}
+
+/// A write-only pipe forwarding everything the guest writes to a `Write` implementation.
+#[derive(Clone)]
+pub(crate) struct WritePipe(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl WritePipe {
+    pub(crate) fn new(sink: Box<dyn Write + Send>) -> Self {
+        WritePipe(Arc::new(Mutex::new(sink)))
+    }
+
+    fn sink(&self) -> MutexGuard<'_, Box<dyn Write + Send>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for WritePipe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WritePipe").finish()
+    }
+}
+
+impl VirtualFile for WritePipe {
+    fn file_type(&self) -> host::__wasi_filetype_t {
+        host::__WASI_FILETYPE_CHARACTER_DEVICE
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn VirtualFile>, host::__wasi_errno_t> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn write_vectored(&self, iovs: &[io::IoSlice]) -> Result<usize, host::__wasi_errno_t> {
+        self.sink().write_vectored(iovs).map_err(errno_from_ioerror)
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t, host::__wasi_errno_t> {
+        Ok(filestat())
+    }
+
+    fn datasync(&self) -> Result<(), host::__wasi_errno_t> {
+        self.sink().flush().map_err(errno_from_ioerror)
+    }
+
+    fn sync(&self) -> Result<(), host::__wasi_errno_t> {
+        self.sink().flush().map_err(errno_from_ioerror)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::memory_with_iovec;
+    use crate::{host, hostcalls, SliceMemory, WasiCtxBuilder};
+
+    #[test]
+    fn capture_stdout_and_stderr() {
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .stdout_capture()
+            .stderr_capture()
+            .build()
+            .unwrap();
+        let mut mem = memory_with_iovec(b"out", 3);
+        for _ in 0..2 {
+            let errno = hostcalls::fd_write(&ctx, &SliceMemory::new(&mut mem), 1, 0, 1, 8);
+            assert_eq!(errno, host::__WASI_ESUCCESS);
+        }
+        let mut mem = memory_with_iovec(b"err", 3);
+        let errno = hostcalls::fd_write(&ctx, &SliceMemory::new(&mut mem), 2, 0, 1, 8);
+        assert_eq!(errno, host::__WASI_ESUCCESS);
+        assert_eq!(ctx.captured_stdout().unwrap(), b"outout");
+        assert_eq!(ctx.captured_stderr().unwrap(), b"err");
+
+        // the captured streams are write-only
+        let errno = hostcalls::fd_read(&ctx, &SliceMemory::new(&mut mem), 1, 0, 1, 8);
+        assert_eq!(errno, host::__WASI_ENOTCAPABLE);
+    }
+
+    #[test]
+    fn uncaptured_streams() {
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .stdout_capture()
+            .stdout(Box::new(std::io::sink()))
+            .build()
+            .unwrap();
+        assert_eq!(ctx.captured_stdout(), None);
+        assert_eq!(ctx.captured_stderr(), None);
+    }
+}
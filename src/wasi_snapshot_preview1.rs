@@ -0,0 +1,26 @@
+//! WASI types as defined in wasi_snapshot_preview1, for both wasm32 and wasm64.
+//!
+//! The snapshot widens `__wasi_linkcount_t` to 64 bits, renumbers `__wasi_whence_t`, and drops
+//! the `identifier` of clock subscriptions. The types are generated from
+//! witx/preview1/typenames.witx and shadow their wasi_unstable counterparts, everything else is
+//! reexported from the `wasm32` module: the numbering of errnos and rights in particular is the
+//! same in both snapshots. Types containing pointers or sizes are the same as in wasi_unstable
+//! too, and are taken from `wasm32` or `wasm64` according to the `GuestAbi`.
+
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(dead_code)]
+
+pub use crate::wasm32::*;
+use wasi_common_witx::witx_guest_types;
+
+witx_guest_types!("witx/preview1/typenames.witx", 32);
+
+pub fn whence_to_str(whence: __wasi_whence_t) -> &'static str {
+    match whence {
+        __WASI_WHENCE_SET => "__WASI_WHENCE_SET",
+        __WASI_WHENCE_CUR => "__WASI_WHENCE_CUR",
+        __WASI_WHENCE_END => "__WASI_WHENCE_END",
+        other => panic!("Undefined whence value {:?}", other),
+    }
+}
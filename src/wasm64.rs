@@ -0,0 +1,23 @@
+//! WASI types as defined in wasm64.
+//!
+//! The two ABIs only differ in the width of `long`, pointers and sizes. The WASI types are
+//! generated from witx/preview0/typenames.witx with 64-bit pointers, and everything else is
+//! reexported from the `wasm32` module. The types defined here shadow their wasm32 counterparts.
+
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(dead_code)]
+
+pub use crate::wasm32::*;
+use wasi_common_witx::witx_guest_types;
+
+// C types
+pub type long = i64;
+pub type ulong = u64;
+
+pub type size_t = ulong;
+pub type intptr_t = long;
+pub type uintptr_t = ulong;
+
+// WASI types
+witx_guest_types!("witx/preview0/typenames.witx", 64);
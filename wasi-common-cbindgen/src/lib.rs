@@ -2,7 +2,7 @@ extern crate proc_macro;
 
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{ArgCaptured, FnArg, Pat, PatIdent, Type, TypeReference, TypeSlice};
+use syn::{ArgCaptured, FnArg, Pat, PatIdent, Type, TypeReference, TypeSlice, TypeTraitObject};
 
 #[proc_macro_attribute]
 pub fn wasi_common_cbindgen(attr: TokenStream, function: TokenStream) -> TokenStream {
@@ -53,6 +53,18 @@ pub fn wasi_common_cbindgen(attr: TokenStream, function: TokenStream) -> TokenSt
                         });
                         arg_ident.push(quote!(#len_ident));
                         arg_type.push(quote!(usize));
+                    } else if let Type::TraitObject(TypeTraitObject { .. }) = &elem {
+                        // trait object: &dyn Trait or &mut dyn Trait
+                        // a pointer to a trait object is a fat pointer which C
+                        // has no way of representing, so take a thin pointer to
+                        // the reference itself instead, i.e. *mut &dyn Trait
+                        arg_type.push(quote!(*mut #ty));
+                        // dereference the raw pointer to get back the reference
+                        if ty.mutability.is_some() {
+                            call_arg_ident.push(quote!(&mut **#ident));
+                        } else {
+                            call_arg_ident.push(quote!(&**#ident));
+                        }
                     } else {
                         // & or &mut type
                         // so simply substitute with *mut type
@@ -0,0 +1,39 @@
+extern crate wasi_common_cbindgen;
+
+pub use wasi_common_cbindgen::wasi_common_cbindgen;
+
+pub trait Counter {
+    fn get(&self) -> usize;
+    fn incr(&mut self);
+}
+
+impl Counter for usize {
+    fn get(&self) -> usize {
+        *self
+    }
+
+    fn incr(&mut self) {
+        *self += 1
+    }
+}
+
+#[wasi_common_cbindgen]
+fn dyn_args(a: &dyn Counter, b: &mut dyn Counter) -> usize {
+    b.incr();
+    a.get() + b.get()
+}
+
+fn main() {
+    let mut expected_b = 2;
+    let expected = dyn_args(&1, &mut expected_b);
+
+    let mut given_b = 2;
+    let given = unsafe {
+        let mut a: &dyn Counter = &1;
+        let mut b: &mut dyn Counter = &mut given_b;
+        wasi_common_dyn_args(&mut a, &mut b)
+    };
+
+    assert_eq!(given, expected);
+    assert_eq!(given_b, expected_b);
+}
@@ -6,4 +6,5 @@ fn tests() {
     t.pass("tests/ref_args.rs");
     t.pass("tests/mut_args.rs");
     t.pass("tests/array_args.rs");
+    t.pass("tests/dyn_args.rs");
 }
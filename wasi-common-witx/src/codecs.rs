@@ -0,0 +1,92 @@
+//! Functions converting scalar WASI types between their host and guest representations.
+//!
+//! A codec is only emitted for the types represented by the same integer in both the host and
+//! the guest. Enums must also have the same values in both, so types which were renumbered
+//! between snapshots need codecs written by hand.
+
+use crate::parser::{Document, IntRepr, Type, Variant};
+use crate::render::{ident, type_ident};
+use proc_macro2::{Ident, TokenStream};
+use quote::quote;
+
+enum Scalar<'a> {
+    Int(IntRepr),
+    Enum(IntRepr, &'a [Variant]),
+}
+
+fn scalar<'a>(doc: &'a Document, ty: &'a Type) -> Option<Scalar<'a>> {
+    match ty {
+        Type::Int(repr) | Type::Flags { repr, .. } => Some(Scalar::Int(*repr)),
+        Type::Handle => Some(Scalar::Int(IntRepr::U32)),
+        Type::Enum { repr, variants } => Some(Scalar::Enum(*repr, variants)),
+        // sizes are as wide as pointers
+        Type::Ref(name) if name == "size" => None,
+        Type::Ref(name) => doc.get(name).and_then(|t| scalar(doc, &t.ty)),
+        _ => None,
+    }
+}
+
+fn same_representation(host: &Scalar, guest: &Scalar) -> bool {
+    match (host, guest) {
+        (Scalar::Int(h), Scalar::Int(g)) => h == g,
+        (Scalar::Enum(h, host_variants), Scalar::Enum(g, guest_variants)) => {
+            h == g
+                && host_variants.len() == guest_variants.len()
+                && host_variants
+                    .iter()
+                    .zip(guest_variants.iter())
+                    .all(|(h, g)| h.name == g.name)
+        }
+        _ => false,
+    }
+}
+
+pub fn render(
+    host_module: &Ident,
+    host: &Document,
+    guest_module: &Ident,
+    guest: &Document,
+) -> TokenStream {
+    let codecs = guest.typenames.iter().filter_map(|typename| {
+        let name = &typename.name;
+        if name == "size" {
+            return None;
+        }
+        let host_scalar = scalar(host, &host.get(name)?.ty)?;
+        let guest_scalar = scalar(guest, &typename.ty)?;
+        if !same_representation(&host_scalar, &guest_scalar) {
+            return None;
+        }
+
+        let ty = type_ident(name);
+        let dec = ident(&format!("dec_{}", name));
+        let dec_byref = ident(&format!("dec_{}_byref", name));
+        let enc = ident(&format!("enc_{}", name));
+        let enc_byref = ident(&format!("enc_{}_byref", name));
+        Some(quote! {
+            pub fn #dec(x: #guest_module::#ty) -> #host_module::#ty {
+                #host_module::#ty::from_le(x)
+            }
+
+            pub fn #dec_byref(
+                memory: &dyn GuestMemory,
+                ptr: GuestAddr,
+            ) -> Result<#host_module::#ty, #host_module::__wasi_errno_t> {
+                dec_pointee::<#guest_module::#ty>(memory, ptr).map(#dec)
+            }
+
+            pub fn #enc(x: #host_module::#ty) -> #guest_module::#ty {
+                x.to_le()
+            }
+
+            pub fn #enc_byref(
+                memory: &dyn GuestMemory,
+                ptr: GuestAddr,
+                x: #host_module::#ty,
+            ) -> Result<(), #host_module::__wasi_errno_t> {
+                enc_pointee::<#guest_module::#ty>(memory, ptr, #enc(x))
+            }
+        })
+    });
+    quote!(#(#codecs)*)
+}
@@ -0,0 +1,367 @@
+//! Names and layout of the WASI types, independently of the language they are emitted in.
+//!
+//! The names follow the ones of `wasi/core.h`: a type `$foo` becomes `__wasi_foo_t`, and its
+//! values become `__WASI_<PREFIX><VALUE>` constants. Unions are tagged, so a union becomes a
+//! C union of its cases, along with a tag field in the struct containing it.
+
+use crate::parser::{Document, Field, IntRepr, Type, Typename, Variant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Native types, with pointers and sizes of the host.
+    Host,
+    /// Guest types, with explicit padding and pointers and sizes of the given width in bytes.
+    Guest { pointer_size: u64 },
+}
+
+pub fn type_name(name: &str) -> String {
+    format!("__wasi_{}_t", name)
+}
+
+/// Prefix of the constants of an enum or flags type, after `__WASI_`.
+fn const_prefix(type_name: &str) -> String {
+    match type_name {
+        "clockid" => "CLOCK_".to_owned(),
+        "errno" => "E".to_owned(),
+        "eventrwflags" => "EVENT_".to_owned(),
+        "fdflags" => "FDFLAG_".to_owned(),
+        "fstflags" => "FILESTAT_SET_".to_owned(),
+        "lookupflags" => "LOOKUP_".to_owned(),
+        "oflags" => "O_".to_owned(),
+        "riflags" | "roflags" => "SOCK_".to_owned(),
+        "rights" => "RIGHT_".to_owned(),
+        "sdflags" => "SHUT_".to_owned(),
+        "signal" => "SIG".to_owned(),
+        "subclockflags" => String::new(),
+        other => format!("{}_", other.to_uppercase()),
+    }
+}
+
+/// The constants of an enum or flags type, along with their values.
+pub fn constants<'a>(
+    type_name: &str,
+    variants: &'a [Variant],
+    flags: bool,
+) -> Vec<(String, u64, &'a Variant)> {
+    let prefix = const_prefix(type_name);
+    variants
+        .iter()
+        .enumerate()
+        .map(|(i, variant)| {
+            let name = format!("__WASI_{}{}", prefix, variant.name.to_uppercase());
+            let value = if flags { 1 << i } else { i as u64 };
+            (name, value, variant)
+        })
+        .collect()
+}
+
+/// Name of a field of a record, which `wasi/core.h` sometimes spells differently.
+fn field_name(record: &str, field: &str) -> String {
+    match (record, field) {
+        ("filestat", field) => format!("st_{}", field),
+        ("subscription_clock", "id") => "clock_id".to_owned(),
+        ("subscription_fd_readwrite", "file_descriptor") => "fd".to_owned(),
+        (_, "type") => "type_".to_owned(),
+        (_, field) => field.to_owned(),
+    }
+}
+
+/// Name of the field holding the tag of a union in the struct `owner`.
+fn tag_field_name(owner: &str) -> &'static str {
+    match owner {
+        "prestat" => "pr_type",
+        _ => "type_",
+    }
+}
+
+fn round_up(offset: u64, align: u64) -> u64 {
+    match offset % align {
+        0 => offset,
+        rem => offset + (align - rem),
+    }
+}
+
+/// The type of a field of a struct.
+#[derive(Debug)]
+pub enum FieldType {
+    Int(IntRepr),
+    /// A type defined by the document.
+    Named(String),
+    /// A pointer, which is an unsigned integer as wide as a pointer in guest types.
+    Pointer {
+        to: Box<FieldType>,
+        mutable: bool,
+    },
+    /// A union without its tag, named after the body rather than the union.
+    UnionBody(String),
+}
+
+/// A field of a struct as it is emitted, i.e. with unions split into a tag and a body.
+pub struct StructField {
+    pub name: String,
+    pub docs: String,
+    pub ty: FieldType,
+    pub size: u64,
+    pub align: u64,
+}
+
+impl StructField {
+    pub fn is_union(&self) -> bool {
+        match self.ty {
+            FieldType::UnionBody(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A member of a struct once it is laid out.
+pub enum Member<'a> {
+    Field {
+        field: &'a StructField,
+        offset: u64,
+    },
+    /// Bytes of padding; runs of padding are numbered from 0 in the order they appear.
+    Padding {
+        index: usize,
+        len: u64,
+    },
+}
+
+pub struct Layout<'a> {
+    pub doc: &'a Document,
+    pub target: Target,
+}
+
+impl<'a> Layout<'a> {
+    /// Whether the union `name` is only ever used as the field of a record, in which case it
+    /// doesn't need a struct of its own to hold the tag.
+    pub fn is_embedded_union(&self, name: &str) -> bool {
+        self.doc.typenames.iter().any(|t| match &t.ty {
+            Type::Record(fields) => fields.iter().any(|f| match &f.ty {
+                Type::Ref(r) => r == name,
+                _ => false,
+            }),
+            _ => false,
+        })
+    }
+
+    pub fn union_body_name(&self, name: &str) -> String {
+        if self.is_embedded_union(name) {
+            name.to_owned()
+        } else {
+            format!("{}_u", name)
+        }
+    }
+
+    /// Names of the fields of a union body, along with their types, with duplicates removed.
+    pub fn union_cases(&self, name: &str, cases: &[String]) -> Vec<(String, String)> {
+        let base = if name.ends_with("_u") {
+            &name[..name.len() - 2]
+        } else {
+            name
+        };
+        let prefix = format!("{}_", base);
+        let mut result: Vec<(String, String)> = Vec::new();
+        for case in cases {
+            if result.iter().any(|(_, ty)| ty == case) {
+                continue;
+            }
+            let field = if case.starts_with(&prefix) {
+                &case[prefix.len()..]
+            } else {
+                case
+            };
+            result.push((field.to_owned(), case.clone()));
+        }
+        result
+    }
+
+    pub fn union_body_layout(&self, name: &str, cases: &[String]) -> Result<(u64, u64), String> {
+        let mut size = 0;
+        let mut align = 1;
+        for (_, case) in self.union_cases(name, cases) {
+            let (case_size, case_align) = self.layout(&Type::Ref(case))?;
+            size = size.max(case_size);
+            align = align.max(case_align);
+        }
+        Ok((round_up(size, align), align))
+    }
+
+    pub fn struct_layout(&self, fields: &[StructField]) -> (u64, u64) {
+        let mut offset = 0;
+        let mut align = 1;
+        for field in fields {
+            offset = round_up(offset, field.align) + field.size;
+            align = align.max(field.align);
+        }
+        (round_up(offset, align), align)
+    }
+
+    /// Places the fields of a struct, making the padding between them explicit unless the
+    /// target is the host.
+    pub fn members<'f>(&self, fields: &'f [StructField]) -> Vec<Member<'f>> {
+        let explicit_padding = self.target != Target::Host;
+        let mut members = Vec::new();
+        let mut offset = 0;
+        let mut padding = 0;
+        let mut pad = |members: &mut Vec<Member>, from: u64, to: u64| {
+            if explicit_padding && to > from {
+                members.push(Member::Padding {
+                    index: padding,
+                    len: to - from,
+                });
+                padding += 1;
+            }
+        };
+        for field in fields {
+            let aligned = round_up(offset, field.align);
+            pad(&mut members, offset, aligned);
+            members.push(Member::Field {
+                field,
+                offset: aligned,
+            });
+            offset = aligned + field.size;
+        }
+        let (size, _) = self.struct_layout(fields);
+        pad(&mut members, offset, size);
+        members
+    }
+
+    fn pointer_size(&self) -> u64 {
+        match self.target {
+            // the layout of host types is only checked on 64-bit hosts
+            Target::Host => 8,
+            Target::Guest { pointer_size } => pointer_size,
+        }
+    }
+
+    /// Size and alignment of a type.
+    pub fn layout(&self, ty: &Type) -> Result<(u64, u64), String> {
+        let pointer_size = self.pointer_size();
+        match ty {
+            Type::Int(repr) => Ok((repr.size(), repr.size())),
+            Type::Enum { repr, .. } | Type::Flags { repr, .. } => Ok((repr.size(), repr.size())),
+            Type::Handle => Ok((4, 4)),
+            Type::Pointer(_) | Type::ConstPointer(_) => Ok((pointer_size, pointer_size)),
+            Type::Ref(name) if name == "size" => Ok((pointer_size, pointer_size)),
+            Type::Ref(name) => {
+                let typename = self.doc.resolve(name)?;
+                match &typename.ty {
+                    Type::Record(fields) => {
+                        Ok(self.struct_layout(&self.struct_fields(name, fields)?))
+                    }
+                    Type::Union { .. } => {
+                        Ok(self.struct_layout(&self.union_struct_fields(typename)?))
+                    }
+                    ty => self.layout(ty),
+                }
+            }
+            Type::Record(_) | Type::Union { .. } | Type::List => {
+                Err("anonymous records, unions and lists are not supported".to_owned())
+            }
+        }
+    }
+
+    /// The type of a field, or of the alias of another type.
+    pub fn field_type(&self, ty: &Type) -> Result<FieldType, String> {
+        match ty {
+            Type::Int(repr) => Ok(FieldType::Int(*repr)),
+            Type::Ref(name) => {
+                self.doc.resolve(name)?;
+                Ok(FieldType::Named(name.clone()))
+            }
+            Type::Pointer(to) => Ok(FieldType::Pointer {
+                to: Box::new(self.field_type(to)?),
+                mutable: true,
+            }),
+            Type::ConstPointer(to) => Ok(FieldType::Pointer {
+                to: Box::new(self.field_type(to)?),
+                mutable: false,
+            }),
+            _ => Err("only integers, pointers and named types may be used as fields".to_owned()),
+        }
+    }
+
+    fn struct_field(&self, name: String, docs: &str, ty: &Type) -> Result<StructField, String> {
+        let (size, align) = self.layout(ty)?;
+        Ok(StructField {
+            name,
+            docs: docs.to_owned(),
+            ty: self.field_type(ty)?,
+            size,
+            align,
+        })
+    }
+
+    fn union_fields(
+        &self,
+        owner: &str,
+        field: String,
+        docs: &str,
+        union: &str,
+        tag: &str,
+        cases: &[String],
+    ) -> Result<Vec<StructField>, String> {
+        let tag = self.struct_field(
+            tag_field_name(owner).to_owned(),
+            "",
+            &Type::Ref(tag.to_owned()),
+        )?;
+        let (size, align) = self.union_body_layout(union, cases)?;
+        Ok(vec![
+            tag,
+            StructField {
+                name: field,
+                docs: docs.to_owned(),
+                ty: FieldType::UnionBody(self.union_body_name(union)),
+                size,
+                align,
+            },
+        ])
+    }
+
+    pub fn struct_fields(
+        &self,
+        record: &str,
+        fields: &[Field],
+    ) -> Result<Vec<StructField>, String> {
+        let mut result = Vec::new();
+        for field in fields {
+            let name = field_name(record, &field.name);
+            let union = match &field.ty {
+                Type::Ref(r) => match &self.doc.resolve(r)?.ty {
+                    Type::Union { tag, cases } => Some((r, tag, cases)),
+                    _ => None,
+                },
+                _ => None,
+            };
+            match union {
+                Some((union, tag, cases)) => result.extend(self.union_fields(
+                    record,
+                    name,
+                    &field.docs,
+                    union,
+                    tag,
+                    cases,
+                )?),
+                None => result.push(self.struct_field(name, &field.docs, &field.ty)?),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Fields of the struct holding a union which isn't embedded in a record.
+    pub fn union_struct_fields(&self, typename: &Typename) -> Result<Vec<StructField>, String> {
+        match &typename.ty {
+            Type::Union { tag, cases } => self.union_fields(
+                &typename.name,
+                "u".to_owned(),
+                "",
+                &typename.name,
+                tag,
+                cases,
+            ),
+            _ => unreachable!(),
+        }
+    }
+}
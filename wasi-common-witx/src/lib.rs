@@ -0,0 +1,165 @@
+//! Generates the WASI types and the functions converting them from `.witx` interface
+//! definitions.
+//!
+//! Paths to `.witx` files are relative to the directory of the manifest of the crate
+//! invoking the macros.
+
+extern crate proc_macro;
+
+mod codecs;
+mod layout;
+mod parser;
+mod render;
+
+use layout::Target;
+use parser::Document;
+use proc_macro::TokenStream;
+use quote::quote;
+use std::path::PathBuf;
+use syn::parse::{Parse, ParseStream};
+use syn::{Ident, LitInt, LitStr, Token};
+
+fn load(path: &LitStr) -> Result<(Document, proc_macro2::TokenStream), syn::Error> {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .map_err(|_| syn::Error::new(path.span(), "CARGO_MANIFEST_DIR is not set"))?;
+    let full_path: PathBuf = [manifest_dir, path.value()].iter().collect();
+    let source = std::fs::read_to_string(&full_path).map_err(|e| {
+        syn::Error::new(
+            path.span(),
+            format!("cannot read {}: {}", full_path.display(), e),
+        )
+    })?;
+    let doc = Document::parse(&source)
+        .map_err(|e| syn::Error::new(path.span(), format!("{}: {}", path.value(), e)))?;
+
+    // make the invoking crate depend on the contents of the file, so that it is rebuilt
+    // whenever the file changes
+    let full_path = full_path.to_string_lossy().into_owned();
+    let source_ident = render::ident(&format!(
+        "__WITX_SOURCE_{}",
+        path.value()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            })
+            .collect::<String>()
+    ));
+    let dependency = quote! {
+        #[allow(dead_code)]
+        const #source_ident: &[u8] = include_bytes!(#full_path);
+    };
+    Ok((doc, dependency))
+}
+
+fn expand(path: &LitStr, target: Target) -> Result<proc_macro2::TokenStream, syn::Error> {
+    let (doc, dependency) = load(path)?;
+    let types = render::render(&doc, target)
+        .map_err(|e| syn::Error::new(path.span(), format!("{}: {}", path.value(), e)))?;
+    Ok(quote!(#dependency #types))
+}
+
+/// Defines the types of a `.witx` file as used by the host, i.e. with native pointers and
+/// sizes.
+///
+/// ```ignore
+/// witx_host_types!("witx/preview1/typenames.witx");
+/// ```
+#[proc_macro]
+pub fn witx_host_types(input: TokenStream) -> TokenStream {
+    let path = syn::parse_macro_input!(input as LitStr);
+    expand(&path, Target::Host)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+struct GuestTypesInput {
+    path: LitStr,
+    pointer_width: LitInt,
+}
+
+impl Parse for GuestTypesInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let pointer_width = input.parse()?;
+        Ok(Self {
+            path,
+            pointer_width,
+        })
+    }
+}
+
+/// Defines the types of a `.witx` file as laid out in the memory of a guest whose pointers
+/// are of the given width in bits. Padding is made explicit, and the layout of every struct
+/// and union is checked by a test.
+///
+/// ```ignore
+/// witx_guest_types!("witx/preview0/typenames.witx", 32);
+/// ```
+#[proc_macro]
+pub fn witx_guest_types(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as GuestTypesInput);
+    let pointer_size = match input.pointer_width.value() {
+        32 => 4,
+        64 => 8,
+        _ => {
+            return syn::Error::new(input.pointer_width.span(), "expected 32 or 64")
+                .to_compile_error()
+                .into()
+        }
+    };
+    expand(&input.path, Target::Guest { pointer_size })
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+struct ScalarCodecsInput {
+    host_module: Ident,
+    host_path: LitStr,
+    guest_module: Ident,
+    guest_path: LitStr,
+}
+
+impl Parse for ScalarCodecsInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let host_module = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let host_path = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let guest_module = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let guest_path = input.parse()?;
+        Ok(Self {
+            host_module,
+            host_path,
+            guest_module,
+            guest_path,
+        })
+    }
+}
+
+/// Defines `dec_*`, `dec_*_byref`, `enc_*` and `enc_*_byref` functions converting the scalar
+/// types which are represented the same way in the host and in the guest. Each module is given
+/// along with the `.witx` file its types were generated from.
+///
+/// ```ignore
+/// witx_scalar_codecs!(
+///     host: "witx/preview1/typenames.witx",
+///     wasm32: "witx/preview0/typenames.witx"
+/// );
+/// ```
+///
+/// The functions rely on `GuestMemory`, `GuestAddr`, `dec_pointee` and `enc_pointee` being in
+/// scope.
+#[proc_macro]
+pub fn witx_scalar_codecs(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as ScalarCodecsInput);
+    let result = load(&input.host_path).and_then(|(host, host_dependency)| {
+        let (guest, guest_dependency) = load(&input.guest_path)?;
+        let codecs = codecs::render(&input.host_module, &host, &input.guest_module, &guest);
+        Ok(quote!(#host_dependency #guest_dependency #codecs))
+    });
+    result.unwrap_or_else(|e| e.to_compile_error()).into()
+}
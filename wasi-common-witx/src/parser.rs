@@ -0,0 +1,320 @@
+//! A parser for the subset of the witx format used by the WASI type definitions.
+//!
+//! Only `typename` declarations are supported, which is all `typenames.witx` contains.
+//! Doc comments (`;;;`) are kept and attached to the declaration, field or variant
+//! which follows them.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntRepr {
+    U8,
+    U16,
+    U32,
+    U64,
+    S8,
+    S16,
+    S32,
+    S64,
+}
+
+impl IntRepr {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "u8" => Some(IntRepr::U8),
+            "u16" => Some(IntRepr::U16),
+            "u32" => Some(IntRepr::U32),
+            "u64" => Some(IntRepr::U64),
+            "s8" => Some(IntRepr::S8),
+            "s16" => Some(IntRepr::S16),
+            "s32" => Some(IntRepr::S32),
+            "s64" => Some(IntRepr::S64),
+            _ => None,
+        }
+    }
+
+    pub fn size(self) -> u64 {
+        match self {
+            IntRepr::U8 | IntRepr::S8 => 1,
+            IntRepr::U16 | IntRepr::S16 => 2,
+            IntRepr::U32 | IntRepr::S32 => 4,
+            IntRepr::U64 | IntRepr::S64 => 8,
+        }
+    }
+
+    pub fn rust_name(self) -> &'static str {
+        match self {
+            IntRepr::U8 => "u8",
+            IntRepr::U16 => "u16",
+            IntRepr::U32 => "u32",
+            IntRepr::U64 => "u64",
+            IntRepr::S8 => "i8",
+            IntRepr::S16 => "i16",
+            IntRepr::S32 => "i32",
+            IntRepr::S64 => "i64",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Type {
+    Int(IntRepr),
+    Ref(String),
+    Enum {
+        repr: IntRepr,
+        variants: Vec<Variant>,
+    },
+    Flags {
+        repr: IntRepr,
+        flags: Vec<Variant>,
+    },
+    Record(Vec<Field>),
+    /// A union discriminated by a value of the `tag` type; the n-th case corresponds to the
+    /// n-th value of the tag.
+    Union {
+        tag: String,
+        cases: Vec<String>,
+    },
+    Handle,
+    /// A list of values of some type, which has no representation of its own: lists are passed
+    /// as a pointer and a length.
+    List,
+    Pointer(Box<Type>),
+    ConstPointer(Box<Type>),
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    pub name: String,
+    pub docs: String,
+}
+
+#[derive(Debug)]
+pub struct Field {
+    pub name: String,
+    pub docs: String,
+    pub ty: Type,
+}
+
+#[derive(Debug)]
+pub struct Typename {
+    pub name: String,
+    pub docs: String,
+    pub ty: Type,
+}
+
+#[derive(Debug)]
+pub struct Document {
+    pub typenames: Vec<Typename>,
+}
+
+impl Document {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let typenames = parse_sexprs(source)?
+            .iter()
+            .map(parse_typename)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { typenames })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Typename> {
+        self.typenames.iter().find(|t| t.name == name)
+    }
+
+    pub fn resolve(&self, name: &str) -> Result<&Typename, String> {
+        self.get(name)
+            .ok_or_else(|| format!("undefined type ${}", name))
+    }
+}
+
+enum Sexpr {
+    Atom(String),
+    List(Vec<Node>),
+}
+
+struct Node {
+    docs: String,
+    sexpr: Sexpr,
+}
+
+fn parse_sexprs(source: &str) -> Result<Vec<Node>, String> {
+    // each open list along with the docs preceding it
+    let mut stack: Vec<(String, Vec<Node>)> = vec![(String::new(), Vec::new())];
+    let mut docs = String::new();
+    let mut line = 1;
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            ';' => {
+                let mut comment = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    comment.push(c);
+                    chars.next();
+                }
+                if comment.starts_with(";;") {
+                    let text = &comment[2..];
+                    docs.push_str(if text.starts_with(' ') {
+                        &text[1..]
+                    } else {
+                        text
+                    });
+                    docs.push('\n');
+                } else if !comment.starts_with(';') {
+                    return Err(format!("line {}: expected a comment", line));
+                }
+            }
+            '(' => stack.push((std::mem::replace(&mut docs, String::new()), Vec::new())),
+            ')' => {
+                let (list_docs, list) = stack.pop().unwrap();
+                let parent = &mut stack
+                    .last_mut()
+                    .ok_or_else(|| format!("line {}: unbalanced ')'", line))?
+                    .1;
+                parent.push(Node {
+                    docs: list_docs,
+                    sexpr: Sexpr::List(list),
+                });
+                docs.clear();
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut atom = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == ';' {
+                        break;
+                    }
+                    atom.push(c);
+                    chars.next();
+                }
+                let parent = &mut stack.last_mut().unwrap().1;
+                parent.push(Node {
+                    docs: std::mem::replace(&mut docs, String::new()),
+                    sexpr: Sexpr::Atom(atom),
+                });
+            }
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err("unbalanced '(' at the end of input".to_owned());
+    }
+    Ok(stack.pop().unwrap().1)
+}
+
+fn atom(node: &Node) -> Result<&str, String> {
+    match &node.sexpr {
+        Sexpr::Atom(atom) => Ok(atom),
+        Sexpr::List(_) => Err("expected an atom, found a list".to_owned()),
+    }
+}
+
+fn list(node: &Node) -> Result<&[Node], String> {
+    match &node.sexpr {
+        Sexpr::List(list) => Ok(list),
+        Sexpr::Atom(atom) => Err(format!("expected a list, found `{}`", atom)),
+    }
+}
+
+/// Parses a `$name` identifier, returning the name without the `$`.
+fn name(node: &Node) -> Result<String, String> {
+    let atom = atom(node)?;
+    if atom.starts_with('$') && atom.len() > 1 {
+        Ok(atom[1..].to_owned())
+    } else {
+        Err(format!("expected a $name, found `{}`", atom))
+    }
+}
+
+/// Splits a list into its head keyword and the remaining elements.
+fn keyword(nodes: &[Node]) -> Result<(&str, &[Node]), String> {
+    match nodes.split_first() {
+        Some((head, rest)) => Ok((atom(head)?, rest)),
+        None => Err("unexpected empty list".to_owned()),
+    }
+}
+
+/// Parses an `(@witx <annotation> <value>)` list.
+fn annotation<'a>(node: &'a Node, expected: &str) -> Result<&'a Node, String> {
+    match keyword(list(node)?)? {
+        ("@witx", [what, value]) if atom(what)? == expected => Ok(value),
+        _ => Err(format!("expected (@witx {} ...)", expected)),
+    }
+}
+
+fn int_repr(node: &Node) -> Result<IntRepr, String> {
+    let atom = atom(node)?;
+    IntRepr::from_name(atom).ok_or_else(|| format!("expected an integer type, found `{}`", atom))
+}
+
+fn parse_typename(node: &Node) -> Result<Typename, String> {
+    match keyword(list(node)?)? {
+        ("typename", [n, ty]) => {
+            let name = name(n)?;
+            let ty = parse_type(ty).map_err(|e| format!("in ${}: {}", name, e))?;
+            Ok(Typename {
+                name,
+                docs: node.docs.clone(),
+                ty,
+            })
+        }
+        (other, _) => Err(format!("unsupported declaration `{}`", other)),
+    }
+}
+
+fn parse_variants(nodes: &[Node]) -> Result<Vec<Variant>, String> {
+    nodes
+        .iter()
+        .map(|node| {
+            Ok(Variant {
+                name: name(node)?,
+                docs: node.docs.clone(),
+            })
+        })
+        .collect()
+}
+
+fn parse_type(node: &Node) -> Result<Type, String> {
+    let nodes = match &node.sexpr {
+        Sexpr::Atom(atom) if atom.starts_with('$') => return Ok(Type::Ref(name(node)?)),
+        Sexpr::Atom(_) => return int_repr(node).map(Type::Int),
+        Sexpr::List(nodes) => nodes,
+    };
+
+    match keyword(nodes)? {
+        ("enum", rest) if !rest.is_empty() => Ok(Type::Enum {
+            repr: int_repr(annotation(&rest[0], "tag")?)?,
+            variants: parse_variants(&rest[1..])?,
+        }),
+        ("flags", rest) if !rest.is_empty() => Ok(Type::Flags {
+            repr: int_repr(annotation(&rest[0], "repr")?)?,
+            flags: parse_variants(&rest[1..])?,
+        }),
+        ("record", fields) => fields
+            .iter()
+            .map(|field| match keyword(list(field)?)? {
+                ("field", [n, ty]) => Ok(Field {
+                    name: name(n)?,
+                    docs: field.docs.clone(),
+                    ty: parse_type(ty)?,
+                }),
+                _ => Err("expected (field $name type)".to_owned()),
+            })
+            .collect::<Result<_, _>>()
+            .map(Type::Record),
+        ("union", rest) if !rest.is_empty() => Ok(Type::Union {
+            tag: name(annotation(&rest[0], "tag")?)?,
+            cases: rest[1..].iter().map(name).collect::<Result<_, _>>()?,
+        }),
+        ("handle", []) => Ok(Type::Handle),
+        ("list", [ty]) => parse_type(ty).map(|_| Type::List),
+        ("@witx", [kind, ty]) => match atom(kind)? {
+            "pointer" => Ok(Type::Pointer(Box::new(parse_type(ty)?))),
+            "const_pointer" => Ok(Type::ConstPointer(Box::new(parse_type(ty)?))),
+            other => Err(format!("unsupported annotation `{}`", other)),
+        },
+        (other, _) => Err(format!("unsupported type `{}`", other)),
+    }
+}
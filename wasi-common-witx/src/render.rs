@@ -0,0 +1,281 @@
+//! Rust definitions of the WASI types, either as seen by the host or as laid out in the
+//! memory of a guest.
+//!
+//! See the `layout` module for how the types are named and laid out.
+
+use crate::layout::{self, FieldType, Layout, Member, StructField, Target};
+use crate::parser::{Document, IntRepr, Type, Typename, Variant};
+use proc_macro2::{Ident, Literal, Span, TokenStream};
+use quote::quote;
+
+pub fn ident(name: &str) -> Ident {
+    Ident::new(name, Span::call_site())
+}
+
+pub fn type_ident(name: &str) -> Ident {
+    ident(&layout::type_name(name))
+}
+
+fn docs(docs: &str) -> TokenStream {
+    let lines = docs.lines().map(|line| format!(" {}", line));
+    quote!(#(#[doc = #lines])*)
+}
+
+struct Generator<'a> {
+    layout: Layout<'a>,
+}
+
+impl<'a> Generator<'a> {
+    fn int(&self, repr: IntRepr) -> TokenStream {
+        let repr = ident(repr.rust_name());
+        quote!(#repr)
+    }
+
+    /// The unsigned integer type as wide as a guest pointer.
+    fn guest_pointer(&self, pointer_size: u64) -> TokenStream {
+        self.int(if pointer_size == 8 {
+            IntRepr::U64
+        } else {
+            IntRepr::U32
+        })
+    }
+
+    /// The Rust type of a field.
+    fn field_type(&self, ty: &FieldType) -> TokenStream {
+        match (ty, self.layout.target) {
+            (FieldType::Int(repr), _) => self.int(*repr),
+            (FieldType::Named(name), _) | (FieldType::UnionBody(name), _) => {
+                let ty = type_ident(name);
+                quote!(#ty)
+            }
+            (FieldType::Pointer { to, mutable }, Target::Host) => {
+                let to = self.field_type(to);
+                if *mutable {
+                    quote!(*mut #to)
+                } else {
+                    quote!(*const #to)
+                }
+            }
+            (FieldType::Pointer { .. }, Target::Guest { pointer_size }) => {
+                self.guest_pointer(pointer_size)
+            }
+        }
+    }
+
+    fn constants(&self, type_name: &str, variants: &[Variant], flags: bool) -> TokenStream {
+        let ty = type_ident(type_name);
+        let constants = layout::constants(type_name, variants, flags)
+            .into_iter()
+            .map(|(name, value, variant)| {
+                let docs = docs(&variant.docs);
+                let name = ident(&name);
+                let value = Literal::u64_unsuffixed(value);
+                quote! {
+                    #docs
+                    pub const #name: #ty = #value;
+                }
+            });
+        quote!(#(#constants)*)
+    }
+
+    /// Emits a struct, along with its layout test for guest types.
+    fn render_struct(
+        &self,
+        name: &str,
+        struct_docs: &TokenStream,
+        fields: &[StructField],
+    ) -> (TokenStream, TokenStream) {
+        let ty = type_ident(name);
+        let derive = if fields.iter().any(StructField::is_union) {
+            quote!(#[derive(Copy, Clone)])
+        } else {
+            quote!(#[derive(Debug, Copy, Clone)])
+        };
+
+        let mut items = Vec::new();
+        let mut offsets = Vec::new();
+        for member in self.layout.members(fields) {
+            match member {
+                Member::Field { field, offset } => {
+                    offsets.push((ident(&field.name), offset));
+                    let field_docs = docs(&field.docs);
+                    let field_name = ident(&field.name);
+                    let field_ty = self.field_type(&field.ty);
+                    items.push(quote! {
+                        #field_docs
+                        pub #field_name: #field_ty,
+                    });
+                }
+                Member::Padding { index, len } => {
+                    let field = ident(&format!("__padding_{}", index));
+                    let len = Literal::u64_unsuffixed(len);
+                    items.push(quote!(pub #field: [u8; #len],));
+                }
+            }
+        }
+
+        let (size, align) = self.layout.struct_layout(fields);
+        let test = layout_test(name, size, align, &offsets);
+
+        let def = quote! {
+            #struct_docs
+            #[repr(C)]
+            #derive
+            pub struct #ty {
+                #(#items)*
+            }
+        };
+        (def, test)
+    }
+
+    fn render_union_body(
+        &self,
+        name: &str,
+        cases: &[String],
+    ) -> Result<(TokenStream, TokenStream), String> {
+        let body_name = self.layout.union_body_name(name);
+        let ty = type_ident(&body_name);
+        let fields = self
+            .layout
+            .union_cases(name, cases)
+            .into_iter()
+            .map(|(field, case)| {
+                let field = ident(&field);
+                let case = type_ident(&case);
+                quote!(pub #field: #case,)
+            });
+        let def = quote! {
+            #[repr(C)]
+            #[derive(Copy, Clone)]
+            pub union #ty {
+                #(#fields)*
+            }
+        };
+        let (size, align) = self.layout.union_body_layout(name, cases)?;
+        let test = layout_test(&body_name, size, align, &[]);
+        Ok((def, test))
+    }
+
+    fn render_typename(&self, typename: &Typename) -> Result<(TokenStream, TokenStream), String> {
+        let name = &typename.name;
+        let ty = type_ident(name);
+        let docs = docs(&typename.docs);
+        let no_test = quote!();
+        match &typename.ty {
+            Type::Ref(_) | Type::Int(_) if name == "size" => {
+                let size = match self.layout.target {
+                    Target::Host => quote!(usize),
+                    Target::Guest { pointer_size } => self.guest_pointer(pointer_size),
+                };
+                Ok((quote!(#docs pub type #ty = #size;), no_test))
+            }
+            Type::Int(_) | Type::Ref(_) | Type::Pointer(_) | Type::ConstPointer(_) => {
+                let alias = self.field_type(&self.layout.field_type(&typename.ty)?);
+                Ok((quote!(#docs pub type #ty = #alias;), no_test))
+            }
+            Type::Handle => Ok((quote!(#docs pub type #ty = u32;), no_test)),
+            Type::Enum { repr, variants } => {
+                let repr = self.int(*repr);
+                let constants = self.constants(name, variants, false);
+                Ok((quote!(#docs pub type #ty = #repr; #constants), no_test))
+            }
+            Type::Flags { repr, flags } => {
+                let repr = self.int(*repr);
+                let constants = self.constants(name, flags, true);
+                Ok((quote!(#docs pub type #ty = #repr; #constants), no_test))
+            }
+            Type::Record(fields) => {
+                let fields = self.layout.struct_fields(name, fields)?;
+                Ok(self.render_struct(name, &docs, &fields))
+            }
+            Type::Union { cases, .. } => {
+                let (body, body_test) = self.render_union_body(name, cases)?;
+                if self.layout.is_embedded_union(name) {
+                    Ok((quote!(#docs #body), body_test))
+                } else {
+                    let fields = self.layout.union_struct_fields(typename)?;
+                    let (def, test) = self.render_struct(name, &docs, &fields);
+                    Ok((quote!(#def #body), quote!(#test #body_test)))
+                }
+            }
+            Type::List => Ok((quote!(), no_test)),
+        }
+    }
+}
+
+fn layout_test(name: &str, size: u64, align: u64, offsets: &[(Ident, u64)]) -> TokenStream {
+    let ty = type_ident(name);
+    let test = ident(&format!("layout___wasi_{}_t", name));
+    let size = Literal::u64_unsuffixed(size);
+    let align = Literal::u64_unsuffixed(align);
+    let offsets = offsets.iter().map(|(field, offset)| {
+        let offset = Literal::u64_unsuffixed(*offset);
+        quote! {
+            assert_eq!(
+                offset_of!(#ty, #field),
+                #offset,
+                concat!("Offset of field: ", stringify!(#ty), "::", stringify!(#field))
+            );
+        }
+    });
+    quote! {
+        #[test]
+        fn #test() {
+            assert_eq!(
+                ::std::mem::size_of::<#ty>(),
+                #size,
+                concat!("Size of: ", stringify!(#ty))
+            );
+            assert_eq!(
+                ::std::mem::align_of::<#ty>(),
+                #align,
+                concat!("Alignment of ", stringify!(#ty))
+            );
+            #(#offsets)*
+        }
+    }
+}
+
+/// Emits all the types of a document, along with a test module checking their layout.
+pub fn render(doc: &Document, target: Target) -> Result<TokenStream, String> {
+    let generator = Generator {
+        layout: Layout { doc, target },
+    };
+    let mut defs = Vec::new();
+    let mut tests = Vec::new();
+    for typename in &doc.typenames {
+        let (def, test) = generator
+            .render_typename(typename)
+            .map_err(|e| format!("in ${}: {}", typename.name, e))?;
+        defs.push(def);
+        tests.push(test);
+    }
+
+    let cfg = match target {
+        Target::Host => quote!(#[cfg(all(test, target_pointer_width = "64"))]),
+        Target::Guest { .. } => quote!(#[cfg(test)]),
+    };
+    let tests = quote! {
+        #cfg
+        mod witx_layout_tests {
+            use super::*;
+
+            macro_rules! offset_of {
+                ($ty:ty, $field:ident) => {{
+                    let value = ::std::mem::MaybeUninit::<$ty>::uninit();
+                    let base = value.as_ptr();
+                    #[allow(unused_unsafe)]
+                    let field = unsafe { &(*base).$field as *const _ as usize };
+                    field - base as usize
+                }};
+            }
+
+            #(#tests)*
+        }
+    };
+
+    Ok(quote! {
+        #(#defs)*
+        #tests
+    })
+}
@@ -0,0 +1,67 @@
+#![allow(non_camel_case_types)]
+
+mod host {
+    wasi_common_witx::witx_host_types!("tests/types.witx");
+}
+
+mod wasm32 {
+    wasi_common_witx::witx_guest_types!("tests/types.witx", 32);
+}
+
+mod wasm64 {
+    wasi_common_witx::witx_guest_types!("tests/types.witx", 64);
+}
+
+use std::mem::size_of;
+
+#[test]
+fn constants() {
+    assert_eq!(wasm32::__WASI_KIND_FIRST, 0);
+    assert_eq!(wasm32::__WASI_KIND_SECOND, 1);
+    assert_eq!(wasm32::__WASI_MODE_READ, 1);
+    assert_eq!(wasm32::__WASI_MODE_WRITE, 2);
+    assert_eq!(host::__WASI_MODE_WRITE, wasm64::__WASI_MODE_WRITE);
+}
+
+#[test]
+fn explicit_padding() {
+    let padded = wasm32::__wasi_padded_t {
+        kind: wasm32::__WASI_KIND_SECOND,
+        __padding_0: [0; 7],
+        value: 42,
+        mode: wasm32::__WASI_MODE_READ,
+        __padding_1: [0; 6],
+    };
+    assert_eq!(padded.value, 42);
+}
+
+#[test]
+fn pointer_width() {
+    assert_eq!(size_of::<wasm32::__wasi_buf_t>(), 8);
+    assert_eq!(size_of::<wasm64::__wasi_buf_t>(), 16);
+    assert_eq!(size_of::<host::__wasi_size_t>(), size_of::<usize>());
+    assert_eq!(size_of::<wasm32::__wasi_prestat_t>(), 8);
+    assert_eq!(size_of::<wasm64::__wasi_prestat_t>(), 16);
+}
+
+#[test]
+fn unions() {
+    let payload = wasm32::__wasi_payload_t {
+        userdata: 1,
+        type_: wasm32::__WASI_KIND_SECOND,
+        __padding_0: [0; 7],
+        u: wasm32::__wasi_payload_u_t {
+            short: wasm32::__wasi_payload_short_t { fd: 3 },
+        },
+    };
+    assert_eq!(unsafe { payload.u.short.fd }, 3);
+    assert_eq!(size_of::<wasm32::__wasi_payload_t>(), 32);
+
+    let prestat = host::__wasi_prestat_t {
+        pr_type: host::__WASI_KIND_FIRST,
+        u: host::__wasi_prestat_u_t {
+            dir: host::__wasi_prestat_dir_t { pr_name_len: 4 },
+        },
+    };
+    assert_eq!(unsafe { prestat.u.dir.pr_name_len }, 4);
+}